@@ -0,0 +1,166 @@
+use crate::options::split_args;
+use crate::{file, Editor, Output};
+use std::{
+    io::{self, stdout, Write},
+    path::PathBuf,
+};
+
+/// The files v2 was started with, or that `:args` gave it since, and which of them is being
+/// edited.
+#[derive(Default)]
+pub struct ArgList {
+    files: Vec<PathBuf>,
+    idx: usize,
+    /// `:q` already said there are files left, so the next one quits
+    warned: bool,
+}
+
+impl ArgList {
+    pub fn new(files: Vec<PathBuf>) -> Self {
+        Self {
+            files,
+            ..Default::default()
+        }
+    }
+}
+
+impl Editor {
+    /// `:ar[gs]` shows the argument list with the current file in `[]`, with files it becomes
+    /// those and the first one gets edited. Wildcards in them get expanded, `**` going through
+    /// any number of directories.
+    pub fn args(&mut self, bang: bool, args: &str) -> io::Result<()> {
+        if args.trim().is_empty() {
+            return self.show_args();
+        }
+        if self.set_args(args) {
+            self.go_to_arg(0, bang);
+        }
+        Ok(())
+    }
+
+    /// Makes the files `args` names the argument list, gives back whether it names any.
+    fn set_args(&mut self, args: &str) -> bool {
+        let mut files = Vec::new();
+        for word in split_args(args) {
+            let found = file::glob(&word);
+            if found.is_empty() {
+                self.output
+                    .status_message
+                    .set_error(format!("No match: {}", word));
+                return false;
+            }
+            files.extend(found);
+        }
+        self.arglist = ArgList::new(files);
+        true
+    }
+
+    fn show_args(&mut self) -> io::Result<()> {
+        let arglist = &self.arglist;
+        let line = arglist
+            .files
+            .iter()
+            .enumerate()
+            .map(|(idx, path)| match idx == arglist.idx {
+                true => format!("[{}]", path.display()),
+                false => path.display().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        if line.len() < self.output.win_size.0 {
+            self.output.status_message.set_message(line);
+            return Ok(());
+        }
+        Output::clear_screen()?;
+        print!("{}", line);
+        stdout().flush()?;
+        self.press_enter()
+    }
+
+    /// Edits the file at `idx` in the argument list.
+    fn go_to_arg(&mut self, idx: usize, bang: bool) -> bool {
+        let Some(path) = self.arglist.files.get(idx).cloned() else {
+            return false;
+        };
+        if !self.edit_path(bang, path) {
+            return false;
+        }
+        self.arglist.idx = idx;
+        self.arglist.warned = false;
+        true
+    }
+
+    /// `:n[ext][!]` edits the next file in the argument list, `:n[ext][!] {files}` makes
+    /// `files` the argument list like `:args` does.
+    pub fn next_arg(&mut self, bang: bool, args: &str) {
+        if !args.trim().is_empty() {
+            if self.set_args(args) {
+                self.go_to_arg(0, bang);
+            }
+            return;
+        }
+        let next = self.arglist.idx + 1;
+        if next >= self.arglist.files.len() {
+            self.output
+                .status_message
+                .set_error("Cannot go beyond last file".into());
+            return;
+        }
+        self.go_to_arg(next, bang);
+    }
+
+    /// `:N[ext][!]` and `:prev[ious][!]`
+    pub fn previous_arg(&mut self, bang: bool) {
+        if self.arglist.idx == 0 || self.arglist.files.is_empty() {
+            self.output
+                .status_message
+                .set_error("Cannot go before first file".into());
+            return;
+        }
+        self.go_to_arg(self.arglist.idx - 1, bang);
+    }
+
+    /// `:fir[st][!]`, `:rew[ind][!]` and `:la[st][!]`
+    pub fn edge_arg(&mut self, bang: bool, last: bool) {
+        let len = self.arglist.files.len();
+        if len == 0 {
+            self.output
+                .status_message
+                .set_error("Argument list is empty".into());
+            return;
+        }
+        self.go_to_arg(if last { len - 1 } else { 0 }, bang);
+    }
+
+    /// `:argdo[!] {cmd}` edits each file in the argument list in turn and runs `cmd` on it. It
+    /// stops at the first file it can't go to, and when `cmd` quits.
+    pub fn argdo(&mut self, bang: bool, cmd: &str) -> io::Result<bool> {
+        for idx in 0..self.arglist.files.len() {
+            if !self.go_to_arg(idx, bang) {
+                break;
+            }
+            if !self.parse_command(cmd.to_string())? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Whether quitting can go ahead as far as the argument list goes. The first time there are
+    /// files after the current one that didn't get edited it says how many, the next it quits.
+    pub fn files_left(&mut self) -> bool {
+        let left = self
+            .arglist
+            .files
+            .len()
+            .saturating_sub(self.arglist.idx + 1);
+        if left == 0 || self.arglist.warned {
+            return true;
+        }
+        self.arglist.warned = true;
+        self.output
+            .status_message
+            .set_error(format!("{} more files to edit", left));
+        false
+    }
+}
@@ -0,0 +1,229 @@
+use crate::buffer::display_name;
+use crate::Editor;
+use std::io;
+
+/// What `:bufdo` and the others run their command in, by number.
+#[derive(Clone, Copy)]
+enum Target {
+    Buffer(usize),
+    Window(usize),
+    /// the index of a quickfix entry
+    Entry(usize),
+}
+
+/// A message running the command gave, with the name of the buffer it ran in unless it's
+/// about not getting there.
+struct Said {
+    name: Option<String>,
+    message: String,
+    error: bool,
+}
+
+/// How what the targets said gets shown once they've all run.
+#[derive(PartialEq, Debug)]
+enum Report {
+    Nothing,
+    Message(String),
+    Error(String),
+    /// a screen with a line for each, and how many of them were errors for the status line
+    Screen(Vec<String>, Option<String>),
+}
+
+fn report(said: &[Said]) -> Report {
+    let line = |said: &Said| match &said.name {
+        Some(name) => format!("{}: {}", name, said.message),
+        None => said.message.clone(),
+    };
+    match said {
+        [] => Report::Nothing,
+        [one] if one.error => Report::Error(line(one)),
+        [one] => Report::Message(line(one)),
+        _ => {
+            let errors = said.iter().filter(|said| said.error).count();
+            let error = match errors {
+                0 => None,
+                1 => Some(format!("1 of {} messages is an error", said.len())),
+                _ => Some(format!("{} of {} messages are errors", errors, said.len())),
+            };
+            Report::Screen(said.iter().map(line).collect(), error)
+        }
+    }
+}
+
+impl Editor {
+    /// Makes `target` the current place to run a command in.
+    fn go_to_target(&mut self, target: Target, bang: bool) -> bool {
+        match target {
+            Target::Buffer(nr) => self.switch_buffer(nr, bang),
+            Target::Window(id) => {
+                self.enter_window(id);
+                true
+            }
+            Target::Entry(idx) => self.go_to_entry(idx, bang),
+        }
+    }
+
+    /// Runs `cmd` in each of `targets`. A message one of them gives (an error or not) doesn't
+    /// stop the others unless 'stoponerror' is on, in which case the first error does, and they
+    /// all get shown at the end. Not being able to go to a target stops it too, and so does
+    /// quitting. Afterwards the window, buffer and cursor it started in are back, whatever
+    /// changes the buffer it ended in has.
+    fn run_in_each(&mut self, targets: Vec<Target>, bang: bool, cmd: &str) -> io::Result<bool> {
+        if cmd.trim().is_empty() {
+            self.output
+                .status_message
+                .set_error("Argument required".into());
+            return Ok(true);
+        }
+        let (window, buffer) = (self.windows.current, self.buffers.current);
+        let cursor = &self.output.cursor_controller;
+        let (cursor_x, cursor_y) = (cursor.cursor_x, cursor.cursor_y);
+        let stop_on_error = self.output.options.bool("stoponerror");
+
+        let mut said = Vec::new();
+        for target in targets {
+            self.output.status_message.error = None;
+            if !self.go_to_target(target, bang) {
+                said.extend(self.output.status_message.take().map(|message| Said {
+                    name: None,
+                    message,
+                    error: true,
+                }));
+                break;
+            }
+            self.output.status_message.take();
+            self.output.status_message.error = None;
+            if !self.parse_command(cmd.to_string())? {
+                return Ok(false);
+            }
+            let error = self.output.status_message.error.is_some();
+            if let Some(message) = self.output.status_message.take() {
+                let name = display_name(&self.output.editor_rows);
+                said.push(Said {
+                    name: Some(name),
+                    message,
+                    error,
+                });
+            }
+            if error && stop_on_error {
+                break;
+            }
+        }
+
+        self.enter_window(window);
+        // going back isn't leaving the changes behind, so 'hidden' or not that's fine
+        if self.buffers.current != buffer {
+            self.enter_buffer(buffer);
+        }
+        if self.buffers.current == buffer {
+            self.output.set_cursor(cursor_x, cursor_y);
+        }
+        self.output.status_message.take();
+        self.output.status_message.error = None;
+        match report(&said) {
+            Report::Nothing => {}
+            Report::Message(message) => self.output.status_message.set_message(message),
+            Report::Error(message) => self.output.status_message.set_error(message),
+            Report::Screen(lines, error) => {
+                self.show_lines(&lines)?;
+                // what went wrong is still known once the screen is gone
+                if let Some(error) = error {
+                    self.output.status_message.set_error(error);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// `:bufdo[!] {cmd}` runs `cmd` in every buffer, in the order of their numbers.
+    pub fn bufdo(&mut self, bang: bool, cmd: &str) -> io::Result<bool> {
+        let targets = self.buffers.numbers().into_iter().map(Target::Buffer);
+        self.run_in_each(targets.collect(), bang, cmd)
+    }
+
+    /// `:windo {cmd}` runs `cmd` in every window, from the top left.
+    pub fn windo(&mut self, cmd: &str) -> io::Result<bool> {
+        let targets = self.windows.order().into_iter().map(Target::Window);
+        self.run_in_each(targets.collect(), false, cmd)
+    }
+
+    /// `:cdo[!] {cmd}` runs `cmd` on every entry in the quickfix list, `:cfdo[!] {cmd}` once in
+    /// every file the list has entries in.
+    pub fn cdo(&mut self, bang: bool, cmd: &str, per_file: bool) -> io::Result<bool> {
+        let entries = &self.quickfix.entries;
+        if entries.is_empty() {
+            self.output.status_message.set_error("No Errors".into());
+            return Ok(true);
+        }
+        let targets = (0..entries.len())
+            .filter(|&idx| {
+                !per_file
+                    || entries[..idx]
+                        .iter()
+                        .all(|entry| entry.path != entries[idx].path)
+            })
+            .map(Target::Entry)
+            .collect();
+        self.run_in_each(targets, bang, cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn said(name: &str, message: &str, error: bool) -> Said {
+        Said {
+            name: Some(name.into()),
+            message: message.into(),
+            error,
+        }
+    }
+
+    #[test]
+    fn reports_one_message_in_the_status_line() {
+        assert_eq!(report(&[]), Report::Nothing);
+        assert_eq!(
+            report(&[said("a.txt", "3 substitutions on 2 lines", false)]),
+            Report::Message("a.txt: 3 substitutions on 2 lines".into())
+        );
+        assert_eq!(
+            report(&[said("b.txt", "Pattern not found: old", true)]),
+            Report::Error("b.txt: Pattern not found: old".into())
+        );
+    }
+
+    #[test]
+    fn reports_several_on_a_screen() {
+        let not_there = Said {
+            name: None,
+            message: "No write since last change (add ! to override)".into(),
+            error: true,
+        };
+        assert_eq!(
+            report(&[
+                said("a.txt", "3 substitutions on 2 lines", false),
+                said("b.txt", "Pattern not found: old", true),
+                not_there,
+            ]),
+            Report::Screen(
+                vec![
+                    "a.txt: 3 substitutions on 2 lines".into(),
+                    "b.txt: Pattern not found: old".into(),
+                    "No write since last change (add ! to override)".into(),
+                ],
+                Some("2 of 3 messages are errors".into())
+            )
+        );
+        assert_eq!(
+            report(&[
+                said("a.txt", "1 line yanked", false),
+                said("b.txt", "1 line yanked", false)
+            ]),
+            Report::Screen(
+                vec!["a.txt: 1 line yanked".into(), "b.txt: 1 line yanked".into()],
+                None
+            )
+        );
+    }
+}
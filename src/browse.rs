@@ -0,0 +1,369 @@
+use crate::cmdline::{Action, CmdHistory, CmdLine};
+use crate::regex::Haystack;
+use crate::{file, Editor, EditorRows};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// the keys a directory listing knows, shown at the top of it
+const HELP: &str = "\" Enter:open  -:up  %:new file  d:new dir  R:rename  C:copy  D:delete  s:sort  r:reverse  gh:hidden";
+
+/// What directory listings get sorted by, directories always coming first.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum SortBy {
+    #[default]
+    Name,
+    /// the newest first
+    Time,
+    /// the biggest first
+    Size,
+}
+
+impl SortBy {
+    fn name(self) -> &'static str {
+        match self {
+            SortBy::Name => "name",
+            SortBy::Time => "time",
+            SortBy::Size => "size",
+        }
+    }
+
+    /// the next one for `s`
+    fn next(self) -> Self {
+        match self {
+            SortBy::Name => SortBy::Time,
+            SortBy::Time => SortBy::Size,
+            SortBy::Size => SortBy::Name,
+        }
+    }
+}
+
+/// How directory listings are shown, the same for all of them.
+#[derive(Default)]
+pub struct Browser {
+    sort: SortBy,
+    reverse: bool,
+    /// files starting with a `.` get listed too
+    hidden: bool,
+}
+
+/// A file in a directory listing.
+struct Entry {
+    name: String,
+    is_dir: bool,
+    modified: SystemTime,
+    len: u64,
+}
+
+impl Editor {
+    /// The rows of `path`, a listing of what's in it when it's a directory.
+    pub fn load_path(&self, path: PathBuf) -> io::Result<EditorRows> {
+        match path.is_dir() {
+            true => self.listing(path),
+            false => EditorRows::from_file(path, self.output.options.clone()),
+        }
+    }
+
+    /// A listing of directory `dir`: a line saying how it's sorted and one with the keys it
+    /// knows, then `../` and its directories and files, directories with a `/` after them.
+    fn listing(&self, dir: PathBuf) -> io::Result<EditorRows> {
+        let dir = dir.canonicalize()?;
+        let browser = &self.browser;
+        let mut entries: Vec<Entry> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                // a link to a directory counts as one
+                let metadata = fs::metadata(entry.path())
+                    .or_else(|_| entry.metadata())
+                    .ok()?;
+                Some(Entry {
+                    name,
+                    is_dir: metadata.is_dir(),
+                    modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    len: metadata.len(),
+                })
+            })
+            .filter(|entry| browser.hidden || !entry.name.starts_with('.'))
+            .collect();
+        entries.sort_by(|a, b| {
+            let order = match browser.sort {
+                SortBy::Name => a.name.cmp(&b.name),
+                SortBy::Time => b.modified.cmp(&a.modified),
+                SortBy::Size => b.len.cmp(&a.len),
+            };
+            let order = order.then_with(|| a.name.cmp(&b.name));
+            b.is_dir.cmp(&a.is_dir).then(if browser.reverse {
+                order.reverse()
+            } else {
+                order
+            })
+        });
+
+        let mut header = format!("\" {}  sorted by {}", dir.display(), browser.sort.name());
+        if browser.reverse {
+            header += ", reversed";
+        }
+        if browser.hidden {
+            header += ", hidden files shown";
+        }
+        let mut lines = vec![header, HELP.to_string()];
+        if dir.parent().is_some() {
+            lines.push("../".into());
+        }
+        lines.extend(entries.into_iter().map(|entry| match entry.is_dir {
+            true => format!("{}/", entry.name),
+            false => entry.name,
+        }));
+        let mut editor_rows = EditorRows::from_lines(dir, lines, self.output.options.clone());
+        editor_rows.listing = true;
+        Ok(editor_rows)
+    }
+
+    /// the directory the current buffer lists, if it's a directory listing
+    pub fn listing_dir(&self) -> Option<PathBuf> {
+        let editor_rows = &self.output.editor_rows;
+        editor_rows
+            .file_name
+            .clone()
+            .filter(|_| editor_rows.listing)
+    }
+
+    /// the file or directory on line `y` of the listing of `dir`
+    fn listed_path(&self, dir: &Path, y: usize) -> Option<PathBuf> {
+        let line = self.output.editor_rows.line(y)?;
+        if line.is_empty() || line.starts_with('"') {
+            return None;
+        }
+        match line.trim_end_matches('/') {
+            ".." => dir.parent().map(Path::to_path_buf),
+            name => Some(dir.join(name)),
+        }
+    }
+
+    /// Reads the listing of the current directory again, the cursor staying on the same line.
+    fn refresh_listing(&mut self) {
+        let Some(dir) = self.listing_dir() else {
+            return;
+        };
+        let y = self.output.cursor_controller.cursor_y;
+        match self.listing(dir) {
+            Ok(editor_rows) => {
+                self.output.set_rows(editor_rows);
+                self.output.set_cursor(0, y);
+            }
+            Err(error) => self.output.status_message.set_error(error.to_string()),
+        }
+    }
+
+    /// Puts the cursor on the first file when the current buffer is a listing that was just
+    /// read, past the two lines at the top.
+    pub fn skip_listing_header(&mut self) {
+        if self.listing_dir().is_some() {
+            self.output.set_cursor(0, 2);
+        }
+    }
+
+    /// Lists `dir`, reading it again if it already has a buffer, with the cursor on `name`
+    /// when it's in there.
+    fn open_directory(&mut self, dir: PathBuf, name: Option<&str>) {
+        if !self.edit_path(false, dir) {
+            return;
+        }
+        self.output.status_message.take();
+        self.refresh_listing();
+        let found = name.and_then(|name| {
+            let editor_rows = &self.output.editor_rows;
+            (0..editor_rows.nr_of_rows())
+                .find(|y| editor_rows.line(*y).map(|line| line.trim_end_matches('/')) == Some(name))
+        });
+        // past the two lines at the top
+        self.output.set_cursor(0, found.unwrap_or(2));
+    }
+
+    /// Reads a line typed after `prompt`, `None` when it gets cancelled with Esc.
+    pub fn input(&mut self, prompt: &str, initial: &str) -> io::Result<Option<String>> {
+        let mut cmdline = CmdLine::new(initial);
+        let history = CmdHistory::default();
+        let answer = loop {
+            let text = format!("{}{}", prompt, cmdline.text);
+            let col = prompt.chars().count() + cmdline.cursor_col();
+            self.output.prompt = Some((text, col));
+            self.refresh_screen()?;
+            match cmdline.handle(self.read_key()?, &history) {
+                Action::Enter => break Some(cmdline.text),
+                Action::Cancel => break None,
+                _ => {}
+            }
+        };
+        self.output.prompt = None;
+        Ok(answer)
+    }
+
+    /// Asks `question`, gives back whether the answer was `y`.
+    pub fn confirm(&mut self, question: &str) -> io::Result<bool> {
+        let prompt = format!("{} (y/n)", question);
+        self.output.prompt = Some((prompt.clone(), prompt.chars().count()));
+        let answer = loop {
+            self.refresh_screen()?;
+            match self.read_key()?.code {
+                KeyCode::Char('y') => break true,
+                KeyCode::Char('n') | KeyCode::Esc => break false,
+                _ => {}
+            }
+        };
+        self.output.prompt = None;
+        Ok(answer)
+    }
+
+    /// Handles `key` when the current buffer is a directory listing, gives back false for a
+    /// key a listing has nothing special for.
+    pub fn browse_key(&mut self, key: KeyEvent) -> io::Result<bool> {
+        let Some(dir) = self.listing_dir() else {
+            return Ok(false);
+        };
+        if !matches!(key.modifiers, KeyModifiers::NONE | KeyModifiers::SHIFT) {
+            return Ok(false);
+        }
+        let y = self.output.cursor_controller.cursor_y;
+        let path = self.listed_path(&dir, y);
+        // `R`, `C` and `D` leave the `../` line alone, it isn't in the directory
+        let name = path
+            .as_ref()
+            .filter(|path| path.parent() == Some(dir.as_path()))
+            .and_then(|path| path.file_name())
+            .and_then(|name| name.to_str())
+            .map(String::from);
+        match key.code {
+            KeyCode::Enter => match path {
+                Some(path) if path.is_dir() => {
+                    let from = dir.file_name().and_then(|name| name.to_str());
+                    let from = from.filter(|_| dir.starts_with(&path));
+                    self.open_directory(path, from);
+                }
+                Some(path) => {
+                    self.edit_path(false, path);
+                }
+                None => {}
+            },
+            KeyCode::Char('-') => {
+                if let Some(parent) = dir.parent() {
+                    let from = dir.file_name().and_then(|name| name.to_str());
+                    self.open_directory(parent.to_path_buf(), from);
+                }
+            }
+            KeyCode::Char('%') => {
+                if let Some(name) = self.input("New file: ", "")? {
+                    self.create_file(&dir, &name);
+                }
+            }
+            KeyCode::Char('d') => {
+                if let Some(name) = self.input("New directory: ", "")? {
+                    let path = dir.join(file::expand(&name));
+                    self.file_operation(fs::create_dir(&path));
+                }
+            }
+            KeyCode::Char('R') => {
+                if let (Some(path), Some(name)) = (path, name) {
+                    if let Some(to) = self.input("Rename to: ", &name)? {
+                        let to = dir.join(file::expand(&to));
+                        let result = match to.exists() {
+                            true => {
+                                Err(io::Error::new(io::ErrorKind::AlreadyExists, "File exists"))
+                            }
+                            false => fs::rename(&path, &to),
+                        };
+                        self.file_operation(result);
+                    }
+                }
+            }
+            KeyCode::Char('C') => {
+                if let (Some(path), Some(name)) = (path, name) {
+                    self.copy_file(&dir, &path, &name)?;
+                }
+            }
+            KeyCode::Char('D') => {
+                if let (Some(path), Some(name)) = (path, name) {
+                    if self.confirm(&format!("Delete {}?", name))? {
+                        // a link goes, not the directory it points to
+                        let result = match fs::symlink_metadata(&path) {
+                            Ok(metadata) if metadata.is_dir() => fs::remove_dir(&path),
+                            _ => fs::remove_file(&path),
+                        };
+                        self.file_operation(result);
+                    }
+                }
+            }
+            KeyCode::Char('s') => {
+                self.browser.sort = self.browser.sort.next();
+                self.refresh_listing();
+            }
+            KeyCode::Char('r') => {
+                self.browser.reverse = !self.browser.reverse;
+                self.refresh_listing();
+            }
+            KeyCode::Char('g') => {
+                let next = self.read_key()?;
+                if next.code != KeyCode::Char('h') {
+                    // `gt` and the rest work as they do anywhere else
+                    self.unread_key(next);
+                    return Ok(false);
+                }
+                self.browser.hidden = !self.browser.hidden;
+                self.refresh_listing();
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Lists the directory again after a file got created, renamed or the like, or says why
+    /// that didn't work.
+    fn file_operation(&mut self, result: io::Result<()>) {
+        match result {
+            Ok(()) => self.refresh_listing(),
+            Err(error) => self.output.status_message.set_error(error.to_string()),
+        }
+    }
+
+    /// `%` in a listing makes an empty file called `name` and edits it.
+    fn create_file(&mut self, dir: &Path, name: &str) {
+        let path = dir.join(file::expand(name));
+        let created = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        match created {
+            Ok(_) => {
+                self.edit_path(false, path);
+            }
+            Err(error) => self.output.status_message.set_error(error.to_string()),
+        }
+    }
+
+    /// `C` in a listing copies the file on the cursor line to a name that's asked for.
+    fn copy_file(&mut self, dir: &Path, path: &Path, name: &str) -> io::Result<()> {
+        if path.is_dir() {
+            self.output
+                .status_message
+                .set_error("Cannot copy a directory".into());
+            return Ok(());
+        }
+        let Some(to) = self.input("Copy to: ", name)? else {
+            return Ok(());
+        };
+        let mut to = dir.join(file::expand(&to));
+        if to.is_dir() {
+            to.push(name);
+        }
+        let result = match to.exists() {
+            true => Err(io::Error::new(io::ErrorKind::AlreadyExists, "File exists")),
+            false => fs::copy(path, &to).map(|_| ()),
+        };
+        self.file_operation(result);
+        Ok(())
+    }
+}
@@ -0,0 +1,452 @@
+use crate::undo::History;
+use crate::{CursorController, Editor, EditorRows, Missing, Output, DIRTY, NO_FILE_NAME};
+use std::{
+    collections::BTreeMap,
+    io::{self, stdout, Write},
+    path::Path,
+};
+
+/// A buffer that isn't the current one, with what `Output` keeps of the current one.
+pub struct Buffer {
+    editor_rows: EditorRows,
+    dirty: u8,
+    cursor: CursorController,
+}
+
+/// Every buffer by its number. The current one lives in `Output`, only its number is kept here.
+pub struct Buffers {
+    others: BTreeMap<usize, Buffer>,
+    pub current: usize,
+    /// the buffer that was current before this one, for Ctrl-^
+    pub alternate: Option<usize>,
+    last: usize,
+}
+
+impl Buffers {
+    pub fn new() -> Self {
+        Self {
+            others: BTreeMap::new(),
+            current: 1,
+            alternate: None,
+            last: 1,
+        }
+    }
+
+    /// a buffer that isn't the current one
+    pub fn other_mut(&mut self, nr: usize) -> Option<&mut Buffer> {
+        self.others.get_mut(&nr)
+    }
+
+    /// every buffer number, in order
+    pub fn numbers(&self) -> Vec<usize> {
+        let mut numbers: Vec<usize> = self.others.keys().copied().collect();
+        numbers.push(self.current);
+        numbers.sort();
+        numbers
+    }
+}
+
+/// how `:ls` and the rest show a buffer's name
+pub fn display_name(editor_rows: &EditorRows) -> String {
+    editor_rows
+        .file_name
+        .as_ref()
+        .map_or(NO_FILE_NAME.into(), |path| path.display().to_string())
+}
+
+impl Buffer {
+    /// Trades its rows and changes with the current buffer's, to draw it in another window.
+    pub fn swap_rows(&mut self, output: &mut Output) {
+        std::mem::swap(&mut self.editor_rows, &mut output.editor_rows);
+        std::mem::swap(&mut self.dirty, &mut output.dirty);
+    }
+}
+
+impl Output {
+    /// Puts `buffer` in place of the current buffer, giving back the one it replaces.
+    fn swap_buffer(&mut self, buffer: Buffer) -> Buffer {
+        let old = Buffer {
+            editor_rows: std::mem::replace(&mut self.editor_rows, buffer.editor_rows),
+            dirty: std::mem::replace(&mut self.dirty, buffer.dirty),
+            cursor: std::mem::replace(&mut self.cursor_controller, buffer.cursor),
+        };
+        // the window might have changed size since the buffer was last shown
+        self.cursor_controller.screen_cols = self.win_size.0;
+        self.cursor_controller.screen_rows = self.win_size.1;
+        self.highlight = None;
+        self.update_line_nr_padding();
+        old
+    }
+}
+
+impl Editor {
+    /// The rows, changes and cursor of buffer `nr`, whether it's the current one or not.
+    pub fn buffer(&self, nr: usize) -> Option<(&EditorRows, u8, &CursorController)> {
+        if nr == self.buffers.current {
+            let output = &self.output;
+            return Some((&output.editor_rows, output.dirty, &output.cursor_controller));
+        }
+        let buffer = self.buffers.others.get(&nr)?;
+        Some((&buffer.editor_rows, buffer.dirty, &buffer.cursor))
+    }
+
+    /// the first buffer that has changes that aren't written, the current one goes first
+    pub fn modified_buffer(&self) -> Option<usize> {
+        if self.output.dirty > 0 {
+            return Some(self.buffers.current);
+        }
+        self.buffers
+            .others
+            .iter()
+            .find(|(_, buffer)| buffer.dirty > 0)
+            .map(|(nr, _)| *nr)
+    }
+
+    /// The number of the buffer editing `path`, if there is one.
+    pub fn find_buffer(&self, path: &Path) -> Option<usize> {
+        self.buffers.numbers().into_iter().find(|nr| {
+            self.buffer(*nr)
+                .and_then(|(editor_rows, ..)| editor_rows.file_name.as_deref())
+                == Some(path)
+        })
+    }
+
+    /// Whether the current buffer can be left for another one. Changes keep it from that,
+    /// unless another window shows them, 'hidden' is on or there's a `!`, which all leave it
+    /// hidden with its changes.
+    pub fn can_leave(&mut self, bang: bool) -> bool {
+        let shown = self.shown_elsewhere(self.buffers.current);
+        if self.output.dirty == 0 || self.output.options.bool("hidden") || shown || bang {
+            return true;
+        }
+        self.output
+            .status_message
+            .set_error(format!("{} (add ! to override)", DIRTY));
+        false
+    }
+
+    /// Throws away the changes to the current buffer for `:e! {file}`, what's left of it is
+    /// the file as it is on disk. Its options and `<buffer>` mappings stay.
+    pub fn discard_changes(&mut self) {
+        if self.output.dirty == 0 || self.shown_elsewhere(self.buffers.current) {
+            return;
+        }
+        let editor_rows = &mut self.output.editor_rows;
+        let lines = editor_rows
+            .file_name
+            .as_deref()
+            .and_then(|path| EditorRows::read_lines(path).ok())
+            .unwrap_or_default();
+        let tab_stop = editor_rows.tab_stop();
+        editor_rows.row_contents = lines
+            .into_iter()
+            .map(|line| EditorRows::make_row(line, tab_stop))
+            .collect();
+        editor_rows.history = History::default();
+        editor_rows.marks.clear();
+        editor_rows.lowest_marked = 0;
+        self.output.dirty = 0;
+    }
+
+    /// Makes buffer `nr` the current one, the one it replaces becomes the alternate buffer.
+    pub fn enter_buffer(&mut self, nr: usize) {
+        let Some(buffer) = self.buffers.others.remove(&nr) else {
+            return;
+        };
+        let old = self.output.swap_buffer(buffer);
+        self.buffers.others.insert(self.buffers.current, old);
+        self.buffers.alternate = Some(self.buffers.current);
+        self.buffers.current = nr;
+    }
+
+    /// Switches to buffer `nr`, if the current one can be left.
+    pub fn switch_buffer(&mut self, nr: usize, bang: bool) -> bool {
+        if nr == self.buffers.current {
+            return true;
+        }
+        if !self.buffers.others.contains_key(&nr) {
+            self.output
+                .status_message
+                .set_error(format!("Buffer {} does not exist", nr));
+            return false;
+        }
+        if !self.can_leave(bang) {
+            return false;
+        }
+        self.enter_buffer(nr);
+        let editor_rows = &self.output.editor_rows;
+        let message = format!(
+            "\"{}\" {}L",
+            display_name(editor_rows),
+            editor_rows.nr_of_rows()
+        );
+        self.output.status_message.set_message(message);
+        true
+    }
+
+    /// Makes `editor_rows` a buffer of its own and switches to it. An empty buffer without a
+    /// name, like the one there is at the start, gets taken over instead.
+    pub fn add_buffer(&mut self, editor_rows: EditorRows, bang: bool) -> bool {
+        if !self.can_leave(bang) {
+            return false;
+        }
+        let unused = self.output.editor_rows.file_name.is_none()
+            && self.output.dirty == 0
+            && self.output.editor_rows.nr_of_rows() == 0
+            && !self.shown_elsewhere(self.buffers.current);
+        if unused {
+            self.output.set_rows(editor_rows);
+            return true;
+        }
+        self.buffers.last += 1;
+        let nr = self.buffers.last;
+        self.buffers.others.insert(
+            nr,
+            Buffer {
+                editor_rows,
+                dirty: 0,
+                cursor: CursorController::new(self.output.win_size),
+            },
+        );
+        self.enter_buffer(nr);
+        true
+    }
+
+    /// The buffer `arg` stands for: a number, or part of a name that only one buffer has.
+    /// Nothing means the current one.
+    fn buffer_arg(&mut self, arg: &str) -> Option<usize> {
+        let arg = arg.trim();
+        let found = match arg.parse::<usize>() {
+            _ if arg.is_empty() => Ok(self.buffers.current),
+            Ok(nr) if self.buffer(nr).is_some() => Ok(nr),
+            Ok(nr) => Err(format!("Buffer {} does not exist", nr)),
+            Err(_) => {
+                let names: Vec<(usize, String)> = self
+                    .buffers
+                    .numbers()
+                    .into_iter()
+                    .filter_map(|nr| Some((nr, display_name(self.buffer(nr)?.0))))
+                    .collect();
+                // the whole name goes before those it's only part of
+                let matching: Vec<usize> = match names.iter().find(|(_, name)| name == arg) {
+                    Some((nr, _)) => vec![*nr],
+                    None => names
+                        .iter()
+                        .filter(|(_, name)| name.contains(arg))
+                        .map(|(nr, _)| *nr)
+                        .collect(),
+                };
+                match matching[..] {
+                    [nr] => Ok(nr),
+                    [] => Err(format!("No matching buffer for {}", arg)),
+                    _ => Err(format!("More than one match for {}", arg)),
+                }
+            }
+        };
+        match found {
+            Ok(nr) => Some(nr),
+            Err(error) => {
+                self.output.status_message.set_error(error);
+                None
+            }
+        }
+    }
+
+    /// every buffer's name, for completing `:b`
+    pub fn buffer_names(&self) -> Vec<String> {
+        self.buffers
+            .numbers()
+            .into_iter()
+            .filter_map(|nr| {
+                self.buffer(nr)
+                    .map(|(editor_rows, ..)| display_name(editor_rows))
+            })
+            .collect()
+    }
+
+    /// `:b[uffer][!] [N|name]`
+    pub fn buffer_command(&mut self, bang: bool, args: &str) {
+        if let Some(nr) = self.buffer_arg(args) {
+            self.switch_buffer(nr, bang);
+        }
+    }
+
+    /// `:bn[ext][!]` and `:bp[revious][!]`, going around at the last and the first buffer.
+    pub fn cycle_buffer(&mut self, bang: bool, backwards: bool) {
+        let numbers = self.buffers.numbers();
+        let idx = numbers
+            .iter()
+            .position(|nr| *nr == self.buffers.current)
+            .unwrap_or(0);
+        let next = match backwards {
+            true => (idx + numbers.len() - 1) % numbers.len(),
+            false => (idx + 1) % numbers.len(),
+        };
+        self.switch_buffer(numbers[next], bang);
+    }
+
+    /// Ctrl-^ goes back to the buffer that was current before.
+    pub fn alternate_buffer(&mut self) {
+        match self.buffers.alternate {
+            Some(nr) => {
+                self.switch_buffer(nr, false);
+            }
+            None => self
+                .output
+                .status_message
+                .set_error("No alternate file".into()),
+        }
+    }
+
+    /// `:bd[elete][!] [N|name]` drops a buffer. For the current one another one takes its
+    /// place, an empty one if it was the last.
+    pub fn delete_buffer(&mut self, bang: bool, args: &str) {
+        let Some(nr) = self.buffer_arg(args) else {
+            return;
+        };
+        let dirty = self.buffer(nr).is_some_and(|(_, dirty, _)| dirty > 0);
+        if dirty && !bang {
+            self.output.status_message.set_error(format!(
+                "No write since last change for buffer {} (add ! to override)",
+                nr
+            ));
+            return;
+        }
+
+        self.close_buffer_windows(nr);
+        if nr != self.buffers.current {
+            self.buffers.others.remove(&nr);
+        } else {
+            let next = self
+                .buffers
+                .alternate
+                .or_else(|| self.buffers.others.range(nr..).next().map(|(nr, _)| *nr))
+                .or_else(|| self.buffers.others.keys().next_back().copied());
+            match next {
+                Some(next) => {
+                    let buffer = self.buffers.others.remove(&next).unwrap();
+                    self.output.swap_buffer(buffer);
+                    self.buffers.current = next;
+                }
+                None => {
+                    self.buffers.last += 1;
+                    self.buffers.current = self.buffers.last;
+                    self.output
+                        .set_rows(EditorRows::new(self.output.options.clone()));
+                }
+            }
+        }
+        if self
+            .buffers
+            .alternate
+            .is_some_and(|alternate| alternate == nr || alternate == self.buffers.current)
+        {
+            self.buffers.alternate = None;
+        }
+    }
+
+    /// `:ls`, every buffer with `%` for the current one, `#` for the alternate one, `a` for
+    /// those in a window and `+` for those with changes.
+    pub fn list_buffers(&mut self) -> io::Result<()> {
+        let lines: Vec<String> = self
+            .buffers
+            .numbers()
+            .into_iter()
+            .filter_map(|nr| {
+                let (editor_rows, dirty, cursor) = self.buffer(nr)?;
+                let flag = match Some(nr) {
+                    current if current == Some(self.buffers.current) => '%',
+                    alternate if alternate == self.buffers.alternate => '#',
+                    _ => ' ',
+                };
+                let shown = if self.shown(nr) { 'a' } else { 'h' };
+                let name = format!("\"{}\"", display_name(editor_rows));
+                Some(format!(
+                    "{:>3} {}{} {} {:<30} line {}",
+                    nr,
+                    flag,
+                    shown,
+                    if dirty > 0 { '+' } else { ' ' },
+                    name,
+                    cursor.cursor_y + 1
+                ))
+            })
+            .collect();
+
+        Output::clear_screen()?;
+        print!("{}", lines.join("\r\n"));
+        stdout().flush()?;
+        self.press_enter()
+    }
+
+    /// `:wa[ll][!]` writes every buffer with changes, gives back whether they all got written.
+    pub fn write_all(&mut self, bang: bool) -> bool {
+        if self.output.dirty > 0 && !self.write_file(None, bang, "") {
+            return false;
+        }
+        let modified: Vec<usize> = self
+            .buffers
+            .others
+            .iter()
+            .filter(|(_, buffer)| buffer.dirty > 0)
+            .map(|(nr, _)| *nr)
+            .collect();
+        for nr in modified {
+            let editor_rows = &self.buffers.others[&nr].editor_rows;
+            let Some(path) = editor_rows.file_name.clone() else {
+                self.output
+                    .status_message
+                    .set_error(format!("No file name for buffer {}", nr));
+                return false;
+            };
+            if editor_rows.missing == Some(Missing::Unread) && !bang {
+                self.output.status_message.set_error(format!(
+                    "\"{}\" could not be read (add ! to override)",
+                    path.display()
+                ));
+                return false;
+            }
+            if !self.has_directory(&path) {
+                return false;
+            }
+            let buffer = self.buffers.others.get_mut(&nr).unwrap();
+            let rows = 0..buffer.editor_rows.nr_of_rows();
+            let count = rows.len();
+            match buffer.editor_rows.write(&path, rows) {
+                Ok(len) => {
+                    buffer.dirty = 0;
+                    buffer.editor_rows.missing = None;
+                    self.output.status_message.set_message(format!(
+                        "\"{}\" {}L, {}B written",
+                        path.display(),
+                        count,
+                        len
+                    ));
+                }
+                Err(error) => {
+                    self.output.status_message.set_error(format!(
+                        "\"{}\" {}",
+                        path.display(),
+                        error
+                    ));
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// `:qa[ll]`, only when no buffer has changes left to write.
+    pub fn quit_all(&mut self) -> bool {
+        match self.modified_buffer() {
+            Some(nr) => {
+                self.output.status_message.set_error(format!(
+                    "No write since last change for buffer {} (add ! to override)",
+                    nr
+                ));
+                false
+            }
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::regex::is_word_char;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// how many entries a history keeps, same as vim's default 'history'
+const HISTORY_LEN: usize = 50;
+
+/// Past command lines, oldest first. The `:` and `/` prompts each keep their own.
+#[derive(Default)]
+pub struct CmdHistory {
+    entries: Vec<String>,
+}
+
+impl CmdHistory {
+    /// an entry that's already there moves to the end instead of showing up twice
+    pub fn add(&mut self, entry: &str) {
+        if entry.is_empty() {
+            return;
+        }
+        self.entries.retain(|old| old != entry);
+        self.entries.push(entry.to_string());
+        if self.entries.len() > HISTORY_LEN {
+            self.entries.remove(0);
+        }
+    }
+}
+
+/// what a key did to the command line
+pub enum Action {
+    Edited,
+    Moved,
+    Enter,
+    Cancel,
+    Ignored,
+}
+
+/// The text being typed at a `:` or `/` prompt.
+pub struct CmdLine {
+    pub text: String,
+    /// byte offset into `text`
+    pub cursor: usize,
+    /// while going through the history, the entry shown and what had been typed before
+    browsing: Option<(usize, String)>,
+}
+
+impl CmdLine {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            cursor: text.len(),
+            browsing: None,
+        }
+    }
+
+    /// how many chars come before the cursor
+    pub fn cursor_col(&self) -> usize {
+        self.text[..self.cursor].chars().count()
+    }
+
+    fn prev_char(&self) -> usize {
+        self.text[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(idx, _)| idx)
+    }
+
+    fn next_char(&self) -> usize {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |ch| self.cursor + ch.len_utf8())
+    }
+
+    /// start of the word before the cursor, a word being a run of keyword chars or of other
+    /// non blanks like in vim
+    fn word_start(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end();
+        let Some(last) = before.chars().next_back() else {
+            return 0;
+        };
+        let same_kind = |ch: char| !ch.is_whitespace() && is_word_char(ch) == is_word_char(last);
+        before
+            .char_indices()
+            .rev()
+            .take_while(|(_, ch)| same_kind(*ch))
+            .last()
+            .map_or(before.len(), |(idx, _)| idx)
+    }
+
+    /// start of the next blank separated word
+    fn next_word(&self) -> usize {
+        let after = &self.text[self.cursor..];
+        let word_end = after.find(char::is_whitespace).unwrap_or(after.len());
+        let next = after[word_end..]
+            .find(|ch: char| !ch.is_whitespace())
+            .map_or(after.len(), |idx| word_end + idx);
+        self.cursor + next
+    }
+
+    fn delete(&mut self, from: usize, to: usize) -> Action {
+        self.text.replace_range(from..to, "");
+        self.cursor = from;
+        Action::Edited
+    }
+
+    /// Up and Down go through the entries starting with what was typed before browsing.
+    fn browse(&mut self, history: &CmdHistory, older: bool) -> Action {
+        let entries = &history.entries;
+        let (idx, typed) = self
+            .browsing
+            .take()
+            .unwrap_or_else(|| (entries.len(), self.text.clone()));
+        let found = if older {
+            entries[..idx]
+                .iter()
+                .rposition(|entry| entry.starts_with(&typed))
+        } else {
+            entries
+                .get(idx + 1..)
+                .and_then(|newer| newer.iter().position(|entry| entry.starts_with(&typed)))
+                .map(|found| idx + 1 + found)
+        };
+
+        let idx = match found {
+            Some(found) => {
+                self.text = entries[found].clone();
+                found
+            }
+            // past the newest entry there's what was typed
+            None if !older => {
+                self.text = typed.clone();
+                entries.len()
+            }
+            None => idx,
+        };
+        self.cursor = self.text.len();
+        self.browsing = Some((idx, typed));
+        Action::Edited
+    }
+
+    pub fn handle(&mut self, key: KeyEvent, history: &CmdHistory) -> Action {
+        let KeyEvent {
+            code,
+            modifiers,
+            kind: _,
+            state: _,
+        } = key;
+        if !matches!(code, KeyCode::Up | KeyCode::Down) {
+            self.browsing = None;
+        }
+        let control = modifiers.contains(KeyModifiers::CONTROL);
+
+        match code {
+            KeyCode::Enter => Action::Enter,
+            KeyCode::Esc => Action::Cancel,
+            KeyCode::Char('c') if control => Action::Cancel,
+            // like vim, backspacing over nothing leaves the prompt
+            KeyCode::Backspace if self.text.is_empty() => Action::Cancel,
+            KeyCode::Backspace => self.delete(self.prev_char(), self.cursor),
+            KeyCode::Char('h') if control => self.delete(self.prev_char(), self.cursor),
+            KeyCode::Delete if self.cursor == self.text.len() => {
+                self.delete(self.prev_char(), self.cursor)
+            }
+            KeyCode::Delete => self.delete(self.cursor, self.next_char()),
+            KeyCode::Char('w') if control => self.delete(self.word_start(), self.cursor),
+            KeyCode::Char('u') if control => self.delete(0, self.cursor),
+            KeyCode::Left if control || modifiers.contains(KeyModifiers::SHIFT) => {
+                self.cursor = self.word_start();
+                Action::Moved
+            }
+            KeyCode::Right if control || modifiers.contains(KeyModifiers::SHIFT) => {
+                self.cursor = self.next_word();
+                Action::Moved
+            }
+            KeyCode::Left => {
+                self.cursor = self.prev_char();
+                Action::Moved
+            }
+            KeyCode::Right => {
+                self.cursor = self.next_char();
+                Action::Moved
+            }
+            KeyCode::Home => {
+                self.cursor = 0;
+                Action::Moved
+            }
+            KeyCode::Char('b') if control => {
+                self.cursor = 0;
+                Action::Moved
+            }
+            KeyCode::End => {
+                self.cursor = self.text.len();
+                Action::Moved
+            }
+            KeyCode::Char('e') if control => {
+                self.cursor = self.text.len();
+                Action::Moved
+            }
+            KeyCode::Up => self.browse(history, true),
+            KeyCode::Down => self.browse(history, false),
+            KeyCode::Char(ch) if !control && !modifiers.contains(KeyModifiers::ALT) => {
+                self.text.insert(self.cursor, ch);
+                self.cursor += ch.len_utf8();
+                Action::Edited
+            }
+            _ => Action::Ignored,
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::cmdline::CmdLine;
+use crate::{ex, file, options, Editor};
+use std::fs;
+
+/// commands whose arguments are file names
+const FILE_ARGS: &[&str] = &[
+    "edit", "read", "write", "wq", "saveas", "update", "xit", "exit", "source", "args", "next",
+    "split", "vsplit", "new", "vnew", "tabnew", "tabedit",
+];
+
+/// What the arguments of a command complete to, `:command -complete=` picks one by name.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    File,
+    Dir,
+    Command,
+    Option,
+    Buffer,
+}
+
+const ARG_KINDS: &[(&str, ArgKind)] = &[
+    ("file", ArgKind::File),
+    ("dir", ArgKind::Dir),
+    ("command", ArgKind::Command),
+    ("option", ArgKind::Option),
+    ("buffer", ArgKind::Buffer),
+];
+
+impl ArgKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        ARG_KINDS
+            .iter()
+            .find(|(kind_name, _)| *kind_name == name)
+            .map(|(_, kind)| *kind)
+    }
+
+    pub fn name(self) -> &'static str {
+        ARG_KINDS
+            .iter()
+            .find(|(_, kind)| *kind == self)
+            .map_or("", |(name, _)| name)
+    }
+}
+
+/// Tab completion going on at the `:` prompt, the candidates cycle through the command line.
+pub struct Completion {
+    /// the command line before and after the completed text
+    head: String,
+    tail: String,
+    /// what was typed, it comes back after the last candidate
+    typed: String,
+    pub candidates: Vec<String>,
+    /// `None` while `typed` is the one in the command line
+    pub selected: Option<usize>,
+}
+
+impl Completion {
+    /// Selects the next candidate, or the previous one going `backwards`, and puts it in the
+    /// command line.
+    pub fn cycle(&mut self, cmdline: &mut CmdLine, backwards: bool) {
+        let last = self.candidates.len() - 1;
+        self.selected = match (self.selected, backwards) {
+            (None, false) => Some(0),
+            (None, true) => Some(last),
+            (Some(idx), false) if idx == last => None,
+            (Some(0), true) => None,
+            (Some(idx), false) => Some(idx + 1),
+            (Some(idx), true) => Some(idx - 1),
+        };
+
+        let text = self
+            .selected
+            .map_or(self.typed.as_str(), |idx| &self.candidates[idx]);
+        cmdline.text = format!("{}{}{}", self.head, text, self.tail);
+        cmdline.cursor = self.head.len() + text.len();
+    }
+
+    /// what the wildmenu shows for a candidate, files go by their name without the directory
+    pub fn label(candidate: &str) -> &str {
+        let name = candidate.trim_end_matches('/');
+        let start = name.rfind('/').map_or(0, |idx| idx + 1);
+        &candidate[start..]
+    }
+}
+
+/// Start of the word the cursor is at the end of, words being separated by unescaped blanks.
+fn word_start(text: &str) -> usize {
+    let mut start = 0;
+    let mut chars = text.char_indices();
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            ch if ch.is_whitespace() => start = idx + ch.len_utf8(),
+            _ => {}
+        }
+    }
+    start
+}
+
+/// Length of the range in front of a command, close enough for completion, which only needs
+/// to find where the name starts.
+fn range_len(text: &str) -> usize {
+    let mut chars = text.char_indices();
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '\'' => {
+                chars.next();
+            }
+            '0'..='9' | '.' | ',' | ';' | '$' | '%' | '+' | '-' | ' ' | ':' => {}
+            _ => return idx,
+        }
+    }
+    text.len()
+}
+
+/// Files and directories starting with `word`, which can start with a directory of its own.
+fn paths(word: &str) -> Vec<String> {
+    let unescaped = word.replace("\\ ", " ");
+    let (dir, prefix) = match unescaped.rfind('/') {
+        Some(idx) => unescaped.split_at(idx + 1),
+        None => ("", unescaped.as_str()),
+    };
+    let search_dir = match dir {
+        "" => ".".into(),
+        dir => file::expand(dir),
+    };
+    let Ok(entries) = fs::read_dir(search_dir) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            // hidden files only when asked for
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            let path = format!("{}{}{}", dir, name, if is_dir { "/" } else { "" });
+            Some(path.replace(' ', "\\ "))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+impl Editor {
+    /// every command, the built in ones and then the user defined ones
+    fn command_names(&self) -> Vec<String> {
+        ex::command_names()
+            .into_iter()
+            .map(String::from)
+            .chain(self.user_commands.keys().cloned())
+            .collect()
+    }
+
+    /// what the arguments of the command `name` (as typed) complete to
+    fn arg_kind(&self, name: &str) -> Option<ArgKind> {
+        if ex::is_user_command(name) {
+            return self.user_command(name).ok()?.1.complete;
+        }
+        match ex::full_name(name)? {
+            "set" | "setlocal" => Some(ArgKind::Option),
+            "buffer" | "bdelete" => Some(ArgKind::Buffer),
+            command if FILE_ARGS.contains(&command) => Some(ArgKind::File),
+            _ => None,
+        }
+    }
+
+    fn arg_candidates(&self, kind: ArgKind, word: &str) -> Vec<String> {
+        match kind {
+            ArgKind::File => paths(word),
+            ArgKind::Dir => paths(word)
+                .into_iter()
+                .filter(|path| path.ends_with('/'))
+                .collect(),
+            ArgKind::Command => self
+                .command_names()
+                .into_iter()
+                .filter(|command| command.starts_with(word))
+                .collect(),
+            ArgKind::Buffer => self
+                .buffer_names()
+                .into_iter()
+                .filter(|name| name.contains(word))
+                .collect(),
+            ArgKind::Option => {
+                // booleans can be switched off or toggled with a `no` or `inv` in front
+                let (prefix, option) = ["no", "inv"]
+                    .iter()
+                    .find_map(|prefix| Some((*prefix, word.strip_prefix(prefix)?)))
+                    .unwrap_or(("", word));
+                options::names()
+                    .filter(|name| name.starts_with(option))
+                    .map(|name| format!("{}{}", prefix, name))
+                    .collect()
+            }
+        }
+    }
+
+    /// Works out the candidates for whatever the command line ends with up to the cursor,
+    /// `None` when there are none.
+    pub fn complete(&self, cmdline: &CmdLine) -> Option<Completion> {
+        let before = &cmdline.text[..cmdline.cursor];
+        let name_start = range_len(before);
+        let name_len = before[name_start..]
+            .find(|ch: char| !ch.is_ascii_alphanumeric())
+            .unwrap_or(before.len() - name_start);
+        let name = &before[name_start..name_start + name_len];
+
+        let (start, candidates): (usize, Vec<String>) = if name_start + name_len == before.len() {
+            (name_start, self.arg_candidates(ArgKind::Command, name))
+        } else {
+            let start = word_start(before);
+            let candidates = match self.arg_kind(name) {
+                Some(kind) => self.arg_candidates(kind, &before[start..]),
+                None => Vec::new(),
+            };
+            (start, candidates)
+        };
+
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(Completion {
+            head: cmdline.text[..start].to_string(),
+            tail: cmdline.text[cmdline.cursor..].to_string(),
+            typed: before[start..].to_string(),
+            candidates,
+            selected: None,
+        })
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{Editor, Output, NAME};
+use std::{
+    env, fs,
+    io::{self, stdout, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// how deep `:source` can go, so a file sourcing itself doesn't run forever
+const MAX_SOURCE_DEPTH: usize = 50;
+
+/// `$XDG_CONFIG_HOME/v2/init.v2`, with `~/.config` standing in for an unset `$XDG_CONFIG_HOME`
+pub fn init_file() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join(NAME).join(format!("init.{}", NAME)))
+}
+
+impl Editor {
+    /// Runs every line of `path` as an ex command, `"` starting a comment. Lines that go wrong
+    /// don't stop the rest, what they said gets listed along with their line number at the
+    /// end, what went fine doesn't. A missing file is only an error when it's `required`.
+    pub fn source(&mut self, path: &Path, required: bool) -> io::Result<bool> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if !required && error.kind() == ErrorKind::NotFound => return Ok(true),
+            Err(error) => {
+                self.output.status_message.set_error(format!(
+                    "Can't source \"{}\": {}",
+                    path.display(),
+                    error
+                ));
+                return Ok(true);
+            }
+        };
+        if self.source_depth >= MAX_SOURCE_DEPTH {
+            self.output
+                .status_message
+                .set_error("Sourcing nested too deep".into());
+            return Ok(true);
+        }
+
+        self.source_depth += 1;
+        let mut errors = Vec::new();
+        let mut running = true;
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('"') {
+                continue;
+            }
+            self.output.status_message.error = None;
+            let result = self.parse_command(line.to_string());
+            if let Some(error) = self.output.status_message.error.take() {
+                errors.push(format!("line {:>4}: {}", idx + 1, error));
+            }
+            match result {
+                Ok(true) => {}
+                // a `:q` in there still quits
+                Ok(false) => {
+                    running = false;
+                    break;
+                }
+                Err(error) => {
+                    self.source_depth -= 1;
+                    return Err(error);
+                }
+            }
+        }
+        self.source_depth -= 1;
+
+        if !errors.is_empty() {
+            Output::clear_screen()?;
+            print!("Error detected while processing {}:", path.display());
+            for error in &errors {
+                print!("\r\n{}", error);
+            }
+            stdout().flush()?;
+            self.press_enter()?;
+            self.output.status_message.message = None;
+            self.output.status_message.set_time = None;
+        }
+        Ok(running)
+    }
+}
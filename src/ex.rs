@@ -0,0 +1,703 @@
+use crate::search::Search;
+use crate::substitute::split_delimited;
+use crate::EditorRows;
+
+/// Every ex command by the shortest abbreviation it goes by and its full name. An abbreviation
+/// that fits more than one goes to the first, so `:s` is `:substitute` and not `:set`.
+const COMMANDS: &[(&str, &str)] = &[
+    ("s", "substitute"),
+    ("se", "set"),
+    ("setl", "setlocal"),
+    ("so", "source"),
+    ("&", "&"),
+    ("!", "!"),
+    ("g", "global"),
+    ("v", "vglobal"),
+    ("d", "delete"),
+    ("m", "move"),
+    ("ma", "mark"),
+    ("co", "copy"),
+    ("t", "t"),
+    ("u", "undo"),
+    ("red", "redo"),
+    ("noh", "nohlsearch"),
+    ("e", "edit"),
+    ("r", "read"),
+    ("w", "write"),
+    ("wq", "wq"),
+    ("sav", "saveas"),
+    ("up", "update"),
+    ("x", "xit"),
+    ("exi", "exit"),
+    ("q", "quit"),
+    ("map", "map"),
+    ("nm", "nmap"),
+    ("vm", "vmap"),
+    ("im", "imap"),
+    ("no", "noremap"),
+    ("nn", "nnoremap"),
+    ("vn", "vnoremap"),
+    ("ino", "inoremap"),
+    ("unm", "unmap"),
+    ("nun", "nunmap"),
+    ("vu", "vunmap"),
+    ("iu", "iunmap"),
+    ("mapc", "mapclear"),
+    ("nmapc", "nmapclear"),
+    ("vmapc", "vmapclear"),
+    ("imapc", "imapclear"),
+    ("com", "command"),
+    ("delc", "delcommand"),
+    ("comc", "comclear"),
+    ("norm", "normal"),
+    ("sor", "sort"),
+    ("ret", "retab"),
+    ("ce", "center"),
+    ("ri", "right"),
+    ("le", "left"),
+    ("b", "buffer"),
+    ("bn", "bnext"),
+    ("bp", "bprevious"),
+    ("bN", "bNext"),
+    ("bd", "bdelete"),
+    ("ls", "ls"),
+    ("files", "files"),
+    ("buffers", "buffers"),
+    ("qa", "qall"),
+    ("quita", "quitall"),
+    ("wa", "wall"),
+    ("wqa", "wqall"),
+    ("xa", "xall"),
+    ("n", "next"),
+    ("N", "Next"),
+    ("prev", "previous"),
+    ("fir", "first"),
+    ("rew", "rewind"),
+    ("la", "last"),
+    ("ar", "args"),
+    ("argdo", "argdo"),
+    ("bufdo", "bufdo"),
+    ("windo", "windo"),
+    ("cdo", "cdo"),
+    ("cfdo", "cfdo"),
+    ("vim", "vimgrep"),
+    ("cc", "cc"),
+    ("cn", "cnext"),
+    ("cp", "cprevious"),
+    ("cN", "cNext"),
+    ("cl", "clist"),
+    ("sp", "split"),
+    ("vs", "vsplit"),
+    ("new", "new"),
+    ("vne", "vnew"),
+    ("clo", "close"),
+    ("on", "only"),
+    ("res", "resize"),
+    ("winc", "wincmd"),
+    ("tabnew", "tabnew"),
+    ("tabe", "tabedit"),
+    ("tabc", "tabclose"),
+    ("tabo", "tabonly"),
+    ("tabm", "tabmove"),
+    ("tabn", "tabnext"),
+    ("tabp", "tabprevious"),
+    ("tabN", "tabNext"),
+    ("tabs", "tabs"),
+];
+
+/// commands that can be followed by a `!`
+const BANG: &[&str] = &[
+    "global",
+    "edit",
+    "write",
+    "wq",
+    "saveas",
+    "update",
+    "xit",
+    "exit",
+    "quit",
+    "command",
+    "normal",
+    "sort",
+    "retab",
+    "buffer",
+    "bnext",
+    "bprevious",
+    "bNext",
+    "bdelete",
+    "qall",
+    "quitall",
+    "wall",
+    "wqall",
+    "xall",
+    "next",
+    "Next",
+    "previous",
+    "first",
+    "rewind",
+    "last",
+    "args",
+    "argdo",
+    "bufdo",
+    "cdo",
+    "cfdo",
+    "vimgrep",
+    "cc",
+    "cnext",
+    "cprevious",
+    "cNext",
+    "close",
+    "only",
+    "tabclose",
+    "tabonly",
+];
+
+/// commands that see a `|` as part of their argument instead of the start of the next command
+const BAR_ARG: &[&str] = &[
+    "global", "vglobal", "!", "command", "normal", "argdo", "bufdo", "windo", "cdo", "cfdo",
+];
+
+/// commands that change the buffer, `:!` only when it has a range to filter
+const CHANGES: &[&str] = &[
+    "substitute",
+    "&",
+    "delete",
+    "move",
+    "copy",
+    "t",
+    "read",
+    "sort",
+    "retab",
+    "center",
+    "right",
+    "left",
+    "undo",
+    "redo",
+];
+
+/// commands that take no argument at all
+const NO_ARGS: &[&str] = &[
+    "undo",
+    "redo",
+    "nohlsearch",
+    "quit",
+    "bnext",
+    "bprevious",
+    "bNext",
+    "ls",
+    "files",
+    "buffers",
+    "qall",
+    "quitall",
+    "wall",
+    "wqall",
+    "xall",
+    "Next",
+    "previous",
+    "first",
+    "rewind",
+    "last",
+    "cnext",
+    "cprevious",
+    "cNext",
+    "clist",
+    "close",
+    "only",
+    "tabonly",
+    "tabs",
+];
+
+/// An inclusive range of rows, 0 based.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Range {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Range {
+    pub fn line(line: usize) -> Self {
+        Self {
+            start: line,
+            end: line,
+        }
+    }
+}
+
+/// what the addresses get resolved against
+pub struct Position<'a> {
+    pub cursor_y: usize,
+    pub editor_rows: &'a EditorRows,
+    pub search: &'a Search,
+}
+
+/// An ex command split into its parts, the range already resolved.
+pub struct Command<'a> {
+    pub range: Option<Range>,
+    /// the full name, empty for a bare range like `:12`, user commands go by what was typed
+    pub name: &'a str,
+    pub bang: bool,
+    pub args: &'a str,
+    /// the last `/pattern/` of the range, it becomes the last search pattern
+    pub pattern: Option<String>,
+    /// the last address of the range as a 1 based line number, `0` (before the first line)
+    /// included, for `:r`
+    pub line: Option<usize>,
+    /// whatever follows a `|`
+    pub next: Option<&'a str>,
+}
+
+fn parse_number(cmd: &str) -> (Option<usize>, &str) {
+    let digits = cmd.len() - cmd.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    (cmd[..digits].parse().ok(), &cmd[digits..])
+}
+
+struct AddressParser<'a, 'b> {
+    pos: &'b Position<'a>,
+    pattern: Option<String>,
+    /// the last address of the range, before it got turned into a row
+    line: Option<usize>,
+}
+
+impl AddressParser<'_, '_> {
+    /// The first line after (or before) line `from` matching `pattern`, wrapping around the file.
+    /// Lines are 1 based, so searching from line 0 includes the first one.
+    fn search(&mut self, pattern: String, from: isize, backward: bool) -> Result<isize, String> {
+        let pattern = match pattern.is_empty() {
+            false => pattern,
+            true => self
+                .pattern
+                .clone()
+                .or_else(|| self.pos.search.pattern.clone())
+                .ok_or("No previous regular expression")?,
+        };
+        let regex = self
+            .pos
+            .search
+            .regex(&pattern)
+            .map_err(|error| error.to_string())?;
+        let editor_rows = self.pos.editor_rows;
+        let nr_of_rows = editor_rows.nr_of_rows() as isize;
+
+        for step in 1..=nr_of_rows {
+            let y = if backward {
+                (from - 1 - step).rem_euclid(nr_of_rows)
+            } else {
+                (from - 1 + step).rem_euclid(nr_of_rows)
+            };
+            if regex.find_at(editor_rows, y as usize, 0).is_some() {
+                self.pattern = Some(pattern);
+                return Ok(y + 1);
+            }
+        }
+        Err(format!("Pattern not found: {}", pattern))
+    }
+
+    /// A single address like `.`, `$`, `12`, `'a`, `/pat/` or `.+3`, as a 1 based line number so
+    /// that `0` (before the first line) stays expressible. `cursor` is the line `.` stands for.
+    fn address<'c>(
+        &mut self,
+        cmd: &'c str,
+        cursor: isize,
+    ) -> Result<(Option<isize>, &'c str), String> {
+        let nr_of_rows = self.pos.editor_rows.nr_of_rows() as isize;
+        let mut chars = cmd.chars();
+        let (mut line, mut rest) = match chars.next() {
+            Some('.') => (Some(cursor), &cmd[1..]),
+            Some('$') => (Some(nr_of_rows), &cmd[1..]),
+            Some(ch) if ch.is_ascii_digit() => {
+                let (number, rest) = parse_number(cmd);
+                (number.map(|n| n as isize), rest)
+            }
+            Some('\'') => {
+                let mark = chars.next().ok_or("Invalid range")?;
+                let (_, y) = self.pos.editor_rows.mark(mark).ok_or("Mark not set")?;
+                (Some(y as isize + 1), &cmd[1 + mark.len_utf8()..])
+            }
+            _ => (None, cmd),
+        };
+
+        loop {
+            match rest.chars().next() {
+                Some(sign @ ('+' | '-')) => {
+                    let (number, after) = parse_number(&rest[1..]);
+                    let offset = number.unwrap_or(1) as isize;
+                    let current = line.unwrap_or(cursor);
+                    line = Some(if sign == '+' {
+                        current + offset
+                    } else {
+                        current - offset
+                    });
+                    rest = after;
+                }
+                Some(delimiter @ ('/' | '?')) => {
+                    let (pattern, after) = split_delimited(&rest[1..], delimiter);
+                    let from = line.unwrap_or(cursor);
+                    line = Some(self.search(pattern, from, delimiter == '?')?);
+                    rest = after.unwrap_or("");
+                }
+                _ => break,
+            }
+        }
+        Ok((line, rest))
+    }
+
+    /// Splits the leading range off an ex command, `None` when the command has none. With more
+    /// than two addresses the last two count, `;` makes the next address relative to the last.
+    fn range<'c>(&mut self, cmd: &'c str) -> Result<(Option<Range>, &'c str), String> {
+        let nr_of_rows = self.pos.editor_rows.nr_of_rows();
+        if let Some(rest) = cmd.strip_prefix('%') {
+            let range = (nr_of_rows > 0).then(|| Range {
+                start: 0,
+                end: nr_of_rows - 1,
+            });
+            self.line = Some(nr_of_rows);
+            return Ok((range, rest));
+        }
+
+        let mut cursor = self.pos.cursor_y as isize + 1;
+        let mut lines = Vec::new();
+        let mut rest = cmd;
+        loop {
+            let (line, after) = self.address(rest.trim_start(), cursor)?;
+            rest = after;
+            match rest.trim_start().chars().next() {
+                Some(separator @ (',' | ';')) => {
+                    let line = line.unwrap_or(cursor);
+                    if separator == ';' {
+                        cursor = line;
+                    }
+                    lines.push(line);
+                    rest = &rest.trim_start()[1..];
+                }
+                _ => {
+                    // `:5,` is `:5,.`
+                    if let Some(line) = line.or((!lines.is_empty()).then_some(cursor)) {
+                        lines.push(line);
+                    }
+                    break;
+                }
+            }
+        }
+
+        let (first, last) = match lines[..] {
+            [] => return Ok((None, rest)),
+            [line] => (line, line),
+            [.., first, last] => (first, last),
+        };
+        let to_row = |line: isize| -> Result<usize, String> {
+            if line < 0 || line > nr_of_rows as isize {
+                Err("Invalid range".into())
+            } else {
+                Ok(line.max(1) as usize - 1)
+            }
+        };
+
+        let (start, end) = (to_row(first)?, to_row(last)?);
+        if start > end {
+            return Err("Backwards range given".into());
+        }
+        self.line = Some(last as usize);
+        Ok((Some(Range { start, end }), rest))
+    }
+}
+
+/// A lone address, like the destination of `:m`, as a 1 based line number with `0` meaning
+/// before the first line.
+pub fn parse_line(cmd: &str, pos: &Position) -> Result<usize, String> {
+    let mut parser = AddressParser {
+        pos,
+        pattern: None,
+        line: None,
+    };
+    let cursor = pos.cursor_y as isize + 1;
+    match parser.address(cmd.trim_start(), cursor)? {
+        (Some(line), _) if line >= 0 && line <= pos.editor_rows.nr_of_rows() as isize => {
+            Ok(line as usize)
+        }
+        _ => Err("Invalid address".into()),
+    }
+}
+
+/// Splits an ex command into its name and whatever follows it, the name being a run of letters
+/// or one of the single char commands.
+fn split_name(cmd: &str) -> (&str, &str) {
+    let cmd = cmd.trim_start();
+    let name_len = match cmd.chars().next() {
+        Some('&' | '!') => 1,
+        // user commands can have digits in them
+        Some(ch) if ch.is_ascii_uppercase() => cmd
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(cmd.len()),
+        _ => cmd
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(cmd.len()),
+    };
+    (&cmd[..name_len], &cmd[name_len..])
+}
+
+/// `name` is `full` shortened to no less than `min`, like `del` for `delete`
+fn is_abbrev(name: &str, min: &str, full: &str) -> bool {
+    name.starts_with(min) && full.starts_with(name)
+}
+
+/// the full name of the command `name` abbreviates
+pub fn full_name(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .find(|(min, full)| is_abbrev(name, min, full))
+        .map(|(_, full)| *full)
+}
+
+/// every command by its full name, in alphabetical order
+pub fn command_names() -> Vec<&'static str> {
+    let mut names: Vec<_> = COMMANDS
+        .iter()
+        .map(|(_, full)| *full)
+        .filter(|full| full.chars().all(|c| c.is_ascii_alphabetic()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// whether `name` is one of the commands defined with `:command`, which all start with an
+/// upper case letter, `:Next` being the one built in command that does too
+pub fn is_user_command(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_ascii_uppercase()) && full_name(name).is_none()
+}
+
+/// whether running `name` changes the buffer, `ranged` saying whether it was given a range
+pub fn changes_buffer(name: &str, ranged: bool) -> bool {
+    CHANGES.contains(&name) || (name == "!" && ranged)
+}
+
+/// Splits `text` at the first `|` that isn't escaped with a backslash.
+pub fn split_bar(text: &str) -> (&str, Option<&str>) {
+    let mut chars = text.char_indices();
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '|' => return (&text[..idx], Some(&text[idx + 1..])),
+            _ => {}
+        }
+    }
+    (text, None)
+}
+
+/// Parses the first command off `cmd`: `[range] name[!] [args] [| next command]`.
+pub fn parse<'c>(cmd: &'c str, pos: &Position) -> Result<Command<'c>, String> {
+    let cmd = cmd.trim_start_matches([' ', ':']);
+    let mut parser = AddressParser {
+        pos,
+        pattern: None,
+        line: None,
+    };
+    let (range, rest) = parser.range(cmd)?;
+
+    let (name, rest) = split_name(rest);
+    let not_a_command = || format!("Not an editor command: {}", cmd.trim_end());
+    let name = match name {
+        "" if !(rest.trim().is_empty() || rest.trim_start().starts_with('|')) => {
+            return Err(not_a_command())
+        }
+        "" => "",
+        // they get looked up when they run, whether they take a `!` or a `|` is up to them
+        name if is_user_command(name) => name,
+        name => full_name(name).ok_or_else(not_a_command)?,
+    };
+    let user = is_user_command(name);
+
+    let (bang, rest) = match rest.strip_prefix('!') {
+        Some(rest) if BANG.contains(&name) || user => (true, rest),
+        _ => (false, rest),
+    };
+    // and so do the shell commands of `:w !cmd` and `:r !cmd`
+    let shell_arg = ["read", "write"].contains(&name) && rest.trim_start().starts_with('!');
+    let (args, next) = if BAR_ARG.contains(&name) || shell_arg || user {
+        (rest, None)
+    } else {
+        split_bar(rest)
+    };
+    let args = args.trim_start();
+    if (name.is_empty() || NO_ARGS.contains(&name)) && !args.trim().is_empty() {
+        return Err(format!("Trailing characters: {}", args.trim()));
+    }
+
+    Ok(Command {
+        range,
+        name,
+        bang,
+        args,
+        pattern: parser.pattern,
+        line: parser.line,
+        next,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+
+    const LINES: [&str; 10] = [
+        "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    ];
+
+    /// `cmd` parsed in a buffer of `LINES` with the cursor on line 5 and mark `a` on line 3
+    fn parsed(cmd: &str) -> Result<Command<'_>, String> {
+        let mut editor_rows = EditorRows::new(Options::new());
+        editor_rows.replace_rows(0, 0, LINES.iter().map(|line| line.to_string()).collect());
+        editor_rows.set_mark('a', 0, 2);
+        let search = Search::new();
+        let pos = Position {
+            cursor_y: 4,
+            editor_rows: &editor_rows,
+            search: &search,
+        };
+        parse(cmd, &pos)
+    }
+
+    /// the range of `cmd` as 1 based lines
+    fn range(cmd: &str) -> Result<Option<(usize, usize)>, String> {
+        let command = parsed(cmd)?;
+        Ok(command.range.map(|range| (range.start + 1, range.end + 1)))
+    }
+
+    #[test]
+    fn addresses() {
+        for (cmd, lines) in [
+            ("", None),
+            (".", Some((5, 5))),
+            ("$", Some((10, 10))),
+            ("%", Some((1, 10))),
+            ("3", Some((3, 3))),
+            ("2,4", Some((2, 4))),
+            ("'a", Some((3, 3))),
+            ("'a,$", Some((3, 10))),
+            ("/eight/", Some((8, 8))),
+            ("/eight", Some((8, 8))),
+            ("?two?", Some((2, 2))),
+            // searches wrap around the end of the file
+            ("/four/", Some((4, 4))),
+            ("?six?", Some((6, 6))),
+            ("+2", Some((7, 7))),
+            ("-", Some((4, 4))),
+            ("+", Some((6, 6))),
+            (".+1,$-1", Some((6, 9))),
+            ("/six/+1", Some((7, 7))),
+            ("/t/,/t/", Some((8, 8))),
+            // `;` makes the next address count from the one before it
+            ("2;+1", Some((2, 3))),
+            ("2,+1", Some((2, 6))),
+            ("/two/;/t/", Some((2, 3))),
+            // the last two count
+            ("1,2,3", Some((2, 3))),
+            ("5,", Some((5, 5))),
+        ] {
+            assert_eq!(range(cmd), Ok(lines), "{}", cmd);
+        }
+    }
+
+    #[test]
+    fn bad_ranges() {
+        assert_eq!(range("4,2"), Err("Backwards range given".into()));
+        assert_eq!(range("$,1"), Err("Backwards range given".into()));
+        assert_eq!(range("11"), Err("Invalid range".into()));
+        assert_eq!(range("1,$+1"), Err("Invalid range".into()));
+        assert_eq!(range("'b"), Err("Mark not set".into()));
+        assert_eq!(range("/zero/"), Err("Pattern not found: zero".into()));
+        assert_eq!(range("//"), Err("No previous regular expression".into()));
+    }
+
+    #[test]
+    fn bars() {
+        let command = parsed("d | s/a/b/").unwrap();
+        assert_eq!((command.name, command.args), ("delete", ""));
+        assert_eq!(command.next, Some(" s/a/b/"));
+
+        // an escaped one stays in the argument
+        let command = parsed("s/a\\|b/c/").unwrap();
+        assert_eq!(command.args, "/a\\|b/c/");
+        assert_eq!(command.next, None);
+
+        // and `:g` keeps them all for the command it runs
+        let command = parsed("g/x/d | d").unwrap();
+        assert_eq!(command.args, "/x/d | d");
+        assert_eq!(command.next, None);
+    }
+
+    #[test]
+    fn names_and_arguments() {
+        let command = parsed("%s/a/b/g").unwrap();
+        assert_eq!((command.name, command.args), ("substitute", "/a/b/g"));
+        assert_eq!(command.range, Some(Range { start: 0, end: 9 }));
+
+        let command = parsed(":  3,4d").unwrap();
+        assert_eq!(command.name, "delete");
+        assert_eq!(command.range, Some(Range { start: 2, end: 3 }));
+
+        let command = parsed("/six/d").unwrap();
+        assert_eq!(command.pattern.as_deref(), Some("six"));
+
+        let command = parsed("w! name").unwrap();
+        assert!(command.bang);
+        assert_eq!(command.args, "name");
+
+        // only some commands take a `!`
+        assert!(!parsed("d!").unwrap().bang);
+
+        assert_eq!(parsed("3").unwrap().name, "");
+        assert_eq!(
+            parsed("undo x").err(),
+            Some("Trailing characters: x".into())
+        );
+        assert_eq!(
+            parsed("frobnicate").err(),
+            Some("Not an editor command: frobnicate".into())
+        );
+    }
+
+    #[test]
+    fn abbreviations() {
+        for (name, full) in [
+            ("s", "substitute"),
+            ("sub", "substitute"),
+            ("substitute", "substitute"),
+            ("se", "set"),
+            ("setl", "setlocal"),
+            ("so", "source"),
+            ("sor", "sort"),
+            ("d", "delete"),
+            ("del", "delete"),
+            ("m", "move"),
+            ("ma", "mark"),
+            ("co", "copy"),
+            ("t", "t"),
+            ("u", "undo"),
+            ("red", "redo"),
+            ("noh", "nohlsearch"),
+            ("e", "edit"),
+            ("r", "read"),
+            ("w", "write"),
+            ("wq", "wq"),
+            ("x", "xit"),
+            ("q", "quit"),
+            ("no", "noremap"),
+            ("norm", "normal"),
+            ("b", "buffer"),
+            ("bn", "bnext"),
+            ("n", "next"),
+            ("ne", "next"),
+            ("new", "new"),
+            ("cl", "clist"),
+            ("clo", "close"),
+            ("tabm", "tabmove"),
+        ] {
+            assert_eq!(full_name(name), Some(full), "{}", name);
+        }
+        // longer than the full name, or not short for anything
+        assert_eq!(full_name("substitutes"), None);
+        assert_eq!(full_name("frobnicate"), None);
+    }
+}
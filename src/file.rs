@@ -0,0 +1,475 @@
+use crate::{ex, Editor, EditorRows, Missing, DIRTY};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A file name as it was typed, spaces escaped with a backslash and `~` for the home directory.
+pub fn expand(arg: &str) -> PathBuf {
+    let name = arg.trim().replace("\\ ", " ");
+    let home = || PathBuf::from(env::var("HOME").unwrap_or_default());
+    match name.strip_prefix('~') {
+        Some("") => home(),
+        Some(rest) if rest.starts_with('/') => home().join(&rest[1..]),
+        _ => name.into(),
+    }
+}
+
+/// whether `word` has any of the wildcards `glob` knows
+pub fn has_wildcards(word: &str) -> bool {
+    word.contains(['*', '?', '['])
+}
+
+/// Whether the first thing in `pattern`, a `?`, a `[...]` set or a character, matches `ch`, and
+/// how much of the pattern it is.
+fn match_one(pattern: &[char], ch: char) -> (bool, usize) {
+    match pattern[0] {
+        '?' => (true, 1),
+        '[' => {
+            let negated = matches!(pattern.get(1), Some('!' | '^'));
+            let set = &pattern[1 + negated as usize..];
+            // a `]` right at the start is part of the set
+            let Some(end) = set
+                .iter()
+                .skip(1)
+                .position(|c| *c == ']')
+                .map(|end| end + 1)
+            else {
+                return (ch == '[', 1);
+            };
+            let mut found = false;
+            let mut idx = 0;
+            while idx < end {
+                if idx + 2 < end && set[idx + 1] == '-' {
+                    found |= (set[idx]..=set[idx + 2]).contains(&ch);
+                    idx += 3;
+                } else {
+                    found |= set[idx] == ch;
+                    idx += 1;
+                }
+            }
+            (found != negated, 1 + negated as usize + end + 1)
+        }
+        other => (other == ch, 1),
+    }
+}
+
+/// `name` matches `pattern` with `*` for any run of characters, `?` for any one and `[...]`
+/// for one of a set, `[!...]` or `[^...]` for one that's not in it.
+fn wildmatch(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // just after the last `*`, and where in `name` it stops for now
+    let mut star = None;
+    while n < name.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            star = Some((p, n));
+            continue;
+        }
+        if p < pattern.len() {
+            let (matched, len) = match_one(&pattern[p..], name[n]);
+            if matched {
+                p += len;
+                n += 1;
+                continue;
+            }
+        }
+        // the last `*` takes one more character and the rest starts over from after it, a
+        // `*` before that has nothing to gain from taking more
+        let Some((after, stop)) = star else {
+            return false;
+        };
+        star = Some((after, stop + 1));
+        (p, n) = (after, stop + 1);
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// The paths under `dir` that match the pattern `parts`, one part per path component. `**`
+/// goes through any number of directories.
+fn glob_in(dir: &Path, parts: &[&str], found: &mut Vec<PathBuf>) {
+    let Some((part, rest)) = parts.split_first() else {
+        found.push(dir.to_path_buf());
+        return;
+    };
+    if !has_wildcards(part) {
+        let path = dir.join(part);
+        if path.exists() && (rest.is_empty() || path.is_dir()) {
+            glob_in(&path, rest, found);
+        }
+        return;
+    }
+    let search_dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    let Ok(entries) = fs::read_dir(search_dir) else {
+        return;
+    };
+    // whether it's a directory, and whether it's one without following a link to get there
+    let mut entries: Vec<(String, bool, bool)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let real_dir = entry.file_type().ok()?.is_dir();
+            Some((name, real_dir || entry.path().is_dir(), real_dir))
+        })
+        // hidden files only when asked for
+        .filter(|(name, ..)| !name.starts_with('.') || part.starts_with('.'))
+        .collect();
+    entries.sort();
+
+    if *part == "**" {
+        glob_in(dir, rest, found);
+        // links are left alone, one pointing back up would go round forever
+        for (name, _, real_dir) in entries {
+            if real_dir {
+                glob_in(&dir.join(name), parts, found);
+            }
+        }
+        return;
+    }
+    let pattern: Vec<char> = part.chars().collect();
+    for (name, is_dir, _) in entries {
+        let chars: Vec<char> = name.chars().collect();
+        if (rest.is_empty() || is_dir) && wildmatch(&pattern, &chars) {
+            glob_in(&dir.join(name), rest, found);
+        }
+    }
+}
+
+/// The files `word` stands for, with `*`, `?`, `[...]` and `**` expanded, in order. A word
+/// without wildcards is taken as it is, whether the file is there or not.
+pub fn glob(word: &str) -> Vec<PathBuf> {
+    let path = expand(word);
+    let pattern = path.to_string_lossy();
+    if !has_wildcards(&pattern) {
+        return vec![path];
+    }
+    let (root, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (PathBuf::from("/"), rest),
+        None => (PathBuf::new(), pattern.as_ref()),
+    };
+    let parts: Vec<&str> = rest.split('/').filter(|part| !part.is_empty()).collect();
+    let mut found = Vec::new();
+    glob_in(&root, &parts, &mut found);
+    found.dedup();
+    found
+}
+
+impl Editor {
+    /// the file a command names, or the one being edited when it names none
+    fn file_arg(&mut self, args: &str) -> Option<PathBuf> {
+        let path = match args.trim() {
+            "" => self.output.editor_rows.file_name.clone(),
+            name => Some(expand(name)),
+        };
+        if path.is_none() {
+            self.output.status_message.set_error("No file name".into());
+        }
+        path
+    }
+
+    /// `:e[dit][!] [file]`, without a file it loads the current one again. Either way the `!`
+    /// throws away the changes. Another file gets a buffer of its own, `#` is the alternate one.
+    pub fn edit_file(&mut self, bang: bool, args: &str) {
+        let other =
+            !args.trim().is_empty() && self.file_arg(args) != self.output.editor_rows.file_name;
+        if bang && other {
+            self.discard_changes();
+        }
+        if args.trim() == "#" {
+            match self.buffers.alternate {
+                Some(nr) => {
+                    self.switch_buffer(nr, bang);
+                }
+                None => self
+                    .output
+                    .status_message
+                    .set_error("No alternate file".into()),
+            }
+            return;
+        }
+        if let Some(path) = self.file_arg(args) {
+            self.edit_path(bang, path);
+        }
+    }
+
+    /// Edits `path`, in its own buffer unless it's the current file. Gives back whether that
+    /// went through.
+    pub fn edit_path(&mut self, bang: bool, path: PathBuf) -> bool {
+        // a directory gets listed, under its full name so `.` and `src/..` are the same one
+        let path = match path.is_dir() {
+            true => path.canonicalize().unwrap_or(path),
+            false => path,
+        };
+        let current = self.output.editor_rows.file_name.as_ref() == Some(&path);
+        if current && self.output.dirty > 0 && !bang {
+            self.output
+                .status_message
+                .set_error(format!("{} (add ! to override)", DIRTY));
+            return false;
+        }
+        if !current {
+            if let Some(nr) = self.find_buffer(&path) {
+                return self.switch_buffer(nr, bang);
+            }
+        }
+
+        let (editor_rows, message) = self.open_path(path);
+        if current {
+            self.output.set_rows(editor_rows);
+        } else if !self.add_buffer(editor_rows, bang) {
+            return false;
+        }
+        self.skip_listing_header();
+        self.output.status_message.set_message(message);
+        true
+    }
+
+    /// The rows of `path` and what to say about it. A file that isn't there yet gets none
+    /// and a `[New]`, one that can't be read gets none either, with the error saying why.
+    pub fn open_path(&self, path: PathBuf) -> (EditorRows, String) {
+        match self.load_path(path.clone()) {
+            Ok(editor_rows) => {
+                let lines = editor_rows.nr_of_rows();
+                (editor_rows, format!("\"{}\" {}L", path.display(), lines))
+            }
+            Err(error) => {
+                let (missing, message) = match error.kind() {
+                    io::ErrorKind::NotFound => (Missing::New, "[New]".to_string()),
+                    _ => (Missing::Unread, error.to_string()),
+                };
+                let message = format!("\"{}\" {}", path.display(), message);
+                let options = self.output.options.clone();
+                let mut editor_rows = EditorRows::from_lines(path, Vec::new(), options);
+                editor_rows.missing = Some(missing);
+                (editor_rows, message)
+            }
+        }
+    }
+
+    /// Whether the directory `path` goes in is there, making it when it isn't and that's
+    /// what the answer to asking about it is.
+    pub fn has_directory(&mut self, path: &Path) -> bool {
+        let Some(dir) = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        else {
+            return true;
+        };
+        let question = format!("\"{}\" does not exist, create it?", dir.display());
+        if !matches!(self.confirm(&question), Ok(true)) {
+            self.output
+                .status_message
+                .set_error(format!("\"{}\" not written", path.display()));
+            return false;
+        }
+        match fs::create_dir_all(dir) {
+            Ok(()) => true,
+            Err(error) => {
+                self.output
+                    .status_message
+                    .set_error(format!("\"{}\" {}", dir.display(), error));
+                false
+            }
+        }
+    }
+
+    /// `:r[ead] [file]`, the file's lines go below line `line`, above the first one for `:0r`.
+    pub fn read_file(&mut self, line: usize, args: &str) {
+        let Some(path) = self.file_arg(args) else {
+            return;
+        };
+        match EditorRows::read_lines(&path) {
+            Ok(rows) => self.output.put_lines(line, rows),
+            Err(error) => {
+                self.output
+                    .status_message
+                    .set_error(format!("\"{}\" {}", path.display(), error))
+            }
+        }
+    }
+
+    /// `:[range]w[rite][!] [file]`, gives back whether anything got written. Writing to another
+    /// file doesn't switch to it, unless the buffer had no name yet.
+    pub fn write_file(&mut self, range: Option<ex::Range>, bang: bool, args: &str) -> bool {
+        let Some(path) = self.file_arg(args) else {
+            return false;
+        };
+        let editor_rows = &self.output.editor_rows;
+        let current = editor_rows.file_name.as_ref() == Some(&path);
+        let error = match (current, range) {
+            (false, _) if !bang && path.exists() => Some("File exists (add ! to override)"),
+            (true, Some(_)) if !bang => Some("Use ! to write partial buffer"),
+            (true, None) if !bang && editor_rows.missing == Some(Missing::Unread) => {
+                Some("File could not be read (add ! to override)")
+            }
+            _ => None,
+        };
+        if let Some(error) = error {
+            self.output.status_message.set_error(error.into());
+            return false;
+        }
+        if !self.has_directory(&path) {
+            return false;
+        }
+        let editor_rows = &self.output.editor_rows;
+
+        let rows = range.map_or(0..editor_rows.nr_of_rows(), |range| {
+            range.start..range.end + 1
+        });
+        let count = rows.len();
+        match editor_rows.write(&path, rows) {
+            Ok(len) => {
+                self.output.status_message.set_message(format!(
+                    "\"{}\" {}L, {}B written",
+                    path.display(),
+                    count,
+                    len
+                ));
+                let named = self.output.editor_rows.file_name.is_none();
+                if named {
+                    self.output.editor_rows.file_name = Some(path);
+                }
+                if (current || named) && range.is_none() {
+                    self.output.dirty = 0;
+                    self.output.editor_rows.missing = None;
+                }
+                true
+            }
+            Err(error) => {
+                self.output
+                    .status_message
+                    .set_error(format!("\"{}\" {}", path.display(), error));
+                false
+            }
+        }
+    }
+
+    /// `:sav[eas][!] {file}`, writes to `file` and carries on editing that one.
+    pub fn save_as(&mut self, bang: bool, args: &str) {
+        if args.trim().is_empty() {
+            self.output
+                .status_message
+                .set_error("Argument required".into());
+            return;
+        }
+        let path = expand(args);
+        let current = self.output.editor_rows.file_name.as_ref() == Some(&path);
+        if !current && !bang && path.exists() {
+            self.output
+                .status_message
+                .set_error("File exists (add ! to override)".into());
+            return;
+        }
+
+        let old = self.output.editor_rows.file_name.replace(path);
+        if !self.write_file(None, true, "") {
+            self.output.editor_rows.file_name = old;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        wildmatch(&pattern, &name)
+    }
+
+    #[test]
+    fn stars_and_question_marks() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("m*n*.rs", "main.rs"));
+        assert!(matches("?.txt", "a.txt"));
+        assert!(!matches("?.txt", "ab.txt"));
+        assert!(!matches("?", ""));
+        assert!(matches("*", ""));
+        assert!(matches("a**", "a"));
+        assert!(matches("*a*b", "xaaxb"));
+        assert!(!matches("*a*b", "xaaxbc"));
+    }
+
+    #[test]
+    fn many_stars_take_no_time() {
+        let name = "a".repeat(100);
+        assert!(!matches(&format!("{}b", "a*".repeat(30)), &name));
+        assert!(matches(&"a*".repeat(30), &name));
+    }
+
+    #[test]
+    fn sets() {
+        assert!(matches("[abc].rs", "b.rs"));
+        assert!(!matches("[abc].rs", "d.rs"));
+        assert!(matches("file[0-9]", "file7"));
+        assert!(!matches("file[0-9]", "filex"));
+        assert!(matches("[!a-c]x", "dx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[^a]", "b"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[-a]", "-"));
+        // one that's never closed is a plain `[`
+        assert!(matches("[a", "[a"));
+    }
+
+    /// a directory of its own under the temporary one, emptied
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("glob-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn every_directory() {
+        let dir = scratch("deep");
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        for file in [
+            "top.rs",
+            "a/one.rs",
+            "a/b/two.rs",
+            "a/b/notes.txt",
+            ".hidden.rs",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        // each directory's own files come before those further down
+        let expected: Vec<PathBuf> = ["top.rs", "a/one.rs", "a/b/two.rs"]
+            .iter()
+            .map(|file| dir.join(file))
+            .collect();
+        assert_eq!(glob(&format!("{}/**/*.rs", dir.display())), expected);
+        assert_eq!(
+            glob(&format!("{}/*/*.rs", dir.display())),
+            [dir.join("a/one.rs")]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_going_round() {
+        let dir = scratch("links");
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/file.rs"), "").unwrap();
+        std::os::unix::fs::symlink("..", dir.join("sub/up")).unwrap();
+
+        assert_eq!(
+            glob(&format!("{}/**/*.rs", dir.display())),
+            [dir.join("sub/file.rs")]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_wildcards() {
+        assert_eq!(glob("no/such/file"), [PathBuf::from("no/such/file")]);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::ex::Range;
+use crate::substitute::{plural, split_delimited};
+use crate::Editor;
+use std::io;
+
+impl Editor {
+    /// `:[range]g/pattern/command` marks the lines matching `pattern` first and then runs
+    /// `command` on each one still marked, so lines deleted along the way get skipped. It all
+    /// undoes as one change.
+    pub fn global(&mut self, range: Option<Range>, invert: bool, args: &str) -> io::Result<bool> {
+        if self.global_tally.is_some() {
+            self.output
+                .status_message
+                .set_error("Cannot do :global recursive".into());
+            return Ok(true);
+        }
+        let Some(delimiter) = args
+            .chars()
+            .next()
+            .filter(|ch| !(ch.is_alphanumeric() || "\\\"|".contains(*ch)))
+        else {
+            self.output
+                .status_message
+                .set_error("Regular expression missing from :global".into());
+            return Ok(true);
+        };
+        let (pattern, command) = split_delimited(&args[delimiter.len_utf8()..], delimiter);
+        let command = command.unwrap_or("").to_string();
+
+        let pattern = match (pattern.is_empty(), &self.output.search.pattern) {
+            (false, _) => pattern,
+            (true, Some(previous)) => previous.clone(),
+            (true, None) => {
+                self.output
+                    .status_message
+                    .set_error("No previous regular expression".into());
+                return Ok(true);
+            }
+        };
+        let regex = match self.output.search.regex(&pattern) {
+            Ok(regex) => regex,
+            Err(error) => {
+                self.output.status_message.set_error(error.to_string());
+                return Ok(true);
+            }
+        };
+        self.output.search.set_pattern(pattern.clone());
+
+        let editor_rows = &self.output.editor_rows;
+        let nr_of_rows = editor_rows.nr_of_rows();
+        if nr_of_rows == 0 {
+            return Ok(true);
+        }
+        let range = range.unwrap_or(Range {
+            start: 0,
+            end: nr_of_rows - 1,
+        });
+        let marked: Vec<usize> = (range.start..=range.end)
+            .filter(|&y| regex.find_at(editor_rows, y, 0).is_some() != invert)
+            .collect();
+        if marked.is_empty() {
+            self.output.status_message.set_error(if invert {
+                format!("Pattern found in every line: {}", pattern)
+            } else {
+                format!("Pattern not found: {}", pattern)
+            });
+            return Ok(true);
+        }
+        self.output.editor_rows.mark_rows(&marked);
+
+        let cursor = (
+            self.output.cursor_controller.cursor_x,
+            self.output.cursor_controller.cursor_y,
+        );
+        let began = self.output.editor_rows.history.begin(cursor);
+        self.global_tally = Some((0, 0));
+        let mut result = Ok(true);
+        while let Some(y) = self.output.editor_rows.take_marked() {
+            self.output.set_cursor(0, y);
+            result = self.parse_command(command.clone());
+            if !matches!(result, Ok(true)) {
+                break;
+            }
+        }
+        let tally = self.global_tally.take();
+        if began {
+            self.output.editor_rows.history.end();
+        }
+
+        if let Some((substitutions, lines)) = tally.filter(|(substitutions, _)| *substitutions > 0)
+        {
+            self.output.status_message.set_message(format!(
+                "{} on {}",
+                plural(substitutions, "substitution"),
+                plural(lines, "line")
+            ));
+        }
+        result
+    }
+}
@@ -1,26 +1,119 @@
+mod arglist;
+mod batch;
+mod browse;
+mod buffer;
+mod cmdline;
+mod complete;
+mod config;
+mod ex;
+mod file;
+mod global;
+mod mapping;
+mod options;
+mod quickfix;
+mod regex;
+mod search;
+mod shell;
+mod substitute;
+mod tab;
+mod text;
+mod undo;
+mod user_command;
+mod window;
+
 // use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 // use ratatui::{backend::CrosstermBackend, Terminal};
-use core::panic;
-use std::io::ErrorKind;
 use crossterm::{cursor, event, execute, queue, style, terminal};
 use crossterm::{event::*, terminal::ClearType};
 use std::time::{Duration, Instant};
-use std::{cmp, isize, usize};
+use regex::{Haystack, Pos};
+use arglist::ArgList;
+use browse::Browser;
+use buffer::Buffers;
+use cmdline::{Action, CmdHistory, CmdLine};
+use complete::Completion;
+use search::{Direction, Search};
+use mapping::Mappings;
+use options::Options;
+use quickfix::QuickFix;
+use tab::Tabs;
+use undo::History;
+use window::{Split, Windows};
+use user_command::UserCommand;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::{
     env,
     fs,
     io::{self, stdout, Write},
+    ops, process,
     // vec,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const NAME: &str = env!("CARGO_PKG_NAME");
-const TAB_STOP: usize = 4;
-const MSG_TTL: u64 = 1;
 const NO_FILE_NAME: &str = "[No Name]";
 const HELP_MSG: &str = "Ctrl + Q to Quit";
 const DIRTY: &str = "Unsaved changes!";
+const LISTING: &str = "Cannot make changes, buffer is a directory listing";
+const INSERT_MSG: &str = "-- INSERT --";
+const VISUAL_MSG: &str = "-- VISUAL --";
+const VISUAL_LINE_MSG: &str = "-- VISUAL LINE --";
+
+/// how a highlighted span of text gets drawn
+#[derive(Clone, Copy, PartialEq)]
+enum Highlight {
+    Search,        // every match of the last search
+    CurrentSearch, // the match under the cursor, and the one :s is asking about
+    Visual,
+}
+
+impl Highlight {
+    fn queue(&self, out: &mut EditorContents) {
+        match self {
+            Highlight::Search => queue!(
+                out,
+                style::SetBackgroundColor(style::Color::Yellow),
+                style::SetForegroundColor(style::Color::Black)
+            ),
+            Highlight::CurrentSearch => queue!(out, style::SetAttribute(style::Attribute::Reverse)),
+            Highlight::Visual => queue!(out, style::SetBackgroundColor(style::Color::DarkGrey)),
+        }
+        .unwrap();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+}
+
+/// what v2 got started with: `v2 [-u NONE | -u init_file] [file ...]`
+struct Args {
+    files: Vec<PathBuf>,
+    init: Option<String>, // `-u`, NONE skips the init file
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Self { files: Vec::new(), init: None };
+        let mut words = env::args().skip(1);
+        while let Some(word) = words.next() {
+            match word.as_str() {
+                "-u" => {
+                    let init = words.next().ok_or("Argument missing after: -u")?;
+                    args.init = Some(init);
+                }
+                _ => args.files.push(word.into()),
+            }
+        }
+        Ok(args)
+    }
+}
 
 struct CleanUp;
 
@@ -34,6 +127,9 @@ impl Drop for CleanUp {
 struct StatusMessage {
     message: Option<String>,
     set_time: Option<Instant>,
+    /// the last thing that went wrong, for whoever runs commands and wants to know, like
+    /// `:source`
+    error: Option<String>,
 }
 
 impl StatusMessage {
@@ -41,6 +137,7 @@ impl StatusMessage {
         Self {
             message: Some(initial_message),
             set_time: Some(Instant::now()),
+            error: None,
         }
     }
 
@@ -49,9 +146,22 @@ impl StatusMessage {
         self.set_time = Some(Instant::now())
     }
 
-    fn message(&mut self) -> Option<&String> {
+    /// shows `message` as saying something went wrong
+    fn set_error(&mut self, message: String) {
+        self.error = Some(message.clone());
+        self.set_message(message)
+    }
+
+    /// takes the message away, whatever its age
+    fn take(&mut self) -> Option<String> {
+        self.set_time = None;
+        self.message.take()
+    }
+
+    /// the message, if it's been there for less than `ttl` seconds
+    fn message(&mut self, ttl: u64) -> Option<&String> {
         self.set_time.and_then(|time| {
-            if time.elapsed() > Duration::from_secs(MSG_TTL) {
+            if time.elapsed() > Duration::from_secs(ttl) {
                 self.message = None;
                 self.set_time = None;
                 None
@@ -63,36 +173,83 @@ impl StatusMessage {
 }
 
 struct Output {
-    win_size: (usize, usize),
+    screen: (usize, usize), // the whole terminal
+    win_size: (usize, usize), // the text of the window being drawn, or else the current one
+    origin: (usize, usize), // where on the screen that window starts
     editor_contents: EditorContents,
     editor_rows: EditorRows,
     cursor_controller: CursorController,
     status_message: StatusMessage,
     line_nr_padding: usize,
     dirty: u8,
+    mode: Mode,
+    insert_step: bool, // entering insert mode opened an undo step, and not a :g or :normal around it
+    prompt: Option<(String, usize)>, // and the column of the cursor in it
+    search: Search,
+    highlight: Option<(Pos, Pos)>,
+    preview: Option<substitute::Preview>,
+    visual_start: (usize, usize), // the end of the visual selection the cursor isn't on
+    wildmenu: Option<(Vec<String>, Option<usize>)>, // completions of the `:` prompt and the one picked
+    options: Options, // the global values, buffer and window options get theirs from here
+    window_options: Options,
+    silent: bool, // the key being handled comes from a `<silent>` mapping, prompts stay hidden
 }
 
 impl Output {
     fn new() -> Self {
-        let win_size = terminal::size()
-            .map(|(x, y)| (x as usize, y as usize - 2))
+        let screen = terminal::size()
+            .map(|(x, y)| (x as usize, y as usize))
             .unwrap();
+        let win_size = (screen.0, screen.1 - 2);
         let mut out = Self {
+            screen,
             win_size,
+            origin: (0, 0),
             line_nr_padding: 0,
             editor_contents: EditorContents::new(),
-            editor_rows: EditorRows::new(),
+            editor_rows: EditorRows::new(Options::new()),
             cursor_controller: CursorController::new(win_size),
             status_message: StatusMessage::new(HELP_MSG.into()),
             dirty: 0,
+            mode: Mode::Normal,
+            insert_step: false,
+            prompt: None,
+            search: Search::new(),
+            highlight: None,
+            preview: None,
+            visual_start: (0, 0),
+            wildmenu: None,
+            options: Options::new(),
+            window_options: Options::new(),
+            silent: false,
         };
 
-        out.line_nr_padding =
-            out.editor_rows.nr_of_rows().checked_ilog10().unwrap_or(0) as usize + 2; // god I love rust
-
+        out.update_line_nr_padding();
         out
     }
 
+    fn update_line_nr_padding(&mut self) {
+        self.line_nr_padding =
+            self.editor_rows.nr_of_rows().checked_ilog10().unwrap_or(0) as usize + 2; // god I love rust
+    }
+
+    /// columns the line numbers take up, none with both 'number' and 'relativenumber' off
+    fn gutter_width(&self) -> usize {
+        if self.window_options.bool("number") || self.window_options.bool("relativenumber") {
+            self.line_nr_padding + 1
+        } else {
+            0
+        }
+    }
+
+    /// Shows another file from its top, for `:e`.
+    fn set_rows(&mut self, editor_rows: EditorRows) {
+        self.editor_rows = editor_rows;
+        self.cursor_controller = CursorController::new(self.win_size);
+        self.dirty = 0;
+        self.update_line_nr_padding();
+    }
+
     fn clear_screen() -> io::Result<()> {
         execute!(stdout(), terminal::Clear(ClearType::All))?;
         execute!(stdout(), cursor::MoveTo(0, 0))
@@ -103,20 +260,79 @@ impl Output {
             self.editor_rows.insert_row();
             self.dirty = 1;
         }
+        self.editor_rows.save_row(self.cursor_controller.cursor_y);
+        let tab_stop = self.editor_rows.tab_stop();
         self.editor_rows
             .get_editor_row_mut(self.cursor_controller.cursor_y)
-            .insert_char(self.cursor_controller.cursor_x, ch);
+            .insert_char(self.cursor_controller.cursor_x, ch, tab_stop);
         self.cursor_controller.cursor_x += 1;
         self.dirty = 1;
         // self.cursor_controller.prev_cursor_x = self.cursor_controller.cursor_x;
     }
 
-    fn draw_status_line(&mut self) {
+    /// The completions in place of the status line above the command line, scrolled so the
+    /// picked one is on screen.
+    fn draw_wildmenu(&mut self, items: &[String], selected: Option<usize>) {
+        const SEPARATOR: &str = "  ";
+        let width = |items: &[String]| -> usize {
+            items
+                .iter()
+                .map(|item| item.chars().count() + SEPARATOR.len())
+                .sum()
+        };
+        let mut first = 0;
+        if let Some(selected) = selected {
+            while first < selected && width(&items[first..=selected]) > self.screen.0 {
+                first += 1;
+            }
+        }
+
+        queue!(self.editor_contents, cursor::MoveTo(0, self.screen.1 as u16 - 2)).unwrap();
         self.editor_contents
             .push_str(&style::Attribute::Reverse.to_string());
+        let mut used = 0;
+        for (idx, item) in items.iter().enumerate().skip(first) {
+            let len = item.chars().count();
+            if used + len > self.screen.0 {
+                break;
+            }
+            if Some(idx) == selected {
+                Highlight::Search.queue(&mut self.editor_contents);
+                self.editor_contents.push_str(item);
+                queue!(
+                    self.editor_contents,
+                    style::SetAttribute(style::Attribute::Reset),
+                    style::SetAttribute(style::Attribute::Reverse)
+                )
+                .unwrap();
+            } else {
+                self.editor_contents.push_str(item);
+            }
+            used += len;
+            let gap = SEPARATOR.len().min(self.screen.0 - used);
+            self.editor_contents.push_str(&SEPARATOR[..gap]);
+            used += gap;
+        }
+        for _ in used..self.screen.0 {
+            self.editor_contents.push(' ');
+        }
+        self.editor_contents
+            .push_str(&style::Attribute::Reset.to_string());
+    }
+
+    /// The status line under the window, bold for the current one.
+    fn draw_status_line(&mut self, current: bool) {
+        let (x, y) = (self.origin.0, self.origin.1 + self.win_size.1);
+        queue!(self.editor_contents, cursor::MoveTo(x as u16, y as u16)).unwrap();
+        self.editor_contents
+            .push_str(&style::Attribute::Reverse.to_string());
+        if current {
+            self.editor_contents
+                .push_str(&style::Attribute::Bold.to_string());
+        }
 
         let status = format!(
-            "{}{} -- {} ",
+            "{}{}{} -- {} ",
             self.editor_rows
                 .file_name
                 .as_ref()
@@ -124,12 +340,26 @@ impl Output {
                 .and_then(|name| name.to_str())
                 .unwrap_or(NO_FILE_NAME),
             if self.dirty > 0 {"*"} else {""},
+            if self.editor_rows.missing == Some(Missing::New) {" [New]"} else {""},
             self.editor_rows.nr_of_rows()
         );
         let status_len = cmp::min(status.len(), self.win_size.0);
 
+        let search_count = self
+            .search
+            .count
+            .as_ref()
+            .filter(|_| current)
+            .filter(|count| {
+                (count.x, count.y)
+                    == (self.cursor_controller.cursor_x, self.cursor_controller.cursor_y)
+            })
+            .map(|count| count.status() + "  ")
+            .unwrap_or_default();
+
         let cursor_info = format!(
-            "{}:{}",
+            "{}{}:{}",
+            search_count,
             self.cursor_controller.cursor_y + 1,
             self.cursor_controller.cursor_x + 1
         );
@@ -145,31 +375,46 @@ impl Output {
 
         self.editor_contents
             .push_str(&style::Attribute::Reset.to_string());
-
-        self.editor_contents.push_str("\r\n");
     }
 
     fn draw_status_message(&mut self) {
         queue!(
             self.editor_contents,
+            cursor::MoveTo(0, self.screen.1 as u16 - 1),
             terminal::Clear(ClearType::UntilNewLine)
         )
         .unwrap();
 
-        if let Some(msg) = self.status_message.message() {
+        if let Some((prompt, cursor)) = self.prompt.as_ref().filter(|_| !self.silent) {
+            // scrolled sideways just enough to keep the cursor in view
+            let skip = cursor.saturating_sub(self.screen.0.saturating_sub(1));
+            let visible: String = prompt.chars().skip(skip).take(self.screen.0).collect();
+            self.editor_contents.push_str(&visible);
+        } else if let Some(msg) = self.status_message.message(self.options.number("messagetime") as u64) {
             self.editor_contents
-                .push_str(&msg[..cmp::min(self.win_size.0, msg.len())]);
+                .push_str(&msg[..cmp::min(self.screen.0, msg.len())]);
+        } else {
+            self.editor_contents.push_str(match self.mode {
+                Mode::Normal => "",
+                Mode::Insert => INSERT_MSG,
+                Mode::Visual => VISUAL_MSG,
+                Mode::VisualLine => VISUAL_LINE_MSG,
+            });
         }
     }
 
     fn draw_rows(&mut self) {
         let screen_rows = self.win_size.1;
         let screen_cols = self.win_size.0;
+        let matches = self.visible_matches();
 
         for i in 0..screen_rows {
             let file_row = i + self.cursor_controller.row_offset;
+            let (x, y) = (self.origin.0, self.origin.1 + i);
+            queue!(self.editor_contents, cursor::MoveTo(x as u16, y as u16)).unwrap();
+            let mut used;
 
-            if file_row >= self.editor_rows.nr_of_rows() {
+            if file_row >= self.nr_of_shown_rows() {
                 if self.editor_rows.nr_of_rows() == 0 && i == screen_rows / 3 {
                     let mut welcome = format!("{}! --- v{}", NAME.to_uppercase(), VERSION);
                     if welcome.len() > screen_cols {
@@ -182,24 +427,34 @@ impl Output {
                     }
                     (0..padding).for_each(|_| self.editor_contents.push(' '));
                     self.editor_contents.push_str(&welcome);
+                    used = (screen_cols - welcome.len()) / 2 + welcome.len();
                 } else {
                     self.editor_contents.push('~');
+                    used = 1;
                     // self.editor_contents.push_str(&((i + 1).to_string() + "  "));
                 }
             } else {
-                let row = self.editor_rows.get_render(file_row);
-                let col_offset = self.cursor_controller.col_offset;
                 let row_offset = self.cursor_controller.row_offset;
                 let line_nr = i + row_offset + 1;
                 let cursor_y = self.cursor_controller.cursor_y;
+                let number = self.window_options.bool("number");
+                let relative = self.window_options.bool("relativenumber");
 
-                if cursor_y == line_nr - 1 {
+                if !number && !relative {
+                    // no line numbers at all
+                } else if cursor_y == line_nr - 1 && number {
                     let line_nr_str = line_nr.to_string();
 
-                    let line_nr_formatted =
-                        format!("{:<pad$} ", line_nr_str, pad = self.line_nr_padding);
+                    let line_nr_formatted = if relative {
+                        format!("{:<pad$} ", line_nr_str, pad = self.line_nr_padding)
+                    } else {
+                        format!("{:>pad$} ", line_nr_str, pad = self.line_nr_padding)
+                    };
 
                     self.editor_contents.push_str(&(line_nr_formatted));
+                } else if !relative {
+                    let line_nr_formatted = format!("{:>pad$} ", line_nr, pad = self.line_nr_padding);
+                    self.editor_contents.push_str(&line_nr_formatted);
                 } else {
                     let rel_line_nr = (i + row_offset).abs_diff(cursor_y);
 
@@ -209,44 +464,250 @@ impl Output {
 
                     self.editor_contents.push_str(&(rel_line_nr_formatted)); // vim :set nornu basically
                 }
-                let len = if row.len() < col_offset {
-                    0
+                let highlights = self.highlights(file_row, &matches);
+                used = self.gutter_width();
+                used += self.draw_render(file_row, &highlights, screen_cols.saturating_sub(used));
+            }
+
+            // blanks over whatever was there, windows can sit next to each other
+            for _ in used..screen_cols {
+                self.editor_contents.push(' ');
+            }
+        }
+    }
+
+    /// every match of the highlighted search pattern that's on screen, for hlsearch and incsearch
+    fn visible_matches(&self) -> Vec<(Pos, Pos)> {
+        // the preview moves rows around, the matches would end up in the wrong place
+        if self.preview.is_some() {
+            return Vec::new();
+        }
+        let Some(regex) = self
+            .search
+            .highlighted()
+            .and_then(|pattern| self.search.regex(pattern).ok())
+        else {
+            return Vec::new();
+        };
+        let row_offset = self.cursor_controller.row_offset;
+        let last = cmp::min(row_offset + self.win_size.1, self.editor_rows.nr_of_rows());
+        // a match can start on the row above the screen and run onto it
+        (row_offset.saturating_sub(1)..last)
+            .flat_map(|y| regex.find_iter(&self.editor_rows, y))
+            .map(|m| (m.start(), m.end()))
+            .collect()
+    }
+
+    /// render columns of `file_row` to draw highlighted, end exclusive
+    fn highlights(&self, file_row: usize, matches: &[(Pos, Pos)]) -> Vec<(usize, usize, Highlight)> {
+        let row = self.shown_row(file_row);
+        if let Some(preview) = &self.preview {
+            if (preview.start..preview.start + preview.rows.len()).contains(&file_row) {
+                // deleted text has nothing left to highlight
+                return preview
+                    .spans
+                    .iter()
+                    .filter(|(start, end)| start != end)
+                    .filter_map(|&(start, end)| {
+                        self.span(row, file_row - preview.start, start, end, Highlight::CurrentSearch)
+                    })
+                    .collect();
+            }
+        }
+
+        let cursor = Pos::new(self.cursor_controller.cursor_y, self.cursor_controller.cursor_x);
+        let under_cursor = |&(start, end): &(Pos, Pos)| start == cursor || (start..end).contains(&cursor);
+        // the first span covering a column wins, so the current match goes first
+        self.highlight
+            .iter()
+            .map(|&(start, end)| (start, end, Highlight::CurrentSearch))
+            .chain(self.visual_selection().map(|(start, end)| (start, end, Highlight::Visual)))
+            .chain(matches.iter().map(|span| {
+                let highlight = if under_cursor(span) {
+                    Highlight::CurrentSearch
                 } else {
-                    let len = row.len() - col_offset;
-                    if len > screen_cols {
-                        screen_cols
-                    } else {
-                        len
-                    }
+                    Highlight::Search
                 };
+                (span.0, span.1, highlight)
+            }))
+            .filter_map(|(start, end, highlight)| self.span(row, file_row, start, end, highlight))
+            .collect()
+    }
+
+    /// the part of `row`, the `y`th one, between `start` and `end` in render columns
+    fn span(
+        &self,
+        row: &Row,
+        y: usize,
+        start: Pos,
+        end: Pos,
+        highlight: Highlight,
+    ) -> Option<(usize, usize, Highlight)> {
+        if y < start.line || y > end.line {
+            return None;
+        }
+        let from = if y == start.line {
+            row.render_x(start.col, self.editor_rows.tab_stop())
+        } else {
+            0
+        };
+        // a match running into the next row also covers the line break
+        let to = if y == end.line {
+            row.render_x(end.col, self.editor_rows.tab_stop())
+        } else {
+            row.render.chars().count() + 1
+        };
+        Some((from, cmp::max(to, from + 1), highlight))
+    }
 
-                let start = if len == 0 { 0 } else { col_offset };
+    /// how many rows get drawn, which the `:s` preview can change
+    fn nr_of_shown_rows(&self) -> usize {
+        match &self.preview {
+            Some(preview) => self.editor_rows.nr_of_rows() + preview.rows.len() - preview.old_len,
+            None => self.editor_rows.nr_of_rows(),
+        }
+    }
 
-                self.editor_contents.push_str(&(row[start..start + len]));
+    /// row `idx` as it gets drawn, from the preview for the rows it stands in for
+    fn shown_row(&self, idx: usize) -> &Row {
+        match &self.preview {
+            Some(preview) if idx >= preview.start => match preview.rows.get(idx - preview.start) {
+                Some(row) => row,
+                None => self
+                    .editor_rows
+                    .get_editor_row(idx - preview.rows.len() + preview.old_len),
+            },
+            _ => self.editor_rows.get_editor_row(idx),
+        }
+    }
+
+    /// `render` the way 'list' shows it, tabs, spaces and the line end drawn with what
+    /// 'listchars' has for them
+    fn list_render(&self, row: &Row) -> String {
+        let listchars = self.options.list("listchars");
+        let listchar = |name: &str| -> Vec<char> {
+            listchars
+                .iter()
+                .find_map(|item| item.strip_prefix(name)?.strip_prefix(':'))
+                .map_or(Vec::new(), |chars| chars.chars().collect())
+        };
+        let (tab, space, trail, eol) = (listchar("tab"), listchar("space"), listchar("trail"), listchar("eol"));
+        let tab_stop = self.editor_rows.tab_stop();
+        let trailing = row.row_content.trim_end_matches(' ').len();
+
+        let mut shown = String::with_capacity(row.render.len() + 1);
+        let mut col = 0;
+        for (idx, ch) in row.row_content.char_indices() {
+            match ch {
+                '\t' => {
+                    // same width as in `render`, the first char and then the second one
+                    let width = tab_stop - col % tab_stop;
+                    for i in 0..width {
+                        shown.push(match tab[..] {
+                            [first, ..] if i == 0 => first,
+                            [_, fill, ..] => fill,
+                            _ => ' ',
+                        });
+                    }
+                    col += width;
+                    continue;
+                }
+                ' ' if idx >= trailing => shown.push(*trail.first().or(space.first()).unwrap_or(&' ')),
+                ' ' => shown.push(*space.first().unwrap_or(&' ')),
+                ch => shown.push(ch),
             }
+            col += 1;
+        }
+        shown.extend(eol.first());
+        shown
+    }
 
-            queue!(
-                self.editor_contents,
-                terminal::Clear(ClearType::UntilNewLine)
-            )
-            .unwrap();
+    /// Draws `file_row` in no more than `screen_cols` columns, gives back how many it took.
+    fn draw_render(&mut self, file_row: usize, highlights: &[(usize, usize, Highlight)], screen_cols: usize) -> usize {
+        let col_offset = self.cursor_controller.col_offset;
+        let row = if self.window_options.bool("list") {
+            self.list_render(self.shown_row(file_row))
+        } else {
+            self.shown_row(file_row).render.clone()
+        };
+        let style_at = |col: usize| {
+            highlights
+                .iter()
+                .find(|(start, end, _)| (*start..*end).contains(&col))
+                .map(|(_, _, highlight)| *highlight)
+        };
+        // SGR 0 resets the colors along with the attributes
+        let reset =
+            |out: &mut EditorContents| queue!(out, style::SetAttribute(style::Attribute::Reset)).unwrap();
+
+        let mut current = None;
+        let mut col = col_offset;
+        for ch in row.chars().skip(col_offset).take(screen_cols) {
+            let style = style_at(col);
+            if style != current {
+                reset(&mut self.editor_contents);
+                if let Some(highlight) = style {
+                    highlight.queue(&mut self.editor_contents);
+                }
+                current = style;
+            }
+            self.editor_contents.push(ch);
+            col += 1;
+        }
 
-            // if i < screen_rows - 1 {
-            self.editor_contents.push_str("\r\n");
-            // }
+        // highlights past the last char (empty matches, line breaks) get a blank cell
+        if col - col_offset < screen_cols && col >= row.chars().count() {
+            if let Some(highlight) = style_at(col) {
+                if current != Some(highlight) {
+                    reset(&mut self.editor_contents);
+                    highlight.queue(&mut self.editor_contents);
+                }
+                self.editor_contents.push(' ');
+                current = Some(highlight);
+                col += 1;
+            }
         }
+        if current.is_some() {
+            reset(&mut self.editor_contents);
+        }
+        col - col_offset
     }
 
-    fn refresh_screen(&mut self) -> io::Result<()> {
+    /// Draws the window `origin` and `win_size` say where to, scrolled so its cursor is in view.
+    fn draw_window(&mut self, current: bool) {
+        self.cursor_controller.screen_rows = self.win_size.1;
+        self.cursor_controller.screen_cols = cmp::max(self.win_size.0.saturating_sub(self.gutter_width()), 1);
         self.cursor_controller.scroll(&self.editor_rows);
-        queue!(self.editor_contents, cursor::Hide, cursor::MoveTo(0, 0))?;
         self.draw_rows();
-        self.draw_status_line();
+        self.draw_status_line(current);
+    }
+
+    /// Draws what's left once the windows are: the lines between them, the command line and the
+    /// cursor, which is in the current window.
+    fn finish_screen(&mut self, separators: &[window::Rect]) -> io::Result<()> {
+        self.editor_contents.push_str(&style::Attribute::Reverse.to_string());
+        for separator in separators {
+            for y in separator.y..separator.y + separator.height {
+                queue!(self.editor_contents, cursor::MoveTo(separator.x as u16, y as u16))?;
+                self.editor_contents.push('|');
+            }
+        }
+        self.editor_contents.push_str(&style::Attribute::Reset.to_string());
+        if let Some((items, selected)) = self.wildmenu.clone() {
+            self.draw_wildmenu(&items, selected);
+        }
         self.draw_status_message();
-        let cursor_x = self.cursor_controller.render_x - self.cursor_controller.col_offset
-            + self.line_nr_padding
-            + 1;
-        let cursor_y = self.cursor_controller.cursor_y - self.cursor_controller.row_offset;
+        let (cursor_x, cursor_y) = match self.prompt.as_ref().filter(|_| !self.silent) {
+            Some((_, cursor)) => (
+                cmp::min(*cursor, self.screen.0.saturating_sub(1)),
+                self.screen.1 - 1,
+            ),
+            None => (
+                self.origin.0 + self.cursor_controller.render_x - self.cursor_controller.col_offset
+                    + self.gutter_width(),
+                self.origin.1 + self.cursor_controller.cursor_y - self.cursor_controller.row_offset,
+            ),
+        };
         queue!(
             self.editor_contents,
             cursor::MoveTo(cursor_x as u16, cursor_y as u16),
@@ -264,8 +725,237 @@ impl Output {
         self.cursor_controller
             .jump_cursor(direction, &self.win_size, &self.editor_rows);
     }
+
+    fn undo(&mut self, redo: bool) {
+        let cursor = if redo {
+            self.editor_rows.redo()
+        } else {
+            self.editor_rows.undo()
+        };
+        match cursor {
+            Some((x, y)) => {
+                self.set_cursor(x, y);
+                self.dirty = 1;
+            }
+            None => self.status_message.set_message(if redo {
+                "Already at newest change".into()
+            } else {
+                "Already at oldest change".into()
+            }),
+        }
+    }
+
+    /// moves the cursor, keeping it inside the file
+    fn set_cursor(&mut self, x: usize, y: usize) {
+        let nr_of_rows = self.editor_rows.nr_of_rows();
+        let y = cmp::min(y, nr_of_rows.saturating_sub(1));
+        let row_len = if y < nr_of_rows {
+            self.editor_rows.get_editor_row(y).row_content.len()
+        } else {
+            0
+        };
+        self.cursor_controller.cursor_y = y;
+        self.cursor_controller.cursor_x = cmp::min(x, row_len);
+        self.cursor_controller.prev_cursor_x = self.cursor_controller.cursor_x;
+    }
+
+    /// Keeps the cursor inside the file after the rows changed under it, from another window
+    /// showing the same buffer say. The column it's trying to get back to stays the same.
+    fn clamp_cursor(&mut self) {
+        let prev_cursor_x = self.cursor_controller.prev_cursor_x;
+        let cursor = &self.cursor_controller;
+        self.set_cursor(cursor.cursor_x, cursor.cursor_y);
+        self.cursor_controller.prev_cursor_x = prev_cursor_x;
+    }
+
+    /// Where the visual selection starts and ends, end exclusive. A line wise one covers the
+    /// rows from start to end.
+    fn visual_selection(&self) -> Option<(Pos, Pos)> {
+        let cursor = (self.cursor_controller.cursor_x, self.cursor_controller.cursor_y);
+        let (start, end) = (cmp::min(self.visual_start, cursor), cmp::max(self.visual_start, cursor));
+        let (start, end) = (Pos::new(start.1, start.0), Pos::new(end.1, end.0));
+        let row = |y: usize| self.editor_rows.row_contents.get(y).map_or("", |row| row.row_content.as_str());
+        match self.mode {
+            Mode::Visual => {
+                let end_row = row(end.line);
+                let end_col = end_row[cmp::min(end.col, end_row.len())..]
+                    .chars()
+                    .next()
+                    .map_or(end_row.len(), |ch| end.col + ch.len_utf8());
+                Some((start, Pos::new(end.line, end_col)))
+            }
+            Mode::VisualLine => Some((Pos::new(start.line, 0), Pos::new(end.line, row(end.line).len()))),
+            _ => None,
+        }
+    }
+
+    /// back to normal mode, leaving the '< and '> marks on the selection
+    fn end_visual(&mut self) {
+        if let Some((start, end)) = self.visual_selection() {
+            self.editor_rows.set_mark('<', start.col, start.line);
+            self.editor_rows.set_mark('>', end.col.saturating_sub(1), end.line);
+        }
+        self.mode = Mode::Normal;
+    }
+
+    /// runs `edit` as a single undo step, which puts the cursor back where it is now
+    fn edit<T>(&mut self, edit: impl FnOnce(&mut Self) -> T) -> T {
+        let cursor = (self.cursor_controller.cursor_x, self.cursor_controller.cursor_y);
+        let began = self.editor_rows.history.begin(cursor);
+        let result = edit(self);
+        if began {
+            self.editor_rows.history.end();
+        }
+        self.dirty = 1;
+        result
+    }
+
+    fn set_cursor_first_non_blank(&mut self, y: usize) {
+        let x = self
+            .editor_rows
+            .row_contents
+            .get(y)
+            .and_then(|row| row.row_content.find(|c: char| !c.is_whitespace()))
+            .unwrap_or(0);
+        self.set_cursor(x, y);
+    }
+
+    /// The paragraph line `y` is in for `ip`, a run of blank lines counting as one. `ap` also
+    /// takes the blank lines after it, or before it when it's the last one.
+    fn paragraph(&self, y: usize, around: bool) -> ex::Range {
+        let rows = &self.editor_rows.row_contents;
+        let blank = |y: usize| rows[y].row_content.trim().is_empty();
+        let run = |y: usize| {
+            let kind = blank(y);
+            let start = (0..y).rev().take_while(|&y| blank(y) == kind).last().unwrap_or(y);
+            let end = (y + 1..rows.len()).take_while(|&y| blank(y) == kind).last().unwrap_or(y);
+            ex::Range { start, end }
+        };
+
+        let mut range = run(y);
+        if around {
+            if range.end + 1 < rows.len() {
+                range.end = run(range.end + 1).end;
+            } else if range.start > 0 {
+                range.start = run(range.start - 1).start;
+            }
+        }
+        range
+    }
+
+    fn row_contents(&self, range: ex::Range) -> Vec<String> {
+        (range.start..=range.end)
+            .map(|y| self.editor_rows.get_editor_row(y).row_content.clone())
+            .collect()
+    }
+
+    /// `:d`, the cursor ends up on the line after the deleted ones
+    fn delete_lines(&mut self, range: ex::Range) {
+        let count = range.end - range.start + 1;
+        // like vim a file never goes without a line
+        let rows = if count == self.editor_rows.nr_of_rows() {
+            vec![String::new()]
+        } else {
+            Vec::new()
+        };
+        self.edit(|output| output.editor_rows.replace_rows(range.start, count, rows));
+        self.set_cursor_first_non_blank(range.start);
+    }
+
+    /// `:r`, puts `rows` below line `line` (1 based, 0 being above the first one) and the cursor
+    /// on the first of them
+    fn put_lines(&mut self, line: usize, rows: Vec<String>) {
+        if rows.is_empty() {
+            return;
+        }
+        let dest = cmp::min(line, self.editor_rows.nr_of_rows());
+        self.edit(|output| output.editor_rows.replace_rows(dest, 0, rows));
+        self.set_cursor_first_non_blank(dest);
+    }
+
+    /// `:m`, puts the lines below line `dest` (1 based, 0 being above the first one)
+    fn move_lines(&mut self, range: ex::Range, dest: usize) -> Result<(), String> {
+        if dest > range.start && dest <= range.end {
+            return Err("Move lines into themselves".into());
+        }
+        let count = range.end - range.start + 1;
+        let at = if dest > range.end { dest - count } else { dest };
+        self.edit(|output| output.editor_rows.move_rows(range, at));
+        self.set_cursor_first_non_blank(at + count - 1);
+        Ok(())
+    }
+
+    /// `:t` and `:co`, copies the lines below line `dest`
+    fn copy_lines(&mut self, range: ex::Range, dest: usize) {
+        let rows = self.row_contents(range);
+        let count = rows.len();
+        self.edit(|output| output.editor_rows.replace_rows(dest, 0, rows));
+        self.set_cursor_first_non_blank(dest + count - 1);
+    }
+
+    /// `*` and `#` when `whole_word`, `g*` and `g#` otherwise
+    fn search_word(&mut self, direction: Direction, whole_word: bool) {
+        let (cursor_x, cursor_y) = (self.cursor_controller.cursor_x, self.cursor_controller.cursor_y);
+        let Some(row) = self.editor_rows.row_contents.get(cursor_y) else {
+            return;
+        };
+        let row = &row.row_content;
+        let Some((start, word, keyword)) = search::word_under(row, cmp::min(cursor_x, row.len())) else {
+            self.status_message.set_error("No string under cursor".into());
+            return;
+        };
+
+        let escaped = search::escape(word);
+        self.search.set_pattern(if whole_word && keyword {
+            format!("\\<{}\\>", escaped)
+        } else {
+            escaped
+        });
+        self.search.direction = direction;
+        // from the start of the word, so `#` doesn't just find the word the cursor is on
+        self.cursor_controller.cursor_x = start;
+        self.search_next(direction);
+    }
+
+    fn search_next(&mut self, direction: Direction) {
+        let Some(pattern) = self.search.pattern.clone() else {
+            self.status_message
+                .set_error("No previous search pattern".into());
+            return;
+        };
+        let regex = match self.search.regex(&pattern) {
+            Ok(regex) => regex,
+            Err(error) => {
+                self.status_message.set_error(error.to_string());
+                return;
+            }
+        };
+        let cursor = (self.cursor_controller.cursor_x, self.cursor_controller.cursor_y);
+        self.search.no_highlight = false;
+
+        match self.search.find(&self.editor_rows, &regex, cursor, direction) {
+            Some(hit) => {
+                self.cursor_controller.cursor_x = hit.x;
+                self.cursor_controller.cursor_y = hit.y;
+                self.cursor_controller.prev_cursor_x = hit.x;
+
+                if hit.wrapped {
+                    self.status_message.set_message(match direction {
+                        Direction::Forward => "search hit BOTTOM, continuing at TOP".into(),
+                        Direction::Backward => "search hit TOP, continuing at BOTTOM".into(),
+                    });
+                }
+                self.search.count =
+                    Some(self.search.count_matches(&self.editor_rows, &regex, (hit.x, hit.y)));
+            }
+            None => self
+                .status_message
+                .set_error(format!("Pattern not found: {}", pattern)),
+        }
+    }
 }
 
+#[derive(Clone)]
 struct CursorController {
     cursor_x: usize,
     cursor_y: usize,
@@ -293,23 +983,15 @@ impl CursorController {
         }
     }
 
-    fn get_render_x(&self, row: &Row) -> usize {
-        row.row_content[..self.cursor_x]
-            .chars()
-            .fold(0, |render_x, c| {
-                if c == '\t' {
-                    render_x + (TAB_STOP - 1) - (render_x % TAB_STOP) + 1
-                } else {
-                    render_x + 1
-                }
-            })
+    fn get_render_x(&self, row: &Row, tab_stop: usize) -> usize {
+        row.render_x(self.cursor_x, tab_stop)
     }
 
     fn scroll(&mut self, editor_rows: &EditorRows) {
         self.render_x = 0;
 
         if self.cursor_y < editor_rows.nr_of_rows() {
-            self.render_x = self.get_render_x(editor_rows.get_editor_row(self.cursor_y))
+            self.render_x = self.get_render_x(editor_rows.get_editor_row(self.cursor_y), editor_rows.tab_stop())
         }
 
         self.row_offset = cmp::min(self.row_offset, self.cursor_y);
@@ -477,6 +1159,16 @@ impl Reader {
             }
         }
     }
+
+    /// a key if one comes within `timeout`
+    fn poll_key(&self, timeout: Duration) -> io::Result<Option<KeyEvent>> {
+        if event::poll(timeout)? {
+            if let Event::Key(event) = event::read()? {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
 }
 
 struct EditorContents {
@@ -522,6 +1214,7 @@ impl io::Write for EditorContents {
 struct Row {
     row_content: String,
     render: String,
+    marked: bool, // for :g
 }
 
 impl Row {
@@ -529,67 +1222,123 @@ impl Row {
         Self {
             row_content,
             render,
+            marked: false,
         }
     }
 
-    fn insert_char(&mut self, idx: usize, ch: char) {
+    fn insert_char(&mut self, idx: usize, ch: char, tab_stop: usize) {
         self.row_content.insert(idx, ch);
-        EditorRows::render_row(self)
+        EditorRows::render_row(self, tab_stop)
+    }
+
+    /// column in `render` of the byte `idx` of `row_content`, tabs being the difference
+    fn render_x(&self, idx: usize, tab_stop: usize) -> usize {
+        // a cursor another window left behind can be past the end, or inside a character
+        let mut idx = idx.min(self.row_content.len());
+        while !self.row_content.is_char_boundary(idx) {
+            idx -= 1;
+        }
+        self.row_content[..idx]
+            .chars()
+            .fold(0, |render_x, c| {
+                if c == '\t' {
+                    render_x + (tab_stop - 1) - (render_x % tab_stop) + 1
+                } else {
+                    render_x + 1
+                }
+            })
     }
 }
 
+/// Why a buffer's file wasn't read when it was opened.
+#[derive(Clone, Copy, PartialEq)]
+enum Missing {
+    /// there's no such file yet, the first write makes it
+    New,
+    /// it couldn't be read, so writing over it takes a `!`
+    Unread,
+}
+
 struct EditorRows {
     row_contents: Vec<Row>,
     file_name: Option<PathBuf>,
+    missing: Option<Missing>,
+    listing: bool, // a directory listing, which can't be changed
+    history: History,
+    lowest_marked: usize, // no row before this one is marked
+    marks: HashMap<char, (usize, usize)>,
+    options: Options, // the buffer local ones
+    mappings: Mappings, // the `<buffer>` ones
 }
 
 impl EditorRows {
-    fn new() -> Self {
-        match env::args().nth(1) {
-            None => Self {
-                row_contents: Vec::new(),
-                file_name: None,
-            },
-            Some(file) => Self::from_file(file.into()),
+    fn new(options: Options) -> Self {
+        Self {
+            row_contents: Vec::new(),
+            file_name: None,
+            missing: None,
+            listing: false,
+            history: History::default(),
+            lowest_marked: 0,
+            marks: HashMap::new(),
+            options,
+            mappings: Mappings::default(),
         }
     }
 
-    fn save(&self) -> io::Result<usize> {
-        match &self.file_name {
-            None => Err(io::Error::new(ErrorKind::Other, "no file name!")),
-            Some(name) => {
-                let mut file = fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .open(name)?;
-                let contents: String = self
-                    .row_contents
-                    .iter()
-                    .map(|it| it.row_content.as_str())
-                    .collect::<Vec<&str>>()
-                    .join("\n");
-                file.set_len(contents.len() as u64)?;
-                file.write_all(contents.as_bytes())?;
-                Ok(contents.as_bytes().len())
-            }
-        }
+    /// Writes `rows` to `path`, giving back how many bytes that was.
+    fn write(&self, path: &Path, rows: ops::Range<usize>) -> io::Result<usize> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let contents: String = self.row_contents[rows]
+            .iter()
+            .map(|it| it.row_content.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n");
+        file.set_len(contents.len() as u64)?;
+        file.write_all(contents.as_bytes())?;
+        Ok(contents.len())
+    }
+
+    fn read_lines(file: &Path) -> io::Result<Vec<String>> {
+        let file_contents = fs::read_to_string(file)?;
+        Ok(file_contents.lines().map(String::from).collect())
     }
 
-    fn from_file(file: PathBuf) -> Self {
-        let file_contents = fs::read_to_string(&file).expect("Failed to read file");
+    fn from_file(file: PathBuf, options: Options) -> io::Result<Self> {
+        let lines = Self::read_lines(&file)?;
+        Ok(Self::from_lines(file, lines, options))
+    }
+
+    /// rows for `file` holding `lines`, which needn't be what's in it
+    fn from_lines(file: PathBuf, lines: Vec<String>, options: Options) -> Self {
+        let tab_stop = options.number("tabstop");
         Self {
             file_name: Some(file),
-            row_contents: file_contents
-                .lines()
-                .map(|it| {
-                    let mut row = Row::new(it.into(), String::new());
-                    Self::render_row(&mut row);
-                    row
-                })
-                .collect(),
+            missing: None,
+            listing: false,
+            history: History::default(),
+            lowest_marked: 0,
+            marks: HashMap::new(),
+            options,
+            mappings: Mappings::default(),
+            row_contents: lines.into_iter().map(|line| Self::make_row(line, tab_stop)).collect(),
         }
     }
 
+    fn tab_stop(&self) -> usize {
+        self.options.number("tabstop")
+    }
+
+    /// renders every row again, after the tab stop changed
+    fn render_all(&mut self) {
+        let tab_stop = self.tab_stop();
+        self.row_contents.iter_mut().for_each(|row| Self::render_row(row, tab_stop));
+    }
+
     fn get_render(&self, idx: usize) -> &String {
         &self.row_contents[idx].render
     }
@@ -607,26 +1356,167 @@ impl EditorRows {
     }
 
     fn insert_row(&mut self) {
+        self.history
+            .record(self.row_contents.len(), Vec::new(), 1);
         self.row_contents.push(Row::default());
     }
 
+    fn make_row(content: String, tab_stop: usize) -> Row {
+        let mut row = Row::new(content, String::new());
+        Self::render_row(&mut row, tab_stop);
+        row
+    }
+
+    /// remembers the row as it is for undo, call before editing it in place
+    fn save_row(&mut self, idx: usize) {
+        let content = self.row_contents[idx].row_content.clone();
+        self.history.record(idx, vec![content], 1);
+    }
+
+    /// replaces `count` rows from `start` on with `rows`
+    fn replace_rows(&mut self, start: usize, count: usize, rows: Vec<String>) {
+        let new_len = rows.len();
+        let tab_stop = self.tab_stop();
+        let old = self
+            .row_contents
+            .splice(start..start + count, rows.into_iter().map(|row| Self::make_row(row, tab_stop)))
+            .map(|row| row.row_content)
+            .collect();
+        self.history.record(start, old, new_len);
+        // rows past `start` may have moved up to it, but none before it
+        self.lowest_marked = cmp::min(self.lowest_marked, start);
+        Self::move_marks(&mut self.marks, start, count, new_len);
+    }
+
+    /// marks move along with their rows when `count` rows from `start` on become `new_len`
+    /// others, and go away with them
+    fn move_marks(marks: &mut HashMap<char, (usize, usize)>, start: usize, count: usize, new_len: usize) {
+        marks.retain(|_, (_, y)| {
+            if *y >= start + count {
+                *y = *y + new_len - count;
+                true
+            } else {
+                *y < start + new_len
+            }
+        });
+    }
+
+    /// Moves the rows in `range` to `at`, counted once they're out of the way. Rows marked for
+    /// :g stay marked wherever they end up, and :g goes on from where it was.
+    fn move_rows(&mut self, range: ex::Range, at: usize) {
+        let count = range.end - range.start + 1;
+        let moved = &self.row_contents[range.start..=range.end];
+        let rows = moved.iter().map(|row| row.row_content.clone()).collect();
+        let marked: Vec<bool> = moved.iter().map(|row| row.marked).collect();
+        let lowest_marked = self.lowest_marked;
+        self.replace_rows(range.start, count, Vec::new());
+        self.replace_rows(at, 0, rows);
+
+        // where a row that wasn't moved ended up
+        let moved_to = |y: usize| {
+            let y = if y > range.end { y - count } else { y };
+            if y >= at { y + count } else { y }
+        };
+        for (idx, marked) in marked.iter().enumerate() {
+            self.row_contents[at + idx].marked = *marked;
+        }
+        // the rows that weren't moved kept their order, so none before it is marked still
+        let first = match lowest_marked >= range.start && lowest_marked <= range.end {
+            true => range.end + 1,
+            false => lowest_marked,
+        };
+        let lowest_marked = match first < self.nr_of_rows() {
+            true => moved_to(first),
+            false => self.nr_of_rows(),
+        };
+        self.lowest_marked = match marked.contains(&true) {
+            true => cmp::min(at, lowest_marked),
+            false => lowest_marked,
+        };
+    }
+
+    /// `a` to `z`, and `<` `>` for the last visual selection. Returns false for any other mark.
+    fn set_mark(&mut self, mark: char, x: usize, y: usize) -> bool {
+        if !(mark.is_ascii_lowercase() || mark == '<' || mark == '>') {
+            return false;
+        }
+        self.marks.insert(mark, (x, y));
+        true
+    }
+
+    fn mark(&self, mark: char) -> Option<(usize, usize)> {
+        self.marks
+            .get(&mark)
+            .copied()
+            .filter(|(_, y)| *y < self.nr_of_rows())
+    }
+
+    /// marks `rows` (in order) for :g, unmarking all the others
+    fn mark_rows(&mut self, rows: &[usize]) {
+        self.row_contents.iter_mut().for_each(|row| row.marked = false);
+        rows.iter().for_each(|&idx| self.row_contents[idx].marked = true);
+        self.lowest_marked = rows.first().copied().unwrap_or(0);
+    }
+
+    /// unmarks the first marked row and gives it back
+    fn take_marked(&mut self) -> Option<usize> {
+        let idx = self.row_contents[self.lowest_marked.min(self.row_contents.len())..]
+            .iter()
+            .position(|row| row.marked)?
+            + self.lowest_marked;
+        self.row_contents[idx].marked = false;
+        self.lowest_marked = idx + 1;
+        Some(idx)
+    }
+
+    /// Returns where the cursor should go, `None` when there's nothing to undo.
+    fn undo(&mut self) -> Option<(usize, usize)> {
+        let step = self.history.pop_undo()?;
+        let tab_stop = self.tab_stop();
+        let redo = step.apply(
+            &mut self.row_contents,
+            |row| row.row_content.clone(),
+            |content| Self::make_row(content, tab_stop),
+            |start, count, new_len| Self::move_marks(&mut self.marks, start, count, new_len),
+        );
+        let cursor = redo.cursor();
+        self.history.push_redo(redo);
+        self.lowest_marked = 0;
+        Some(cursor)
+    }
+
+    fn redo(&mut self) -> Option<(usize, usize)> {
+        let step = self.history.pop_redo()?;
+        let first_row = step.first_row();
+        let tab_stop = self.tab_stop();
+        let undo = step.apply(
+            &mut self.row_contents,
+            |row| row.row_content.clone(),
+            |content| Self::make_row(content, tab_stop),
+            |start, count, new_len| Self::move_marks(&mut self.marks, start, count, new_len),
+        );
+        self.history.push_undo(undo);
+        self.lowest_marked = 0;
+        Some((0, first_row))
+    }
+
     // fn get_row(&self, idx:usize ) -> &str {
     //     &self.row_contents[idx]
     // }
 
-    fn render_row(row: &mut Row) {
+    fn render_row(row: &mut Row, tab_stop: usize) {
         let mut idx = 0;
         let cap = row
             .row_content
             .chars()
-            .fold(0, |acc, next| acc + if next == 't' { TAB_STOP } else { 1 });
+            .fold(0, |acc, next| acc + if next == 't' { tab_stop } else { 1 });
 
         row.render = String::with_capacity(cap);
         row.row_content.chars().for_each(|c| {
             idx += 1;
             if c == '\t' {
                 row.render.push(' ');
-                while idx % TAB_STOP != 0 {
+                while idx % tab_stop != 0 {
                     row.render.push(' ');
                     idx += 1
                 }
@@ -637,10 +1527,30 @@ impl EditorRows {
     }
 }
 
+impl Haystack for EditorRows {
+    fn line(&self, idx: usize) -> Option<&str> {
+        self.row_contents.get(idx).map(|row| row.row_content.as_str())
+    }
+}
+
 struct Editor {
     reader: Reader,
     output: Output,
-    command: String,
+    command_history: CmdHistory,
+    search_history: CmdHistory,
+    global_tally: Option<(usize, usize)>, // substitutions and lines while :g runs, reported at the end
+    source_depth: usize, // how many :source are running
+    mappings: Mappings,
+    typeahead: VecDeque<mapping::Typed>, // keys yet to be handled, mostly from mappings
+    user_commands: BTreeMap<String, UserCommand>, // by name, which is the order `:command` lists them in
+    command_depth: usize, // how many user commands are running
+    normal_depth: usize, // how many :normal are running, keys come from them instead of the terminal
+    buffers: Buffers,
+    arglist: ArgList,
+    quickfix: QuickFix,
+    windows: Windows,
+    tabs: Tabs,
+    browser: Browser,
 }
 
 impl Editor {
@@ -648,98 +1558,506 @@ impl Editor {
         Self {
             reader: Reader,
             output: Output::new(),
-            command: String::new(),
+            command_history: CmdHistory::default(),
+            search_history: CmdHistory::default(),
+            global_tally: None,
+            source_depth: 0,
+            mappings: Mappings::default(),
+            typeahead: VecDeque::new(),
+            user_commands: BTreeMap::new(),
+            command_depth: 0,
+            normal_depth: 0,
+            buffers: Buffers::new(),
+            arglist: ArgList::default(),
+            quickfix: QuickFix::default(),
+            windows: Windows::new(),
+            tabs: Tabs::new(),
+            browser: Browser::default(),
         }
     }
 
-    fn save_file(&mut self) -> io::Result<bool> {
-        match self.output.editor_rows.save() {
-            Ok(len) => {
-                self.output
-                    .status_message
-                    .set_message(format!("{}B written", len));
-                self.output.dirty = 0;
-                Ok(true)
+    /// Runs the init file before opening the file, so the file gets the options it sets.
+    fn start(&mut self, args: Args) -> io::Result<bool> {
+        let init = match args.init.as_deref() {
+            Some("NONE") => None,
+            Some(path) => Some((file::expand(path), true)),
+            None => config::init_file().map(|path| (path, false)),
+        };
+        if let Some((path, required)) = init {
+            if !self.source(&path, required)? {
+                return Ok(false);
             }
-            Err(error) => {
-                self.output
-                    .status_message
-                    .set_message(format!("Something went wrong :("));
-                Err(error)
+        }
+
+        if let Some(file) = args.files.first() {
+            let (editor_rows, message) = self.open_path(file.clone());
+            // a file that's there just gets shown, without a word about it
+            if editor_rows.missing.is_some() {
+                self.output.status_message.set_message(message);
             }
+            self.output.set_rows(editor_rows);
+            self.skip_listing_header();
         }
+        self.arglist = ArgList::new(args.files);
+        Ok(true)
     }
 
-    fn quit(&mut self) -> io::Result<bool> {
+    /// `:q[uit][!]`, which only closes the window when there's more than one, and the tab
+    /// page when there's more than one of those
+    fn quit(&mut self, bang: bool) -> io::Result<bool> {
+        if self.windows.count() > 1 {
+            self.close_current(bang);
+            return Ok(true);
+        }
+        if self.tabs.count() > 1 {
+            self.close_tab(self.tabs.current(), bang);
+            return Ok(true);
+        }
+        if bang {
+            return Ok(false);
+        }
         if self.output.dirty > 0 {
-            self.output.status_message.set_message(DIRTY.into());
+            self.output.status_message.set_error(DIRTY.into());
             return Ok(true);
         }
-        Ok(false)
+        // and so do hidden buffers with changes
+        Ok(!self.files_left() || !self.quit_all())
     }
 
+    /// Runs a command line, which can be several commands split by `|`.
     fn parse_command(&mut self, command: String) -> io::Result<bool> {
-        if command == "w" {
-            return self.save_file()
-            // return Ok(true)
+        let mut command = command;
+        loop {
+            let position = ex::Position {
+                cursor_y: self.output.cursor_controller.cursor_y,
+                editor_rows: &self.output.editor_rows,
+                search: &self.output.search,
+            };
+            let parsed = match ex::parse(&command, &position) {
+                Ok(parsed) => parsed,
+                Err(error) => {
+                    self.output.status_message.set_error(error);
+                    return Ok(true);
+                }
+            };
+            if let Some(pattern) = parsed.pattern.clone() {
+                self.output.search.set_pattern(pattern);
+            }
+            let next = parsed.next.map(String::from);
+            if !self.run_command(parsed)? {
+                return Ok(false);
+            }
+            match next {
+                Some(next) => command = next,
+                None => return Ok(true),
+            }
         }
-        if command == "q" {
-            return self.quit();
+    }
+
+    fn run_command(&mut self, command: ex::Command) -> io::Result<bool> {
+        let ex::Command { range, name, bang, args, line, .. } = command;
+        let cursor_y = self.output.cursor_controller.cursor_y;
+        let lines = range.unwrap_or(ex::Range::line(cursor_y));
+        let nr_of_rows = self.output.editor_rows.nr_of_rows();
+        if self.output.editor_rows.listing && ex::changes_buffer(name, range.is_some()) {
+            self.output.status_message.set_error(LISTING.into());
+            return Ok(true);
         }
-        if command == "wq" {
-            match self.save_file() {
-                Ok(..) => return Ok(false),
-                Err(error) => return Err(error)
+        match name {
+            // `:12` jumps to line 12
+            "" => if let Some(range) = range {
+                self.output.set_cursor_first_non_blank(range.end);
+            },
+            "set" => self.set(args, false)?,
+            "setlocal" => self.set(args, true)?,
+            _ if mapping::is_map_command(name) => self.map_command(name, args)?,
+            _ if ex::is_user_command(name) => return self.run_user_command(name, range, bang, args),
+            "command" => self.define_command(bang, args)?,
+            "delcommand" => self.delete_command(args),
+            "comclear" => self.user_commands.clear(),
+            "normal" => return self.normal(range, bang, args),
+            "sort" => self.sort(range, bang, args),
+            "retab" => self.retab(range, bang, args),
+            _ if nr_of_rows == 0 && ["center", "right", "left"].contains(&name) => {}
+            "center" | "right" | "left" => self.align(lines, name, args),
+            "source" => match args.trim() {
+                "" => self.output.status_message.set_error("Argument required".into()),
+                path => return self.source(&file::expand(path), true),
+            },
+            "undo" => self.output.undo(false),
+            "redo" => self.output.undo(true),
+            "nohlsearch" => self.output.search.no_highlight = true,
+            "substitute" | "&" => return self.substitute(range, args),
+            "global" | "vglobal" => return self.global(range, bang || name == "vglobal", args),
+            _ if nr_of_rows == 0 && ["delete", "move", "copy", "t", "mark"].contains(&name) => {}
+            "delete" => self.output.delete_lines(lines),
+            "move" | "copy" | "t" => {
+                let position = ex::Position {
+                    cursor_y,
+                    editor_rows: &self.output.editor_rows,
+                    search: &self.output.search,
+                };
+                let result = ex::parse_line(args, &position).and_then(|dest| {
+                    if name == "move" {
+                        self.output.move_lines(lines, dest)
+                    } else {
+                        self.output.copy_lines(lines, dest);
+                        Ok(())
+                    }
+                });
+                if let Err(error) = result {
+                    self.output.status_message.set_error(error);
+                }
+            }
+            "mark" => match args.trim().chars().collect::<Vec<_>>()[..] {
+                [mark] if self.output.editor_rows.set_mark(mark, 0, lines.end) => {}
+                [] => self.output.status_message.set_error("Argument required".into()),
+                _ => self.output.status_message.set_error(format!("Invalid argument: {}", args.trim())),
+            },
+            "edit" => self.edit_file(bang, args),
+            "read" => match args.strip_prefix('!') {
+                Some(cmd) => self.read_command(line.unwrap_or(cursor_y + 1), cmd)?,
+                None => self.read_file(line.unwrap_or(cursor_y + 1), args),
+            },
+            "write" => match args.strip_prefix('!') {
+                Some(cmd) => self.write_to_command(range, cmd)?,
+                None => {
+                    self.write_file(range, bang, args);
+                }
+            },
+            "!" => self.shell_command(range, args)?,
+            "saveas" => self.save_as(bang, args),
+            "update" => if self.output.dirty > 0 {
+                self.write_file(range, bang, args);
+            },
+            "quit" => return self.quit(bang),
+            "wq" => return Ok(!self.write_file(range, bang, args) || self.quit(false)?),
+            "xit" | "exit" => return Ok((self.output.dirty > 0 && !self.write_file(range, bang, args)) || self.quit(false)?),
+            "buffer" => self.buffer_command(bang, args),
+            "bnext" => self.cycle_buffer(bang, false),
+            "bprevious" | "bNext" => self.cycle_buffer(bang, true),
+            "bdelete" => self.delete_buffer(bang, args),
+            "ls" | "files" | "buffers" => self.list_buffers()?,
+            "qall" | "quitall" => return Ok(!bang && !self.quit_all()),
+            "wall" => {
+                self.write_all(bang);
             }
+            "wqall" | "xall" => return Ok(!self.write_all(bang) || !self.quit_all()),
+            "args" => self.args(bang, args)?,
+            "next" => self.next_arg(bang, args),
+            "Next" | "previous" => self.previous_arg(bang),
+            "first" | "rewind" => self.edge_arg(bang, false),
+            "last" => self.edge_arg(bang, true),
+            "argdo" => return self.argdo(bang, args),
+            "bufdo" => return self.bufdo(bang, args),
+            "windo" => return self.windo(args),
+            "cdo" => return self.cdo(bang, args, false),
+            "cfdo" => return self.cdo(bang, args, true),
+            "vimgrep" => self.vimgrep(bang, args),
+            "cc" => self.current_entry(bang, args),
+            "cnext" => self.cycle_entry(bang, false),
+            "cprevious" | "cNext" => self.cycle_entry(bang, true),
+            "clist" => self.list_entries()?,
+            "split" => self.split_command(Split::Horizontal, false, args),
+            "vsplit" => self.split_command(Split::Vertical, false, args),
+            "new" => self.split_command(Split::Horizontal, true, args),
+            "vnew" => self.split_command(Split::Vertical, true, args),
+            "close" => self.close_current(bang),
+            "only" => self.only_window(bang),
+            "resize" => self.resize_command(args),
+            "wincmd" => return self.wincmd(args),
+            "tabnew" | "tabedit" => self.new_tab(args),
+            "tabclose" => self.close_tab_command(bang, args),
+            "tabonly" => self.only_tab(bang),
+            "tabmove" => self.move_tab(args),
+            "tabnext" => self.next_tab(args),
+            "tabprevious" | "tabNext" => self.previous_tab(args),
+            "tabs" => self.list_tabs()?,
+            _ => unreachable!("{} is missing from ex::COMMANDS", name),
         }
         Ok(true)
     }
 
-    fn process_command(&mut self) -> io::Result<bool> {
-        self.command.clear();
+    /// `:` prompt, a `:s` being typed gets previewed on the rows on screen and Tab completes
+    fn process_command(&mut self, initial: &str) -> io::Result<bool> {
+        let mut cmdline = CmdLine::new(initial);
+        let saved_cursor = self.output.cursor_controller.clone();
+        let mut completion: Option<Completion> = None;
         loop {
-            match self.reader.read_key()? {
-                KeyEvent {
-                    code: ch @ (KeyCode::Char(..) | KeyCode::Esc | KeyCode::Enter ),
-                    modifiers: KeyModifiers::NONE,
-                    kind: _,
-                    state: _ 
-                } => {
-                        match ch { 
-                            KeyCode::End => return Ok(true),
-                            KeyCode::Enter => break,
-                            KeyCode::Char(ch) => { 
-                                self.command.push(ch)
+            self.output.prompt = Some((format!(":{}", cmdline.text), cmdline.cursor_col() + 1));
+            self.output.preview = self.preview_substitute(&cmdline.text);
+            self.refresh_screen()?;
+
+            let key = self.read_key()?;
+            if let KeyCode::Tab | KeyCode::BackTab = key.code {
+                let backwards = key.code == KeyCode::BackTab;
+                match &mut completion {
+                    Some(completion) => completion.cycle(&mut cmdline, backwards),
+                    None => {
+                        completion = self.complete(&cmdline);
+                        match &mut completion {
+                            // a single candidate just gets filled in
+                            Some(only) if only.candidates.len() == 1 => {
+                                only.cycle(&mut cmdline, false);
+                                completion = None;
                             }
-                            _ => unimplemented!()
+                            Some(completion) => completion.cycle(&mut cmdline, backwards),
+                            None => {}
                         }
                     }
-                _ => unimplemented!()
+                }
+                self.output.wildmenu = completion.as_ref().map(|completion| {
+                    let labels = completion.candidates.iter().map(|c| Completion::label(c).to_string());
+                    (labels.collect(), completion.selected)
+                });
+                continue;
+            }
+            completion = None;
+            self.output.wildmenu = None;
+
+            match cmdline.handle(key, &self.command_history) {
+                Action::Enter => break,
+                Action::Cancel => {
+                    // the file was never touched so only the view needs to go back
+                    self.output.prompt = None;
+                    self.output.preview = None;
+                    self.output.cursor_controller = saved_cursor;
+                    return Ok(true);
+                }
+                _ => {}
             }
         }
 
-        self.parse_command(self.command.to_string())
+        self.output.prompt = None;
+        self.output.preview = None;
+        self.command_history.add(&cmdline.text);
+        self.parse_command(cmdline.text)
     }
 
-    fn process_keypress(&mut self) -> io::Result<bool> {
-        match self.reader.read_key()? {
+    /// `/` and `?` prompt, the cursor jumps to the first match while the pattern is typed and
+    /// goes back to where it was if the search is cancelled
+    fn process_search(&mut self, direction: Direction) -> io::Result<bool> {
+        let saved_cursor = self.output.cursor_controller.clone();
+        let mut cmdline = CmdLine::new("");
+
+        loop {
+            self.output.prompt = Some((format!("{}{}", direction.prefix(), cmdline.text), cmdline.cursor_col() + 1));
+            self.refresh_screen()?;
+
+            match cmdline.handle(self.read_key()?, &self.search_history) {
+                Action::Enter => break,
+                Action::Cancel => {
+                    self.output.prompt = None;
+                    self.output.search.typed = None;
+                    self.output.cursor_controller = saved_cursor;
+                    return Ok(true);
+                }
+                Action::Edited => {}
+                _ => continue,
+            }
+
+            self.output.cursor_controller = saved_cursor.clone();
+            if !self.output.search.incsearch {
+                continue;
+            }
+            let pattern = &cmdline.text;
+            self.output.search.typed = Some(pattern.clone());
+            let cursor = (saved_cursor.cursor_x, saved_cursor.cursor_y);
+            // half typed patterns are often invalid, `foo\(` say, those just don't move the cursor
+            if let Some(hit) = self.output.search.regex(pattern).ok().filter(|_| !pattern.is_empty()).and_then(|regex| {
+                self.output
+                    .search
+                    .find(&self.output.editor_rows, &regex, cursor, direction)
+            }) {
+                self.output.cursor_controller.cursor_x = hit.x;
+                self.output.cursor_controller.cursor_y = hit.y;
+            }
+        }
+
+        self.output.prompt = None;
+        self.output.search.typed = None;
+        self.output.cursor_controller = saved_cursor;
+        self.search_history.add(&cmdline.text);
+        if !cmdline.text.is_empty() {
+            self.output.search.set_pattern(cmdline.text);
+        }
+        self.output.search.direction = direction;
+        self.output.search_next(direction);
+        Ok(true)
+    }
+
+    /// Prints `lines` over the file and leaves them there until a key is pressed.
+    fn show_lines(&mut self, lines: &[String]) -> io::Result<()> {
+        Output::clear_screen()?;
+        print!("{}", lines.join("\r\n"));
+        stdout().flush()?;
+        self.press_enter()
+    }
+
+    /// Leaves what got printed over the file there until a key is pressed.
+    fn press_enter(&mut self) -> io::Result<()> {
+        print!("\r\nPress ENTER or type command to continue");
+        stdout().flush()?;
+        self.read_key()?;
+        Output::clear_screen()
+    }
+
+    /// `!{motion}` opens the `:` prompt for filtering the lines the motion goes over, `!!`
+    /// being the cursor line and `!ip` / `!ap` the paragraph.
+    fn process_filter(&mut self) -> io::Result<bool> {
+        if self.output.editor_rows.nr_of_rows() == 0 {
+            return Ok(true);
+        }
+        let saved_cursor = self.output.cursor_controller.clone();
+        let cursor_y = saved_cursor.cursor_y;
+        let range = match self.read_key()? {
             KeyEvent {
-                code: KeyCode::Char(':'),
-                modifiers: KeyModifiers::NONE,
+                code: KeyCode::Char('!'),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
                 kind: _,
                 state: _,
-            } => return self.process_command(),
+            } => ex::Range::line(cursor_y),
             KeyEvent {
-                code: KeyCode::Char('q'),
-                modifiers: KeyModifiers::CONTROL,
+                code: code @ (KeyCode::Char('i') | KeyCode::Char('a')),
+                modifiers: KeyModifiers::NONE,
                 kind: _,
                 state: _,
-            } => return Ok(false),// { if self.output.dirty > 0 {
+            } => match self.read_key()?.code {
+                KeyCode::Char('p') => self.output.paragraph(cursor_y, code == KeyCode::Char('a')),
+                _ => return Ok(true),
+            },
+            key => {
+                if !self.process_motion(key)? {
+                    return Ok(true);
+                }
+                let y = cmp::min(self.output.cursor_controller.cursor_y, self.output.editor_rows.nr_of_rows() - 1);
+                self.output.cursor_controller = saved_cursor;
+                ex::Range { start: cmp::min(y, cursor_y), end: cmp::max(y, cursor_y) }
+            }
+        };
+
+        self.output.set_cursor_first_non_blank(range.start);
+        match range.end - range.start {
+            0 => self.process_command(".!"),
+            lines => self.process_command(&format!(".,.+{}!", lines)),
+        }
+    }
+
+    fn process_keypress(&mut self) -> io::Result<bool> {
+        let key = self.read_mapped_key()?;
+        if let KeyEvent {
+            code: KeyCode::Char('q'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: _,
+            state: _,
+        } = key
+        {
+            return Ok(false);
+            // { if self.output.dirty > 0 {
             //         self.output.status_message.set_message(DIRTY.into());
             //         return Ok(true);
             //     }
-                 // return  Ok(false)
+            // return  Ok(false)
             //},
+        }
+
+        match self.output.mode {
+            Mode::Normal => self.process_normal_keypress(key),
+            Mode::Insert => self.process_insert_keypress(key),
+            Mode::Visual | Mode::VisualLine => self.process_visual_keypress(key),
+        }
+    }
+
+    /// Moves the cursor if `key` is a motion, which works the same in normal and visual mode.
+    /// Returns false for anything else.
+    fn process_motion(&mut self, key: KeyEvent) -> io::Result<bool> {
+        match key {
+            KeyEvent {
+                code: code @ (KeyCode::Char('/') | KeyCode::Char('?')),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                kind: _,
+                state: _,
+            } => {
+                self.process_search(if code == KeyCode::Char('/') {
+                    Direction::Forward
+                } else {
+                    Direction::Backward
+                })?;
+            }
+            KeyEvent {
+                code: KeyCode::Char('n'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } => self.output.search_next(self.output.search.direction),
+            KeyEvent {
+                code: KeyCode::Char('N'),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                kind: _,
+                state: _,
+            } => self.output.search_next(self.output.search.direction.reverse()),
+            KeyEvent {
+                code: code @ (KeyCode::Char('*') | KeyCode::Char('#')),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                kind: _,
+                state: _,
+            } => self.output.search_word(
+                if code == KeyCode::Char('*') { Direction::Forward } else { Direction::Backward },
+                true,
+            ),
+            KeyEvent {
+                code: KeyCode::Char('g'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } => {
+                match self.read_key()? {
+                    KeyEvent {
+                        code: code @ (KeyCode::Char('*') | KeyCode::Char('#')),
+                        modifiers: _,
+                        kind: _,
+                        state: _,
+                    } => self.output.search_word(
+                        if code == KeyCode::Char('*') { Direction::Forward } else { Direction::Backward },
+                        false,
+                    ),
+                    // gt and gT go to the next and previous tab page
+                    KeyEvent {
+                        code: KeyCode::Char('t'),
+                        modifiers: KeyModifiers::NONE,
+                        kind: _,
+                        state: _,
+                    } => self.next_tab(""),
+                    KeyEvent {
+                        code: KeyCode::Char('T'),
+                        modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                        kind: _,
+                        state: _,
+                    } => self.previous_tab(""),
+                    _ => {}
+                }
+            }
+            KeyEvent {
+                code: code @ (KeyCode::Char('\'') | KeyCode::Char('`')),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                kind: _,
+                state: _,
+            } => {
+                // 'a goes to the line of mark a, `a to the exact spot
+                if let KeyEvent {
+                    code: KeyCode::Char(mark),
+                    modifiers: _,
+                    kind: _,
+                    state: _,
+                } = self.read_key()?
+                {
+                    match self.output.editor_rows.mark(mark) {
+                        Some((_, y)) if code == KeyCode::Char('\'') => self.output.set_cursor_first_non_blank(y),
+                        Some((x, y)) => self.output.set_cursor(x, y),
+                        None => self.output.status_message.set_error("Mark not set".into()),
+                    }
+                }
+            }
             KeyEvent {
                 code: direction @ ( KeyCode::Char('H') | KeyCode::Char('L')  // high | low (jump w/o scroll)
                 ),
@@ -767,6 +2085,210 @@ impl Editor {
                 kind: _,
                 state: _,
             } => self.output.jump_cursor(direction),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn process_normal_keypress(&mut self, key: KeyEvent) -> io::Result<bool> {
+        if self.browse_key(key)? || self.process_motion(key)? {
+            return Ok(true);
+        }
+        match key {
+            KeyEvent {
+                code: KeyCode::Char(':'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } => return self.process_command(""),
+            KeyEvent {
+                code: KeyCode::Char('!'),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                kind: _,
+                state: _,
+            } => return self.process_filter(),
+            KeyEvent {
+                code: KeyCode::Char('i'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } if self.output.editor_rows.listing => self.output.status_message.set_error(LISTING.into()),
+            KeyEvent {
+                code: KeyCode::Char('i'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } => {
+                // everything typed until Esc is undone in one go
+                let cursor = (self.output.cursor_controller.cursor_x, self.output.cursor_controller.cursor_y);
+                self.output.insert_step = self.output.editor_rows.history.begin(cursor);
+                self.output.mode = Mode::Insert
+            }
+            KeyEvent {
+                code: code @ (KeyCode::Char('v') | KeyCode::Char('V')),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                kind: _,
+                state: _,
+            } => {
+                self.output.visual_start = (self.output.cursor_controller.cursor_x, self.output.cursor_controller.cursor_y);
+                self.output.mode = if code == KeyCode::Char('v') { Mode::Visual } else { Mode::VisualLine };
+            }
+            KeyEvent {
+                code: KeyCode::Char('m'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } => {
+                if let KeyEvent {
+                    code: KeyCode::Char(mark),
+                    modifiers: _,
+                    kind: _,
+                    state: _,
+                } = self.read_key()?
+                {
+                    let (x, y) = (self.output.cursor_controller.cursor_x, self.output.cursor_controller.cursor_y);
+                    self.output.editor_rows.set_mark(mark, x, y);
+                }
+            }
+            KeyEvent {
+                code: KeyCode::Char('u'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } => self.output.undo(false),
+            KeyEvent {
+                code: KeyCode::Char('r'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: _,
+                state: _,
+            } => self.output.undo(true),
+            // Ctrl-^, which terminals send as Ctrl-6
+            KeyEvent {
+                code: KeyCode::Char('^') | KeyCode::Char('6'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: _,
+                state: _,
+            } => self.alternate_buffer(),
+            KeyEvent {
+                code: KeyCode::Char('w'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: _,
+                state: _,
+            } => return self.window_key(),
+            KeyEvent {
+                code: KeyCode::PageDown,
+                modifiers: KeyModifiers::CONTROL,
+                kind: _,
+                state: _,
+            } => self.next_tab(""),
+            KeyEvent {
+                code: KeyCode::PageUp,
+                modifiers: KeyModifiers::CONTROL,
+                kind: _,
+                state: _,
+            } => self.previous_tab(""),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn process_visual_keypress(&mut self, key: KeyEvent) -> io::Result<bool> {
+        if self.process_motion(key)? {
+            return Ok(true);
+        }
+        match key {
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: _,
+                kind: _,
+                state: _,
+            } => self.output.end_visual(),
+            KeyEvent {
+                code: code @ (KeyCode::Char('v') | KeyCode::Char('V')),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                kind: _,
+                state: _,
+            } => {
+                let mode = if code == KeyCode::Char('v') { Mode::Visual } else { Mode::VisualLine };
+                if self.output.mode == mode {
+                    self.output.end_visual();
+                } else {
+                    self.output.mode = mode;
+                }
+            }
+            KeyEvent {
+                code: KeyCode::Char('o'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } => {
+                // jumps to the other end of the selection
+                let (x, y) = self.output.visual_start;
+                self.output.visual_start = (self.output.cursor_controller.cursor_x, self.output.cursor_controller.cursor_y);
+                self.output.set_cursor(x, y);
+            }
+            KeyEvent {
+                code: KeyCode::Char(':'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } => {
+                self.output.end_visual();
+                return self.process_command("'<,'>");
+            }
+            KeyEvent {
+                code: KeyCode::Char('!'),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                kind: _,
+                state: _,
+            } => {
+                self.output.end_visual();
+                return self.process_command("'<,'>!");
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn process_insert_keypress(&mut self, key: KeyEvent) -> io::Result<bool> {
+        match key {
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: _,
+                kind: _,
+                state: _,
+            } => {
+                if self.output.insert_step {
+                    self.output.editor_rows.history.end();
+                }
+                self.output.mode = Mode::Normal;
+                self.output.move_cursor(KeyCode::Left);
+            }
+            KeyEvent {
+                code: direction @ ( KeyCode::Left | KeyCode::Backspace | KeyCode::Down |
+                                    KeyCode::Up   | KeyCode::Right
+                ),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } => self.output.move_cursor(direction),
+            KeyEvent {
+                code: KeyCode::Tab,
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            } if self.output.editor_rows.options.bool("expandtab") => {
+                // spaces up to the next tab stop
+                let tab_stop = self.output.editor_rows.tab_stop();
+                let cursor_x = self.output.cursor_controller.cursor_x;
+                let render_x = match self.output.cursor_controller.cursor_y < self.output.editor_rows.nr_of_rows() {
+                    true => self.output.editor_rows.get_editor_row(self.output.cursor_controller.cursor_y).render_x(cursor_x, tab_stop),
+                    false => 0,
+                };
+                for _ in 0..tab_stop - render_x % tab_stop {
+                    self.output.insert_char(' ');
+                }
+            }
             KeyEvent { code: code @ (KeyCode::Char(..) | KeyCode::Tab),
                 modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
                 kind: _,
@@ -782,7 +2304,7 @@ impl Editor {
     }
 
     fn run(&mut self) -> io::Result<bool> {
-        self.output.refresh_screen()?;
+        self.refresh_screen()?;
         self.process_keypress()
     }
 }
@@ -801,6 +2323,10 @@ impl Editor {
 // }
 
 fn main() -> io::Result<()> {
+    let args = Args::parse().unwrap_or_else(|error| {
+        eprintln!("{}: {}", NAME, error);
+        process::exit(1)
+    });
     let _clean_up = CleanUp;
 
     terminal::enable_raw_mode()?;
@@ -814,6 +2340,9 @@ fn main() -> io::Result<()> {
     // terminal.clear()?;
 
     let mut editor = Editor::new();
+    if !editor.start(args)? {
+        return Ok(());
+    }
     while editor.run()? {}
 
     Ok(())
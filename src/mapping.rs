@@ -0,0 +1,654 @@
+use crate::{ex, Editor, Mode, Output};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{
+    collections::HashMap,
+    io::{self, stdout, Write},
+    time::Duration,
+};
+
+/// how deep `:normal` can run itself, from a mapping or a user command
+const MAX_NORMAL_DEPTH: usize = 100;
+
+/// mappings applied one after the other without a key coming out, past this it's taken for
+/// a mapping that maps to itself, like vim's 'maxmapdepth'
+const MAX_MAP_DEPTH: usize = 1000;
+
+/// named keys for `<...>` notation, the first name of a key is the one mappings get listed with
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("CR", KeyCode::Enter),
+    ("Enter", KeyCode::Enter),
+    ("Return", KeyCode::Enter),
+    ("Esc", KeyCode::Esc),
+    ("Tab", KeyCode::Tab),
+    ("BS", KeyCode::Backspace),
+    ("Del", KeyCode::Delete),
+    ("Space", KeyCode::Char(' ')),
+    ("lt", KeyCode::Char('<')),
+    ("Bar", KeyCode::Char('|')),
+    ("Bslash", KeyCode::Char('\\')),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Insert", KeyCode::Insert),
+];
+
+/// A key the way mappings compare them: shift only counts for keys that aren't chars, where
+/// it's already in the char, and control chars are lower case.
+type Key = (KeyCode, KeyModifiers);
+
+fn key(event: &KeyEvent) -> Key {
+    match event.code {
+        KeyCode::Char(ch) if event.modifiers.contains(KeyModifiers::CONTROL) => (
+            KeyCode::Char(ch.to_ascii_lowercase()),
+            event.modifiers - KeyModifiers::SHIFT,
+        ),
+        KeyCode::Char(_) | KeyCode::BackTab => (event.code, event.modifiers - KeyModifiers::SHIFT),
+        code => (code, event.modifiers),
+    }
+}
+
+/// a key as the terminal would send it, upper case chars coming with shift
+fn key_event(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+    match code {
+        KeyCode::Char(ch) if ch.is_uppercase() => {
+            KeyEvent::new(code, modifiers | KeyModifiers::SHIFT)
+        }
+        KeyCode::BackTab => KeyEvent::new(code, modifiers | KeyModifiers::SHIFT),
+        _ => KeyEvent::new(code, modifiers),
+    }
+}
+
+/// One `<...>` key like `<C-s>`, `<S-Tab>` or `<F5>`, without the brackets.
+fn parse_special(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = name;
+    // a modifier has something after its dash, so `<C-->` is control and minus
+    while let Some((modifier, after)) = rest.split_once('-').filter(|(_, after)| !after.is_empty())
+    {
+        modifiers |= match modifier.to_ascii_uppercase().as_str() {
+            "C" => KeyModifiers::CONTROL,
+            "S" => KeyModifiers::SHIFT,
+            "A" | "M" => KeyModifiers::ALT,
+            _ => return None,
+        };
+        rest = after;
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(ch), None) => KeyCode::Char(ch),
+        _ if rest.len() > 1 && rest[..1].eq_ignore_ascii_case("f") => {
+            KeyCode::F(rest[1..].parse().ok().filter(|n| (1..=12).contains(n))?)
+        }
+        _ => KEY_NAMES
+            .iter()
+            .find(|(key_name, _)| key_name.eq_ignore_ascii_case(rest))
+            .map(|(_, code)| *code)?,
+    };
+    Some(match code {
+        KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => {
+            key_event(KeyCode::BackTab, modifiers - KeyModifiers::SHIFT)
+        }
+        KeyCode::Char(ch) if modifiers.contains(KeyModifiers::SHIFT) => key_event(
+            KeyCode::Char(ch.to_ascii_uppercase()),
+            modifiers - KeyModifiers::SHIFT,
+        ),
+        code => key_event(code, modifiers),
+    })
+}
+
+/// Turns key notation into keys, `<leader>` standing for `leader` and `<Nop>` for nothing. A
+/// `<` that doesn't start a known key is just a `<`.
+pub fn parse_keys(text: &str, leader: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                let special = if name.eq_ignore_ascii_case("leader") {
+                    Some(parse_keys(leader, ""))
+                } else if name.eq_ignore_ascii_case("nop") {
+                    Some(Vec::new())
+                } else {
+                    parse_special(name).map(|key| vec![key])
+                };
+                if let Some(special) = special {
+                    keys.extend(special);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(key_event(KeyCode::Char(ch), KeyModifiers::NONE));
+        rest = &rest[ch.len_utf8()..];
+    }
+    keys
+}
+
+/// how a key gets listed by `:map`
+fn key_name(event: &KeyEvent) -> String {
+    let (code, modifiers) = key(event);
+    let mut prefix = String::new();
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        prefix.push_str("C-");
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        prefix.push_str("M-");
+    }
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        prefix.push_str("S-");
+    }
+    let name = match code {
+        KeyCode::BackTab => "S-Tab".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::Char(ch) if prefix.is_empty() && !"< |\\".contains(ch) => return ch.to_string(),
+        KeyCode::Char(ch) if !prefix.is_empty() && ch != ' ' => ch.to_string(),
+        code => match KEY_NAMES.iter().find(|(_, named)| *named == code) {
+            Some((name, _)) => name.to_string(),
+            None => return String::new(),
+        },
+    };
+    format!("<{}{}>", prefix, name)
+}
+
+fn key_names(keys: &[KeyEvent]) -> String {
+    keys.iter().map(key_name).collect()
+}
+
+#[derive(Clone)]
+pub struct Mapping {
+    rhs: Vec<KeyEvent>,
+    noremap: bool,
+    silent: bool,
+}
+
+/// Mappings by the keys they start with, a key at a time.
+#[derive(Default, Clone)]
+struct Trie {
+    children: HashMap<Key, Trie>,
+    mapping: Option<Mapping>,
+}
+
+impl Trie {
+    fn insert(&mut self, lhs: &[KeyEvent], mapping: Mapping) {
+        let node = lhs.iter().fold(self, |node, event| {
+            node.children.entry(key(event)).or_default()
+        });
+        node.mapping = Some(mapping);
+    }
+
+    /// takes the mapping for `lhs` out, dropping whatever branch is left empty
+    fn remove(&mut self, lhs: &[KeyEvent]) -> bool {
+        let Some((first, rest)) = lhs.split_first() else {
+            return self.mapping.take().is_some();
+        };
+        let Some(child) = self.children.get_mut(&key(first)) else {
+            return false;
+        };
+        let removed = child.remove(rest);
+        if child.mapping.is_none() && child.children.is_empty() {
+            self.children.remove(&key(first));
+        }
+        removed
+    }
+
+    /// The longest mapping `keys` start with, and whether they also start a longer one (or
+    /// could, with more keys to come).
+    fn lookup(&self, keys: impl Iterator<Item = Key>) -> (Option<(usize, &Mapping)>, bool) {
+        let mut node = self;
+        let mut found = None;
+        for (idx, key) in keys.enumerate() {
+            match node.children.get(&key) {
+                Some(child) => node = child,
+                None => return (found, false),
+            }
+            if let Some(mapping) = &node.mapping {
+                found = Some((idx + 1, mapping));
+            }
+        }
+        (found, !node.children.is_empty())
+    }
+
+    /// every mapping in here as its lhs and the mapping
+    fn list(&self, lhs: &mut Vec<Key>, all: &mut Vec<(Vec<Key>, Mapping)>) {
+        if let Some(mapping) = &self.mapping {
+            all.push((lhs.clone(), mapping.clone()));
+        }
+        for (key, child) in &self.children {
+            lhs.push(*key);
+            child.list(lhs, all);
+            lhs.pop();
+        }
+    }
+}
+
+/// The mappings of each mode, either the global ones or those of a buffer.
+#[derive(Default, Clone)]
+pub struct Mappings {
+    normal: Trie,
+    visual: Trie,
+    insert: Trie,
+}
+
+impl Mappings {
+    fn trie(&self, mode: Mode) -> &Trie {
+        match mode {
+            Mode::Normal => &self.normal,
+            Mode::Visual | Mode::VisualLine => &self.visual,
+            Mode::Insert => &self.insert,
+        }
+    }
+
+    fn trie_mut(&mut self, mode: Mode) -> &mut Trie {
+        match mode {
+            Mode::Normal => &mut self.normal,
+            Mode::Visual | Mode::VisualLine => &mut self.visual,
+            Mode::Insert => &mut self.insert,
+        }
+    }
+}
+
+/// A key waiting to be handled, either typed ahead or put there by a mapping.
+pub struct Typed {
+    event: KeyEvent,
+    /// false for what a `noremap` maps to
+    remap: bool,
+    /// comes from a `<silent>` mapping, the command line it types doesn't get shown
+    silent: bool,
+}
+
+/// What a `:map` command is about: its modes, and whether it's a `noremap`.
+struct MapCommand {
+    modes: &'static [Mode],
+    noremap: bool,
+}
+
+#[derive(Clone, Copy)]
+enum MapAction {
+    Map,
+    Unmap,
+    Clear,
+}
+
+const NORMAL_VISUAL: &[Mode] = &[Mode::Normal, Mode::Visual];
+const NORMAL: &[Mode] = &[Mode::Normal];
+const VISUAL: &[Mode] = &[Mode::Visual];
+const INSERT: &[Mode] = &[Mode::Insert];
+
+/// The map commands by their full name, `map`, `noremap`, `unmap` and `mapclear` being for
+/// normal and visual mode together.
+const MAP_COMMANDS: &[(&str, MapAction, &[Mode], bool)] = &[
+    ("map", MapAction::Map, NORMAL_VISUAL, false),
+    ("nmap", MapAction::Map, NORMAL, false),
+    ("vmap", MapAction::Map, VISUAL, false),
+    ("imap", MapAction::Map, INSERT, false),
+    ("noremap", MapAction::Map, NORMAL_VISUAL, true),
+    ("nnoremap", MapAction::Map, NORMAL, true),
+    ("vnoremap", MapAction::Map, VISUAL, true),
+    ("inoremap", MapAction::Map, INSERT, true),
+    ("unmap", MapAction::Unmap, NORMAL_VISUAL, false),
+    ("nunmap", MapAction::Unmap, NORMAL, false),
+    ("vunmap", MapAction::Unmap, VISUAL, false),
+    ("iunmap", MapAction::Unmap, INSERT, false),
+    ("mapclear", MapAction::Clear, NORMAL_VISUAL, false),
+    ("nmapclear", MapAction::Clear, NORMAL, false),
+    ("vmapclear", MapAction::Clear, VISUAL, false),
+    ("imapclear", MapAction::Clear, INSERT, false),
+];
+
+pub fn is_map_command(name: &str) -> bool {
+    MAP_COMMANDS.iter().any(|(full, ..)| *full == name)
+}
+
+fn mode_letter(mode: Mode) -> char {
+    match mode {
+        Mode::Normal => 'n',
+        Mode::Visual | Mode::VisualLine => 'v',
+        Mode::Insert => 'i',
+    }
+}
+
+/// The keys of `:normal`, which are typed as they are, control characters included.
+fn literal_keys(text: &str) -> Vec<KeyEvent> {
+    text.chars()
+        .map(|ch| match ch {
+            '\x1b' => key_event(KeyCode::Esc, KeyModifiers::NONE),
+            '\r' | '\n' => key_event(KeyCode::Enter, KeyModifiers::NONE),
+            '\t' => key_event(KeyCode::Tab, KeyModifiers::NONE),
+            '\x7f' => key_event(KeyCode::Backspace, KeyModifiers::NONE),
+            '\x01'..='\x1a' => key_event(
+                KeyCode::Char((ch as u8 - 1 + b'a') as char),
+                KeyModifiers::CONTROL,
+            ),
+            ch => key_event(KeyCode::Char(ch), KeyModifiers::NONE),
+        })
+        .collect()
+}
+
+/// Splits the `<buffer>` and `<silent>` off the front of a map command's arguments, in any
+/// order.
+fn map_flags(mut args: &str) -> (bool, bool, &str) {
+    let (mut buffer, mut silent) = (false, false);
+    loop {
+        args = args.trim_start();
+        if let Some(rest) = args.strip_prefix("<buffer>") {
+            buffer = true;
+            args = rest;
+        } else if let Some(rest) = args.strip_prefix("<silent>") {
+            silent = true;
+            args = rest;
+        } else {
+            return (buffer, silent, args);
+        }
+    }
+}
+
+impl Editor {
+    /// Runs one of the `MAP_COMMANDS`.
+    pub fn map_command(&mut self, name: &str, args: &str) -> io::Result<()> {
+        let Some(&(_, action, modes, noremap)) =
+            MAP_COMMANDS.iter().find(|(full, ..)| *full == name)
+        else {
+            unreachable!("{} is missing from mapping::MAP_COMMANDS", name)
+        };
+        let command = MapCommand { modes, noremap };
+        match action {
+            MapAction::Map => return self.map(command, args),
+            MapAction::Unmap => self.unmap(command, args),
+            MapAction::Clear => self.map_clear(command, args),
+        }
+        Ok(())
+    }
+
+    /// The next key to handle, the ones mappings put there coming first. Mappings aren't
+    /// looked at, see `read_mapped_key` for that.
+    pub fn read_key(&mut self) -> io::Result<KeyEvent> {
+        match self.typeahead.pop_front() {
+            Some(typed) => {
+                self.output.silent = typed.silent;
+                Ok(typed.event)
+            }
+            None => {
+                self.output.silent = false;
+                self.terminal_key()
+            }
+        }
+    }
+
+    /// Puts `event` back, to be the next key `read_key` gives.
+    pub fn unread_key(&mut self, event: KeyEvent) {
+        let silent = self.output.silent;
+        self.typeahead.push_front(Typed {
+            event,
+            remap: false,
+            silent,
+        });
+    }
+
+    /// A key from the terminal, except while `:normal` runs: its keys running out ends whatever
+    /// they left unfinished, like an Esc would.
+    fn terminal_key(&mut self) -> io::Result<KeyEvent> {
+        match self.normal_depth {
+            0 => self.reader.read_key(),
+            _ => Ok(key_event(KeyCode::Esc, KeyModifiers::NONE)),
+        }
+    }
+
+    /// The next key for the current mode with its mappings applied. When the keys so far could
+    /// still become a longer mapping it waits up to 'timeoutlen' milliseconds for more.
+    pub fn read_mapped_key(&mut self) -> io::Result<KeyEvent> {
+        let mode = self.output.mode;
+        let mut depth = 0;
+        loop {
+            if self.typeahead.is_empty() {
+                let event = self.terminal_key()?;
+                self.typeahead.push_back(Typed {
+                    event,
+                    remap: true,
+                    silent: false,
+                });
+            }
+
+            // what a noremap maps to isn't looked at again
+            let keys = || {
+                self.typeahead
+                    .iter()
+                    .take_while(|typed| typed.remap)
+                    .map(|typed| key(&typed.event))
+            };
+            let local = self.output.editor_rows.mappings.trie(mode).lookup(keys());
+            let global = self.mappings.trie(mode).lookup(keys());
+            // buffer mappings go first, the longest match wins
+            let found = match (local.0, global.0) {
+                (Some(local), Some(global)) if global.0 > local.0 => Some(global),
+                (Some(local), _) => Some(local),
+                (None, global) => global,
+            };
+            let found = found.map(|(len, mapping)| (len, mapping.clone()));
+            let more = local.1 || global.1;
+            let all_remappable = self.typeahead.iter().all(|typed| typed.remap);
+
+            if more && all_remappable && self.normal_depth == 0 {
+                let timeout = self.output.options.number("timeoutlen") as u64;
+                if let Some(event) = self.reader.poll_key(Duration::from_millis(timeout))? {
+                    self.typeahead.push_back(Typed {
+                        event,
+                        remap: true,
+                        silent: false,
+                    });
+                    continue;
+                }
+            }
+
+            let Some((len, mapping)) = found else {
+                return self.read_key();
+            };
+            depth += 1;
+            if depth > MAX_MAP_DEPTH {
+                self.typeahead.clear();
+                self.output
+                    .status_message
+                    .set_error("Recursive mapping".into());
+                // shown now, not once another key is in
+                self.refresh_screen()?;
+                return self.read_key();
+            }
+            self.typeahead.drain(..len);
+            for event in mapping.rhs.iter().rev() {
+                self.typeahead.push_front(Typed {
+                    event: *event,
+                    remap: !mapping.noremap,
+                    silent: mapping.silent,
+                });
+            }
+        }
+    }
+
+    /// `:map`, `:nnoremap` and the like: `[<buffer>] [<silent>] {lhs} {rhs}`. Without a rhs
+    /// they list the mappings starting with lhs, without anything they list them all.
+    fn map(&mut self, command: MapCommand, args: &str) -> io::Result<()> {
+        let (buffer, silent, args) = map_flags(args);
+        let leader = self.output.options.string("mapleader").to_string();
+        let (lhs, rhs) = match args.split_once(char::is_whitespace) {
+            Some((lhs, rhs)) => (lhs, rhs.trim_start()),
+            None => (args, ""),
+        };
+        let lhs = parse_keys(lhs, &leader);
+        if rhs.is_empty() {
+            return self.list_mappings(&command, buffer, &lhs);
+        }
+
+        let mapping = Mapping {
+            rhs: parse_keys(rhs, &leader),
+            noremap: command.noremap,
+            silent,
+        };
+        let mappings = if buffer {
+            &mut self.output.editor_rows.mappings
+        } else {
+            &mut self.mappings
+        };
+        for &mode in command.modes {
+            mappings.trie_mut(mode).insert(&lhs, mapping.clone());
+        }
+        Ok(())
+    }
+
+    /// `:unmap [<buffer>] {lhs}` and the like.
+    fn unmap(&mut self, command: MapCommand, args: &str) {
+        let (buffer, _, args) = map_flags(args);
+        let leader = self.output.options.string("mapleader").to_string();
+        let lhs = parse_keys(args.trim_end(), &leader);
+        let mappings = if buffer {
+            &mut self.output.editor_rows.mappings
+        } else {
+            &mut self.mappings
+        };
+        let mut removed = false;
+        for &mode in command.modes {
+            removed |= mappings.trie_mut(mode).remove(&lhs);
+        }
+        if !removed {
+            self.output
+                .status_message
+                .set_error("No such mapping".into());
+        }
+    }
+
+    /// `:mapclear [<buffer>]` and the like.
+    fn map_clear(&mut self, command: MapCommand, args: &str) {
+        let (buffer, _, _) = map_flags(args);
+        let mappings = if buffer {
+            &mut self.output.editor_rows.mappings
+        } else {
+            &mut self.mappings
+        };
+        for &mode in command.modes {
+            *mappings.trie_mut(mode) = Trie::default();
+        }
+    }
+
+    fn list_mappings(
+        &mut self,
+        command: &MapCommand,
+        buffer: bool,
+        prefix: &[KeyEvent],
+    ) -> io::Result<()> {
+        let prefix: Vec<Key> = prefix.iter().map(key).collect();
+        let mut lines = Vec::new();
+        for &mode in command.modes {
+            let mut sources = vec![(true, &self.output.editor_rows.mappings)];
+            if !buffer {
+                sources.push((false, &self.mappings));
+            }
+            for (local, mappings) in sources {
+                let mut all = Vec::new();
+                mappings.trie(mode).list(&mut Vec::new(), &mut all);
+                for (lhs, mapping) in all.into_iter().filter(|(lhs, _)| lhs.starts_with(&prefix)) {
+                    let lhs: Vec<KeyEvent> = lhs
+                        .into_iter()
+                        .map(|(code, modifiers)| key_event(code, modifiers))
+                        .collect();
+                    lines.push(format!(
+                        "{}  {:<12} {}{} {}",
+                        mode_letter(mode),
+                        key_names(&lhs),
+                        if mapping.noremap { '*' } else { ' ' },
+                        if local { '@' } else { ' ' },
+                        key_names(&mapping.rhs)
+                    ));
+                }
+            }
+        }
+        lines.sort();
+
+        if lines.is_empty() {
+            self.output
+                .status_message
+                .set_message("No mapping found".into());
+            return Ok(());
+        }
+        Output::clear_screen()?;
+        for (idx, line) in lines.iter().enumerate() {
+            print!("{}{}", if idx > 0 { "\r\n" } else { "" }, line);
+        }
+        stdout().flush()?;
+        self.press_enter()
+    }
+
+    /// `:[range]norm[al][!] {keys}` handles the keys as if they were typed in normal mode, once
+    /// for every line in the range with the cursor at its start. With the `!` mappings are
+    /// left out.
+    pub fn normal(&mut self, range: Option<ex::Range>, bang: bool, args: &str) -> io::Result<bool> {
+        if args.is_empty() {
+            self.output
+                .status_message
+                .set_error("Argument required".into());
+            return Ok(true);
+        }
+        if self.normal_depth >= MAX_NORMAL_DEPTH {
+            self.output
+                .status_message
+                .set_error("Recursive use of :normal too deep".into());
+            return Ok(true);
+        }
+
+        let keys = literal_keys(args);
+        // without a range they're handled once, wherever the cursor is
+        let lines: Vec<Option<usize>> = match range {
+            Some(range) => (range.start..=range.end).map(Some).collect(),
+            None => vec![None],
+        };
+        // whatever was typed ahead comes after, once these are done
+        let typeahead = std::mem::take(&mut self.typeahead);
+        let cursor = (
+            self.output.cursor_controller.cursor_x,
+            self.output.cursor_controller.cursor_y,
+        );
+        // undone in one go
+        let began = self.output.editor_rows.history.begin(cursor);
+        self.normal_depth += 1;
+        let mut result = Ok(true);
+        for y in lines {
+            if let Some(y) = y {
+                if y >= self.output.editor_rows.nr_of_rows() {
+                    break;
+                }
+                self.output.cursor_controller.cursor_y = y;
+                self.output.cursor_controller.cursor_x = 0;
+            }
+            result = self.run_normal_keys(&keys, !bang);
+            if !matches!(result, Ok(true)) {
+                break;
+            }
+        }
+        self.normal_depth -= 1;
+        if began {
+            self.output.editor_rows.history.end();
+        }
+        self.typeahead = typeahead;
+        result
+    }
+
+    fn run_normal_keys(&mut self, keys: &[KeyEvent], remap: bool) -> io::Result<bool> {
+        self.typeahead.extend(keys.iter().map(|event| Typed {
+            event: *event,
+            remap,
+            silent: false,
+        }));
+        // insert or visual mode left going gets ended like with an Esc
+        while !self.typeahead.is_empty() || self.output.mode != Mode::Normal {
+            if !self.process_keypress()? {
+                self.typeahead.clear();
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
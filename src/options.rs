@@ -0,0 +1,511 @@
+use crate::{Editor, Output};
+use std::{
+    env,
+    io::{self, stdout, Write},
+};
+
+/// What an option holds.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Bool(bool),
+    Number(usize),
+    String(String),
+    /// comma separated
+    List(Vec<String>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Bool,
+    Number,
+    String,
+    List,
+}
+
+/// Where an option's value lives. Buffer and window options also keep a global value, the one
+/// new buffers and windows start out with.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scope {
+    Global,
+    Buffer,
+    Window,
+}
+
+struct OptionDef {
+    name: &'static str,
+    short: &'static str,
+    scope: Scope,
+    kind: Kind,
+    default: &'static str,
+}
+
+const fn def(
+    name: &'static str,
+    short: &'static str,
+    scope: Scope,
+    kind: Kind,
+    default: &'static str,
+) -> OptionDef {
+    OptionDef {
+        name,
+        short,
+        scope,
+        kind,
+        default,
+    }
+}
+
+/// Every option, in the order `:set all` lists them.
+const OPTIONS: &[OptionDef] = &[
+    // Tab in insert mode and `:retab` put in spaces
+    def("expandtab", "et", Scope::Buffer, Kind::Bool, "false"),
+    // buffers with changes can be left for another one
+    def("hidden", "hid", Scope::Global, Kind::Bool, "false"),
+    def("hlsearch", "hls", Scope::Global, Kind::Bool, "true"),
+    def("ignorecase", "ic", Scope::Global, Kind::Bool, "false"),
+    def("incsearch", "is", Scope::Global, Kind::Bool, "true"),
+    def("list", "list", Scope::Window, Kind::Bool, "false"),
+    def("listchars", "lcs", Scope::Global, Kind::List, "eol:$"),
+    // what `<leader>` stands for in mappings
+    def("mapleader", "mapleader", Scope::Global, Kind::String, "\\"),
+    // seconds a message stays in the status bar
+    def("messagetime", "mt", Scope::Global, Kind::Number, "1"),
+    def("number", "nu", Scope::Window, Kind::Bool, "true"),
+    def("relativenumber", "rnu", Scope::Window, Kind::Bool, "true"),
+    def("shell", "sh", Scope::Global, Kind::String, "sh"),
+    def("smartcase", "scs", Scope::Global, Kind::Bool, "false"),
+    // an error in one buffer, window or entry stops `:bufdo` and the others going on to the rest
+    def("stoponerror", "soe", Scope::Global, Kind::Bool, "false"),
+    // 0 never shows the tabline, 1 when there's more than one tab page, 2 always
+    def("showtabline", "stal", Scope::Global, Kind::Number, "1"),
+    def("tabstop", "ts", Scope::Buffer, Kind::Number, "4"),
+    // the width `:center` and `:right` line text up in, 0 for 80
+    def("textwidth", "tw", Scope::Buffer, Kind::Number, "0"),
+    // milliseconds to wait for the rest of a mapping
+    def("timeoutlen", "tm", Scope::Global, Kind::Number, "1000"),
+];
+
+/// every option by its full name, for completing `:set`
+pub fn names() -> impl Iterator<Item = &'static str> {
+    OPTIONS.iter().map(|option| option.name)
+}
+
+fn find(name: &str) -> Option<usize> {
+    OPTIONS
+        .iter()
+        .position(|option| option.name == name || option.short == name)
+}
+
+fn index(name: &str) -> usize {
+    find(name).unwrap_or_else(|| unreachable!("{} is missing from options::OPTIONS", name))
+}
+
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+impl Value {
+    fn parse(kind: Kind, text: &str) -> Result<Self, String> {
+        Ok(match kind {
+            Kind::Bool => Value::Bool(text == "true"),
+            Kind::Number => Value::Number(
+                text.parse()
+                    .map_err(|_| format!("Number required after =: {}", text))?,
+            ),
+            Kind::String => Value::String(text.into()),
+            Kind::List => Value::List(split_list(text)),
+        })
+    }
+}
+
+/// A value for every option. Which of them count depends on where the set is kept.
+#[derive(Clone)]
+pub struct Options {
+    values: Vec<Value>,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        let mut options = Self {
+            values: OPTIONS
+                .iter()
+                .map(|option| Value::parse(option.kind, option.default).unwrap())
+                .collect(),
+        };
+        // like vim the shell comes from the environment
+        if let Ok(shell) = env::var("SHELL") {
+            options.values[index("shell")] = Value::String(shell);
+        }
+        options
+    }
+
+    pub fn get(&self, name: &str) -> &Value {
+        &self.values[index(name)]
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        matches!(self.get(name), Value::Bool(true))
+    }
+
+    pub fn number(&self, name: &str) -> usize {
+        match self.get(name) {
+            Value::Number(number) => *number,
+            _ => 0,
+        }
+    }
+
+    pub fn string(&self, name: &str) -> &str {
+        match self.get(name) {
+            Value::String(string) => string,
+            _ => "",
+        }
+    }
+
+    pub fn list(&self, name: &str) -> &[String] {
+        match self.get(name) {
+            Value::List(list) => list,
+            _ => &[],
+        }
+    }
+}
+
+/// how `:set` and `:set all` show an option
+fn show(idx: usize, value: &Value) -> String {
+    let name = OPTIONS[idx].name;
+    match value {
+        Value::Bool(true) => name.into(),
+        Value::Bool(false) => format!("no{}", name),
+        Value::Number(number) => format!("{}={}", name, number),
+        Value::String(string) => format!("{}={}", name, string),
+        Value::List(list) => format!("{}={}", name, list.join(",")),
+    }
+}
+
+/// The new value of `:set name+=arg`, `-=` and `^=`. Numbers add, subtract and multiply,
+/// strings and lists append, remove and prepend.
+fn modify(value: &Value, operator: char, arg: &str) -> Result<Value, String> {
+    let number = || {
+        arg.parse::<usize>()
+            .map_err(|_| format!("Number required after =: {}", arg))
+    };
+    Ok(match (value, operator) {
+        (Value::Number(old), '+') => Value::Number(old + number()?),
+        (Value::Number(old), '-') => Value::Number(old.saturating_sub(number()?)),
+        (Value::Number(old), _) => Value::Number(old * number()?),
+        (Value::String(old), '+') => Value::String(format!("{}{}", old, arg)),
+        (Value::String(old), '-') => Value::String(old.replacen(arg, "", 1)),
+        (Value::String(old), _) => Value::String(format!("{}{}", arg, old)),
+        (Value::List(old), '-') => {
+            let removed = split_list(arg);
+            Value::List(
+                old.iter()
+                    .filter(|item| !removed.contains(item))
+                    .cloned()
+                    .collect(),
+            )
+        }
+        (Value::List(old), _) => {
+            let added: Vec<String> = split_list(arg)
+                .into_iter()
+                .filter(|item| !old.contains(item))
+                .collect();
+            Value::List(if operator == '+' {
+                [old.clone(), added].concat()
+            } else {
+                [added, old.clone()].concat()
+            })
+        }
+        (Value::Bool(_), _) => unreachable!("booleans don't take an argument"),
+    })
+}
+
+/// Splits `:set` arguments at unescaped blanks, a backslash keeps a blank in the value.
+pub fn split_args(args: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = args.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => word.extend(chars.next()),
+            ch if ch.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            ch => word.push(ch),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// What a `:set` argument comes down to.
+#[derive(PartialEq, Debug)]
+enum Action {
+    /// show the option like this
+    Show(String),
+    /// give option `idx` this value
+    Set(usize, Value),
+}
+
+/// One `:set` argument: `name`, `noname`, `invname`, `name!`, `name?`, `name&`, or `name`
+/// followed by `=`, `:`, `+=`, `-=` or `^=` and a value. `current` gives the value an option
+/// has now.
+fn parse_arg(arg: &str, current: impl Fn(usize) -> Value) -> Result<Action, String> {
+    let unknown = || format!("Unknown option: {}", arg);
+    let invalid = || format!("Invalid argument: {}", arg);
+    let name_len = arg
+        .find(|ch: char| !ch.is_ascii_alphabetic())
+        .unwrap_or(arg.len());
+    let (name, rest) = arg.split_at(name_len);
+
+    // `no` and `inv` only go with booleans, so `nu` stays `number`
+    let (idx, prefix) = match find(name) {
+        Some(idx) => (idx, ""),
+        None => ["no", "inv"]
+            .iter()
+            .find_map(|prefix| {
+                let idx = find(name.strip_prefix(prefix)?)?;
+                (OPTIONS[idx].kind == Kind::Bool).then_some((idx, *prefix))
+            })
+            .ok_or_else(unknown)?,
+    };
+    let option = &OPTIONS[idx];
+    let value = current(idx);
+
+    Ok(match (rest, option.kind) {
+        ("?", _) => Action::Show(show(idx, &value)),
+        ("&", _) => Action::Set(idx, Options::new().values.swap_remove(idx)),
+        ("" | "!", Kind::Bool) => {
+            let on = match (prefix, rest) {
+                ("inv", _) | (_, "!") => value != Value::Bool(true),
+                ("no", _) => false,
+                _ => true,
+            };
+            Action::Set(idx, Value::Bool(on))
+        }
+        // asking for anything but a boolean shows it
+        ("", _) => Action::Show(show(idx, &value)),
+        (_, Kind::Bool) => return Err(invalid()),
+        _ => {
+            let (operator, arg) = match rest.chars().next() {
+                Some('=' | ':') => ('=', &rest[1..]),
+                Some(operator @ ('+' | '-' | '^')) => {
+                    (operator, rest[1..].strip_prefix('=').ok_or_else(invalid)?)
+                }
+                _ => return Err(invalid()),
+            };
+            let new = match operator {
+                '=' => Value::parse(option.kind, arg)?,
+                operator => modify(&value, operator, arg)?,
+            };
+            if option.name == "tabstop" && new == Value::Number(0) {
+                return Err(format!("Argument must be positive: {}", arg));
+            }
+            Action::Set(idx, new)
+        }
+    })
+}
+
+impl Output {
+    /// the value of the option that counts for the current buffer and window
+    pub fn option(&self, name: &str) -> &Value {
+        self.local_options(OPTIONS[index(name)].scope).get(name)
+    }
+
+    fn local_options(&self, scope: Scope) -> &Options {
+        match scope {
+            Scope::Global => &self.options,
+            Scope::Buffer => &self.editor_rows.options,
+            Scope::Window => &self.window_options,
+        }
+    }
+
+    /// Sets option `idx`, only for the current buffer or window when `local`. Whatever depends
+    /// on it gets brought up to date.
+    fn set_value(&mut self, idx: usize, value: Value, local: bool) {
+        let option = &OPTIONS[idx];
+        if !local || option.scope == Scope::Global {
+            self.options.values[idx] = value.clone();
+        }
+        match option.scope {
+            Scope::Global => {}
+            Scope::Buffer => self.editor_rows.options.values[idx] = value,
+            Scope::Window => self.window_options.values[idx] = value,
+        }
+
+        match option.name {
+            "tabstop" => self.editor_rows.render_all(),
+            "hlsearch" => self.search.no_highlight = false,
+            _ => {}
+        }
+        self.search.ignore_case = self.options.bool("ignorecase");
+        self.search.smart_case = self.options.bool("smartcase");
+        self.search.hlsearch = self.options.bool("hlsearch");
+        self.search.incsearch = self.options.bool("incsearch");
+    }
+
+    /// sets option `name` for the current buffer or window only, like `:setlocal`
+    pub fn set_local(&mut self, name: &str, value: Value) {
+        self.set_value(index(name), value, true);
+    }
+
+    /// Carries out one `:set` argument. Gives back what to show, if any.
+    fn set_arg(&mut self, arg: &str, local: bool) -> Result<Option<String>, String> {
+        match parse_arg(arg, |idx| self.option(OPTIONS[idx].name).clone())? {
+            Action::Show(shown) => Ok(Some(shown)),
+            Action::Set(idx, value) => {
+                self.set_value(idx, value, local);
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Editor {
+    /// `:set` and `:setlocal`. Without arguments they show the options that changed from their
+    /// default, `all` shows every one of them.
+    pub fn set(&mut self, args: &str, local: bool) -> io::Result<()> {
+        let args = split_args(args);
+        if args.is_empty() || args == ["all"] {
+            let defaults = Options::new();
+            let shown: Vec<String> = (0..OPTIONS.len())
+                .map(|idx| (idx, self.output.option(OPTIONS[idx].name)))
+                .filter(|(idx, value)| args.len() == 1 || **value != defaults.values[*idx])
+                .map(|(idx, value)| show(idx, value))
+                .collect();
+            return self.show_options(&shown);
+        }
+
+        let mut shown = Vec::new();
+        for arg in &args {
+            match self.output.set_arg(arg, local) {
+                Ok(Some(value)) => shown.push(value),
+                Ok(None) => {}
+                Err(error) => {
+                    self.output.status_message.set_error(error);
+                    return Ok(());
+                }
+            }
+        }
+        if !shown.is_empty() {
+            self.output.status_message.set_message(shown.join("  "));
+        }
+        Ok(())
+    }
+
+    /// Lists options in columns over the file, like vim does for `:set all`.
+    fn show_options(&mut self, shown: &[String]) -> io::Result<()> {
+        let width = self.output.win_size.0;
+        let column = shown.iter().map(|value| value.len()).max().unwrap_or(0) + 2;
+        let columns = (width / column).max(1);
+        let lines = shown.len().div_ceil(columns);
+
+        Output::clear_screen()?;
+        print!("--- Options ---");
+        for line in 0..lines {
+            print!("\r\n");
+            // down the columns first, like vim
+            for value in shown.iter().skip(line).step_by(lines) {
+                print!("{:<column$}", value, column = column);
+            }
+        }
+        stdout().flush()?;
+        self.press_enter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// what `:set arg` comes down to with every option at its default
+    fn set(arg: &str) -> Result<Action, String> {
+        let defaults = Options::new();
+        parse_arg(arg, |idx| defaults.values[idx].clone())
+    }
+
+    fn set_to(name: &str, value: Value) -> Result<Action, String> {
+        Ok(Action::Set(index(name), value))
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(set("ts=8"), set_to("tabstop", Value::Number(8)));
+        assert_eq!(set("tabstop:8"), set_to("tabstop", Value::Number(8)));
+        assert_eq!(set("ts+=2"), set_to("tabstop", Value::Number(6)));
+        assert_eq!(set("ts-=1"), set_to("tabstop", Value::Number(3)));
+        assert_eq!(set("ts^=3"), set_to("tabstop", Value::Number(12)));
+        assert_eq!(set("ts&"), set_to("tabstop", Value::Number(4)));
+        assert_eq!(set("ts?"), Ok(Action::Show("tabstop=4".into())));
+        // asking for a number shows it, no `?` needed
+        assert_eq!(set("ts"), Ok(Action::Show("tabstop=4".into())));
+    }
+
+    #[test]
+    fn bad_numbers() {
+        assert_eq!(set("ts=0"), Err("Argument must be positive: 0".into()));
+        assert_eq!(set("ts-=4"), Err("Argument must be positive: 4".into()));
+        assert_eq!(set("ts=x"), Err("Number required after =: x".into()));
+        assert_eq!(set("ts*=2"), Err("Invalid argument: ts*=2".into()));
+        assert_eq!(set("ts+2"), Err("Invalid argument: ts+2".into()));
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(set("list"), set_to("list", Value::Bool(true)));
+        assert_eq!(set("nonu"), set_to("number", Value::Bool(false)));
+        assert_eq!(set("invlist"), set_to("list", Value::Bool(true)));
+        assert_eq!(set("invnu"), set_to("number", Value::Bool(false)));
+        assert_eq!(set("list!"), set_to("list", Value::Bool(true)));
+        assert_eq!(set("list&"), set_to("list", Value::Bool(false)));
+        assert_eq!(set("list?"), Ok(Action::Show("nolist".into())));
+        assert_eq!(set("nu?"), Ok(Action::Show("number".into())));
+        assert_eq!(set("list=1"), Err("Invalid argument: list=1".into()));
+    }
+
+    #[test]
+    fn unknown_options() {
+        assert_eq!(set("xyz"), Err("Unknown option: xyz".into()));
+        // `no` only goes with booleans
+        assert_eq!(set("nots"), Err("Unknown option: nots".into()));
+    }
+
+    #[test]
+    fn lists() {
+        let list =
+            |items: &[&str]| Value::List(items.iter().map(|item| item.to_string()).collect());
+        assert_eq!(
+            set("lcs+=tab:>-"),
+            set_to("listchars", list(&["eol:$", "tab:>-"]))
+        );
+        assert_eq!(
+            set("lcs^=tab:>-"),
+            set_to("listchars", list(&["tab:>-", "eol:$"]))
+        );
+        assert_eq!(set("lcs-=eol:$"), set_to("listchars", list(&[])));
+        // what's in the list already isn't added twice
+        assert_eq!(set("lcs+=eol:$"), set_to("listchars", list(&["eol:$"])));
+        assert_eq!(set("lcs=a,,b"), set_to("listchars", list(&["a", "b"])));
+    }
+
+    #[test]
+    fn strings() {
+        let shell = |value: &str| Value::String(value.into());
+        assert_eq!(modify(&shell("sh"), '+', " -l"), Ok(shell("sh -l")));
+        assert_eq!(modify(&shell("sh -l"), '-', " -l"), Ok(shell("sh")));
+        assert_eq!(modify(&shell("sh"), '^', "/bin/"), Ok(shell("/bin/sh")));
+    }
+
+    #[test]
+    fn splitting_args() {
+        assert_eq!(split_args("ts=8  nu "), ["ts=8", "nu"]);
+        assert_eq!(split_args("sh=my\\ shell list"), ["sh=my shell", "list"]);
+        assert!(split_args("").is_empty());
+    }
+}
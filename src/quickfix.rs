@@ -0,0 +1,214 @@
+use crate::options::split_args;
+use crate::regex::Haystack;
+use crate::substitute::split_delimited;
+use crate::{file, Editor, EditorRows, Output};
+use std::{
+    io::{self, stdout, Write},
+    path::PathBuf,
+};
+
+/// A place in a file the quickfix list points at.
+pub struct Entry {
+    pub path: PathBuf,
+    line: usize,
+    col: usize,
+    text: String,
+}
+
+/// What `:vimgrep` found, with the entry `:cc` and the rest are at.
+#[derive(Default)]
+pub struct QuickFix {
+    pub entries: Vec<Entry>,
+    idx: usize,
+}
+
+impl Editor {
+    /// `:vim[grep][!] /{pattern}/[g][j] {file} ...` puts the lines of the files that match in
+    /// the quickfix list and goes to the first one, unless there's a `j`. With a `g` every match
+    /// gets an entry instead of every line. The pattern can go without the `/` when it has no
+    /// blanks in it.
+    pub fn vimgrep(&mut self, bang: bool, args: &str) {
+        let args = args.trim_start();
+        let (pattern, rest) = match args.chars().next() {
+            Some(ch) if !(ch.is_alphanumeric() || "\\\"|".contains(ch)) => {
+                let (pattern, rest) = split_delimited(&args[ch.len_utf8()..], ch);
+                (pattern, rest.unwrap_or(""))
+            }
+            _ => match args.split_once(char::is_whitespace) {
+                Some((pattern, rest)) => (pattern.to_string(), rest),
+                None => (args.to_string(), ""),
+            },
+        };
+        let flags_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (flags, files) = rest.split_at(flags_len);
+        if let Some(flag) = flags.chars().find(|ch| !"gj".contains(*ch)) {
+            self.output
+                .status_message
+                .set_error(format!("Invalid argument: {}", flag));
+            return;
+        }
+        let words = split_args(files);
+        if words.is_empty() {
+            self.output
+                .status_message
+                .set_error("File name missing or invalid pattern".into());
+            return;
+        }
+
+        let pattern = match (pattern.is_empty(), &self.output.search.pattern) {
+            (false, _) => pattern,
+            (true, Some(previous)) => previous.clone(),
+            (true, None) => {
+                self.output
+                    .status_message
+                    .set_error("No previous regular expression".into());
+                return;
+            }
+        };
+        let regex = match self.output.search.regex(&pattern) {
+            Ok(regex) => regex,
+            Err(error) => {
+                self.output.status_message.set_error(error.to_string());
+                return;
+            }
+        };
+        self.output.search.set_pattern(pattern.clone());
+
+        let mut entries = Vec::new();
+        for path in words.iter().flat_map(|word| file::glob(word)) {
+            if path.is_dir() {
+                continue;
+            }
+            // a buffer that's open has its changes searched too
+            let lines = match self.find_buffer(&path).and_then(|nr| self.buffer(nr)) {
+                Some((editor_rows, ..)) => (0..editor_rows.nr_of_rows())
+                    .filter_map(|y| editor_rows.line(y).map(String::from))
+                    .collect(),
+                None => match EditorRows::read_lines(&path) {
+                    Ok(lines) => lines,
+                    Err(_) => continue,
+                },
+            };
+            for (y, line) in lines.iter().enumerate() {
+                let matches = match flags.contains('g') {
+                    true => regex.find_iter(line.as_str(), 0),
+                    false => regex.find_at(line.as_str(), 0, 0).into_iter().collect(),
+                };
+                entries.extend(matches.into_iter().map(|m| Entry {
+                    path: path.clone(),
+                    line: y,
+                    col: m.start().col,
+                    text: line.trim_start().to_string(),
+                }));
+            }
+        }
+        if entries.is_empty() {
+            self.output
+                .status_message
+                .set_error(format!("No match: {}", pattern));
+            return;
+        }
+
+        self.quickfix = QuickFix { entries, idx: 0 };
+        if flags.contains('j') {
+            let count = self.quickfix.entries.len();
+            self.output
+                .status_message
+                .set_message(format!("(1 of {})", count));
+        } else {
+            self.go_to_entry(0, bang);
+        }
+    }
+
+    /// Goes to the place quickfix entry `idx` points at, editing its file if it's another one.
+    pub fn go_to_entry(&mut self, idx: usize, bang: bool) -> bool {
+        let Some(entry) = self.quickfix.entries.get(idx) else {
+            return false;
+        };
+        let (path, line, col) = (entry.path.clone(), entry.line, entry.col);
+        let current = self.output.editor_rows.file_name.as_ref() == Some(&path);
+        if !current && !self.edit_path(bang, path) {
+            return false;
+        }
+        self.quickfix.idx = idx;
+        self.output.set_cursor(col, line);
+        let entries = &self.quickfix.entries;
+        let message = format!("({} of {}): {}", idx + 1, entries.len(), entries[idx].text);
+        self.output.status_message.set_message(message);
+        true
+    }
+
+    /// whether there's anything in the quickfix list, saying there isn't when there's not
+    fn has_entries(&mut self) -> bool {
+        if self.quickfix.entries.is_empty() {
+            self.output.status_message.set_error("No Errors".into());
+            return false;
+        }
+        true
+    }
+
+    /// `:cc[!] [nr]`, without a number it goes to the current entry again
+    pub fn current_entry(&mut self, bang: bool, args: &str) {
+        if !self.has_entries() {
+            return;
+        }
+        let idx = match args.trim() {
+            "" => self.quickfix.idx,
+            arg => match arg.parse::<usize>() {
+                Ok(nr) if nr > 0 => nr.min(self.quickfix.entries.len()) - 1,
+                _ => {
+                    self.output
+                        .status_message
+                        .set_error(format!("Invalid argument: {}", arg));
+                    return;
+                }
+            },
+        };
+        self.go_to_entry(idx, bang);
+    }
+
+    /// `:cn[ext][!]` and, `backwards`, `:cp[revious][!]` or `:cN[ext][!]`
+    pub fn cycle_entry(&mut self, bang: bool, backwards: bool) {
+        if !self.has_entries() {
+            return;
+        }
+        let idx = self.quickfix.idx;
+        let next = match backwards {
+            true => idx.checked_sub(1),
+            false => Some(idx + 1).filter(|next| *next < self.quickfix.entries.len()),
+        };
+        match next {
+            Some(next) => {
+                self.go_to_entry(next, bang);
+            }
+            None => self.output.status_message.set_error("No more items".into()),
+        }
+    }
+
+    /// `:cl[ist]`
+    pub fn list_entries(&mut self) -> io::Result<()> {
+        if !self.has_entries() {
+            return Ok(());
+        }
+        Output::clear_screen()?;
+        let lines: Vec<String> = self
+            .quickfix
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| {
+                format!(
+                    "{:>3} {}:{} col {}: {}",
+                    idx + 1,
+                    entry.path.display(),
+                    entry.line + 1,
+                    entry.col + 1,
+                    entry.text
+                )
+            })
+            .collect();
+        print!("{}", lines.join("\r\n"));
+        stdout().flush()?;
+        self.press_enter()
+    }
+}
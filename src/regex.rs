@@ -0,0 +1,1427 @@
+// vim flavoured regular expressions, compiled to a small program and run on a pike VM so
+// matching stays linear in the length of the text no matter what the pattern looks like
+use std::fmt;
+
+/// keeps `\{n,m}` from blowing the program up to millions of instructions
+const MAX_PROGRAM_LEN: usize = 20_000;
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn error<T>(msg: &str) -> Result<T, Error> {
+    Err(Error(msg.into()))
+}
+
+/// Anything that can hand out lines of text, matches are allowed to run past the end of a line
+/// into the next one through `\n`.
+pub trait Haystack {
+    fn line(&self, idx: usize) -> Option<&str>;
+}
+
+impl Haystack for str {
+    fn line(&self, idx: usize) -> Option<&str> {
+        (idx == 0).then_some(self)
+    }
+}
+
+impl Haystack for [String] {
+    fn line(&self, idx: usize) -> Option<&str> {
+        self.get(idx).map(String::as_str)
+    }
+}
+
+/// `col` is a byte offset into the line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Pos {
+    pub fn new(line: usize, col: usize) -> Self {
+        Self { line, col }
+    }
+}
+
+#[derive(Debug)]
+pub struct Match {
+    slots: Box<[Option<Pos>]>,
+}
+
+impl Match {
+    pub fn start(&self) -> Pos {
+        self.slots[0].unwrap()
+    }
+
+    pub fn end(&self) -> Pos {
+        self.slots[1].unwrap()
+    }
+
+    /// `\(...\)` capture `idx`, 0 being the whole match
+    pub fn group(&self, idx: usize) -> Option<(Pos, Pos)> {
+        match (self.slots.get(idx * 2)?, self.slots.get(idx * 2 + 1)?) {
+            (Some(start), Some(end)) if start <= end => Some((*start, *end)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum MagicLevel {
+    VeryNomagic,
+    Nomagic,
+    Magic,
+    VeryMagic,
+}
+
+#[derive(Clone, Debug)]
+enum ClassItem {
+    Range(char, char),
+    Named(fn(char) -> bool),
+}
+
+#[derive(Clone, Debug)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+    newline: bool,
+}
+
+impl Class {
+    fn named(f: fn(char) -> bool, negated: bool) -> Self {
+        Self {
+            items: vec![ClassItem::Named(f)],
+            negated,
+            newline: false,
+        }
+    }
+
+    fn contains(&self, ch: char, ignore_case: bool) -> bool {
+        if ch == '\n' {
+            return self.newline;
+        }
+        let test = |c: char| {
+            self.items.iter().any(|item| match item {
+                ClassItem::Range(from, to) => (*from..=*to).contains(&c),
+                ClassItem::Named(f) => f(c),
+            })
+        };
+        let found = test(ch)
+            || (ignore_case && (ch.to_lowercase().any(test) || ch.to_uppercase().any(test)));
+        found != self.negated
+    }
+}
+
+pub fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn is_ident_head(ch: char) -> bool {
+    ch.is_alphabetic() || ch == '_'
+}
+
+fn is_octal(ch: char) -> bool {
+    ('0'..='7').contains(&ch)
+}
+
+fn is_lower(ch: char) -> bool {
+    ch.is_lowercase()
+}
+
+fn is_upper(ch: char) -> bool {
+    ch.is_uppercase()
+}
+
+fn is_blank(ch: char) -> bool {
+    ch == ' ' || ch == '\t'
+}
+
+fn is_space(ch: char) -> bool {
+    ch.is_whitespace()
+}
+
+fn is_filename_char(ch: char) -> bool {
+    is_word_char(ch) || "/.-+,#$%~=".contains(ch)
+}
+
+fn is_printable(ch: char) -> bool {
+    !ch.is_control()
+}
+
+/// `[:name:]` inside a collection
+fn posix_class(name: &str) -> Option<fn(char) -> bool> {
+    Some(match name {
+        "alnum" => char::is_alphanumeric,
+        "alpha" => char::is_alphabetic,
+        "blank" => is_blank,
+        "cntrl" => char::is_control,
+        "digit" => |c: char| c.is_ascii_digit(),
+        "graph" => |c: char| !c.is_control() && !c.is_whitespace(),
+        "lower" => is_lower,
+        "print" => is_printable,
+        "punct" => |c: char| c.is_ascii_punctuation(),
+        "space" => is_space,
+        "upper" => is_upper,
+        "xdigit" => |c: char| c.is_ascii_hexdigit(),
+        "return" => |c: char| c == '\r',
+        "tab" => |c: char| c == '\t',
+        "escape" => |c: char| c == '\x1b',
+        "backspace" => |c: char| c == '\x08',
+        "ident" => is_word_char,
+        "keyword" => is_word_char,
+        "fname" => is_filename_char,
+        _ => return None,
+    })
+}
+
+/// the single letter classes, `\s`, `\d`, `\w` and friends
+fn escape_class(ch: char) -> Option<Class> {
+    let (f, negated): (fn(char) -> bool, bool) = match ch {
+        's' => (is_blank, false),
+        'S' => (is_blank, true),
+        'd' => (|c: char| c.is_ascii_digit(), false),
+        'D' => (|c: char| c.is_ascii_digit(), true),
+        'w' => (is_word_char, false),
+        'W' => (is_word_char, true),
+        'a' => (char::is_alphabetic, false),
+        'A' => (char::is_alphabetic, true),
+        'l' => (is_lower, false),
+        'L' => (is_lower, true),
+        'u' => (is_upper, false),
+        'U' => (is_upper, true),
+        'x' => (|c: char| c.is_ascii_hexdigit(), false),
+        'X' => (|c: char| c.is_ascii_hexdigit(), true),
+        'o' => (is_octal, false),
+        'O' => (is_octal, true),
+        'h' => (is_ident_head, false),
+        'H' => (is_ident_head, true),
+        'i' | 'k' => (is_word_char, false),
+        'I' | 'K' => (|c: char| is_word_char(c) && !c.is_ascii_digit(), false),
+        'f' => (is_filename_char, false),
+        'F' => (|c: char| is_filename_char(c) && !c.is_ascii_digit(), false),
+        'p' => (is_printable, false),
+        'P' => (|c: char| is_printable(c) && !c.is_ascii_digit(), false),
+        _ => return None,
+    };
+    Some(Class::named(f, negated))
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Empty,
+    Char(char),
+    Any {
+        newline: bool,
+    },
+    Class(Class),
+    Bol,
+    Eol,
+    WordStart,
+    WordEnd,
+    MatchStart,
+    MatchEnd,
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+/// what a pattern character turned into once the current magic level was applied
+#[derive(Clone, Copy, PartialEq)]
+enum Token {
+    Meta(char),
+    Escape(char),
+    Literal(char),
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    magic: MagicLevel,
+    ignore_case: Option<bool>,
+    groups: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(pattern: &'a str) -> Self {
+        Self {
+            chars: pattern.chars().peekable(),
+            magic: MagicLevel::Magic,
+            ignore_case: None,
+            groups: 1,
+        }
+    }
+
+    fn is_meta(&self, ch: char, escaped: bool) -> bool {
+        match ch {
+            '(' | ')' | '|' | '+' | '?' | '=' | '{' | '@' | '<' | '>' | '%' | '&' => {
+                (self.magic == MagicLevel::VeryMagic) != escaped
+            }
+            '.' | '*' | '[' | '~' => (self.magic >= MagicLevel::Magic) != escaped,
+            '^' | '$' => (self.magic > MagicLevel::VeryNomagic) != escaped,
+            _ => false,
+        }
+    }
+
+    /// Reads one pattern character, also swallowing the `\v`, `\c`... switches which don't
+    /// produce anything themselves.
+    fn next_token(&mut self) -> Result<Option<Token>, Error> {
+        loop {
+            let Some(ch) = self.chars.next() else {
+                return Ok(None);
+            };
+            if ch != '\\' {
+                return Ok(Some(if self.is_meta(ch, false) {
+                    Token::Meta(ch)
+                } else {
+                    Token::Literal(ch)
+                }));
+            }
+
+            let Some(escaped) = self.chars.next() else {
+                return error("Trailing backslash");
+            };
+            match escaped {
+                'v' => self.magic = MagicLevel::VeryMagic,
+                'm' => self.magic = MagicLevel::Magic,
+                'M' => self.magic = MagicLevel::Nomagic,
+                'V' => self.magic = MagicLevel::VeryNomagic,
+                'c' => self.ignore_case = Some(true),
+                'C' => self.ignore_case = Some(false),
+                ch if self.is_meta(ch, true) => return Ok(Some(Token::Meta(ch))),
+                ch if ch.is_ascii_alphanumeric() || ch == '_' => {
+                    return Ok(Some(Token::Escape(ch)))
+                }
+                ch => return Ok(Some(Token::Literal(ch))),
+            }
+        }
+    }
+
+    fn peek_token(&mut self) -> Result<Option<Token>, Error> {
+        // tokens depend on the magic level, so peeking works on a copy of the parser state
+        let chars = self.chars.clone();
+        let (magic, ignore_case) = (self.magic, self.ignore_case);
+        let token = self.next_token();
+        self.chars = chars;
+        self.magic = magic;
+        self.ignore_case = ignore_case;
+        token
+    }
+
+    fn parse(&mut self) -> Result<Node, Error> {
+        let node = self.parse_alt()?;
+        match self.next_token()? {
+            None => Ok(node),
+            Some(Token::Meta(')')) => error("Unmatched \\)"),
+            Some(_) => error("Invalid pattern"),
+        }
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, Error> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek_token()? == Some(Token::Meta('|')) {
+            self.next_token()?;
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alt(branches)
+        })
+    }
+
+    fn at_branch_end(&mut self) -> Result<bool, Error> {
+        Ok(matches!(
+            self.peek_token()?,
+            None | Some(Token::Meta('|')) | Some(Token::Meta(')')) | Some(Token::Meta('&'))
+        ))
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, Error> {
+        let mut nodes: Vec<Node> = Vec::new();
+
+        loop {
+            let token = match self.peek_token()? {
+                None | Some(Token::Meta('|')) | Some(Token::Meta(')')) => break,
+                Some(token) => token,
+            };
+            self.next_token()?;
+
+            let atom = match token {
+                Token::Meta('^') if nodes.is_empty() => Node::Bol,
+                Token::Meta('$') if self.at_branch_end()? => Node::Eol,
+                Token::Meta('*') if nodes.is_empty() => Node::Char('*'),
+                Token::Meta('*' | '+' | '=' | '?' | '{') => {
+                    return error(&format!("{} follows nothing", self.describe(token)))
+                }
+                Token::Meta('.') => Node::Any { newline: false },
+                Token::Meta('[') => self.parse_collection(false)?,
+                Token::Meta('~') => Node::Char('~'),
+                Token::Meta('(') => {
+                    let idx = self.groups;
+                    self.groups += 1;
+                    let inner = self.parse_alt()?;
+                    if self.next_token()? != Some(Token::Meta(')')) {
+                        return error("Unmatched \\(");
+                    }
+                    Node::Group(Box::new(inner), Some(idx))
+                }
+                Token::Meta('%') => self.parse_percent()?,
+                Token::Meta('<') => Node::WordStart,
+                Token::Meta('>') => Node::WordEnd,
+                Token::Meta('@') => return error("Look-around (\\@) is not supported"),
+                Token::Meta('&') => return error("Branch concatenation (\\&) is not supported"),
+                Token::Meta(ch) => Node::Char(ch),
+                Token::Escape(ch) => self.parse_escape(ch)?,
+                Token::Literal(ch) => Node::Char(ch),
+            };
+
+            nodes.push(self.parse_multi(atom)?);
+        }
+
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn describe(&self, token: Token) -> String {
+        match token {
+            Token::Meta(ch) if self.is_meta(ch, false) => ch.to_string(),
+            Token::Meta(ch) | Token::Escape(ch) | Token::Literal(ch) => format!("\\{}", ch),
+        }
+    }
+
+    fn parse_multi(&mut self, atom: Node) -> Result<Node, Error> {
+        let (min, max, greedy) = match self.peek_token()? {
+            Some(Token::Meta('*')) => (0, None, true),
+            Some(Token::Meta('+')) => (1, None, true),
+            Some(Token::Meta('=' | '?')) => (0, Some(1), true),
+            Some(Token::Meta('{')) => {
+                self.next_token()?;
+                let (min, max, greedy) = self.parse_brace()?;
+                return self.finish_multi(atom, min, max, greedy);
+            }
+            _ => return Ok(atom),
+        };
+        self.next_token()?;
+        self.finish_multi(atom, min, max, greedy)
+    }
+
+    fn finish_multi(
+        &mut self,
+        atom: Node,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    ) -> Result<Node, Error> {
+        if let Some(token @ Token::Meta('*' | '+' | '=' | '?' | '{')) = self.peek_token()? {
+            return error(&format!("Nested multi: {}", self.describe(token)));
+        }
+        if matches!(
+            atom,
+            Node::Bol
+                | Node::Eol
+                | Node::WordStart
+                | Node::WordEnd
+                | Node::MatchStart
+                | Node::MatchEnd
+        ) {
+            return Ok(atom);
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// the inside of `\{n,m}`, `\{-n,m}` is the lazy version
+    fn parse_brace(&mut self) -> Result<(u32, Option<u32>, bool), Error> {
+        let mut body = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => break,
+                Some('\\') if self.chars.peek() == Some(&'}') => {
+                    self.chars.next();
+                    break;
+                }
+                Some(ch) => body.push(ch),
+                None => return error("Missing } after \\{"),
+            }
+        }
+
+        let (greedy, body) = match body.strip_prefix('-') {
+            Some(rest) => (false, rest),
+            None => (true, body.as_str()),
+        };
+        let number = |s: &str| -> Result<Option<u32>, Error> {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                s.parse::<u32>()
+                    .map(Some)
+                    .or_else(|_| error("Syntax error in \\{...}"))
+            }
+        };
+
+        let (min, max) = match body.split_once(',') {
+            None => {
+                let n = number(body)?;
+                (n.unwrap_or(0), n)
+            }
+            Some((min, max)) => (number(min)?.unwrap_or(0), number(max)?),
+        };
+        match max {
+            Some(max) if max < min => Ok((max, Some(min), greedy)),
+            _ => Ok((min, max, greedy)),
+        }
+    }
+
+    fn parse_percent(&mut self) -> Result<Node, Error> {
+        match self.chars.next() {
+            Some('(') => {
+                let inner = self.parse_alt()?;
+                if self.next_token()? != Some(Token::Meta(')')) {
+                    return error("Unmatched \\%(");
+                }
+                Ok(Node::Group(Box::new(inner), None))
+            }
+            Some('d') => self.parse_codepoint(10),
+            Some('x') | Some('u') | Some('U') => self.parse_codepoint(16),
+            Some('o') => self.parse_codepoint(8),
+            _ => error("Invalid character after \\%"),
+        }
+    }
+
+    fn parse_codepoint(&mut self, radix: u32) -> Result<Node, Error> {
+        let mut digits = String::new();
+        while let Some(&ch) = self.chars.peek() {
+            if !ch.is_digit(radix) {
+                break;
+            }
+            digits.push(ch);
+            self.chars.next();
+        }
+        u32::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Node::Char)
+            .ok_or_else(|| Error("Invalid character code".into()))
+    }
+
+    fn parse_escape(&mut self, ch: char) -> Result<Node, Error> {
+        if let Some(class) = escape_class(ch) {
+            return Ok(Node::Class(class));
+        }
+        Ok(match ch {
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            'e' => Node::Char('\x1b'),
+            'r' => Node::Char('\r'),
+            'b' => Node::Char('\x08'),
+            'z' => match self.chars.next() {
+                Some('s') => Node::MatchStart,
+                Some('e') => Node::MatchEnd,
+                _ => return error("Invalid character after \\z"),
+            },
+            '_' => match self.chars.next() {
+                Some('.') => Node::Any { newline: true },
+                Some('^') => Node::Bol,
+                Some('$') => Node::Eol,
+                Some('[') => self.parse_collection(true)?,
+                Some(ch) => match escape_class(ch) {
+                    Some(mut class) => {
+                        class.newline = true;
+                        Node::Class(class)
+                    }
+                    None => return error("Invalid use of \\_"),
+                },
+                None => return error("Invalid use of \\_"),
+            },
+            '1'..='9' => return error("Backreferences in patterns are not supported"),
+            ch => Node::Char(ch),
+        })
+    }
+
+    /// `[...]`, when there's no closing `]` the `[` is taken literally like vim does
+    fn parse_collection(&mut self, newline: bool) -> Result<Node, Error> {
+        let saved = self.chars.clone();
+        let mut class = Class {
+            items: Vec::new(),
+            negated: false,
+            newline,
+        };
+
+        if self.chars.peek() == Some(&'^') {
+            self.chars.next();
+            class.negated = true;
+        }
+        // a `]` right at the start is part of the collection
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            class.items.push(ClassItem::Range(']', ']'));
+        }
+
+        loop {
+            let ch = match self.chars.next() {
+                None => {
+                    if newline {
+                        return error("Missing ] after \\_[");
+                    }
+                    self.chars = saved;
+                    return Ok(Node::Char('['));
+                }
+                Some(']') => break,
+                Some('[') if self.chars.peek() == Some(&':') => {
+                    let rest: String = self.chars.clone().collect();
+                    match rest[1..].split_once(":]") {
+                        Some((name, _)) if posix_class(name).is_some() => {
+                            class
+                                .items
+                                .push(ClassItem::Named(posix_class(name).unwrap()));
+                            (0..name.len() + 3).for_each(|_| {
+                                self.chars.next();
+                            });
+                            continue;
+                        }
+                        _ => '[',
+                    }
+                }
+                Some('\\') => match self.chars.next() {
+                    Some('n') => {
+                        class.newline = true;
+                        continue;
+                    }
+                    Some('t') => '\t',
+                    Some('e') => '\x1b',
+                    Some('r') => '\r',
+                    Some('b') => '\x08',
+                    Some(ch @ ('\\' | ']' | '^' | '-')) => ch,
+                    Some(ch) => match escape_class(ch) {
+                        Some(escaped) if !escaped.negated => {
+                            class.items.extend(escaped.items);
+                            continue;
+                        }
+                        _ => {
+                            class.items.push(ClassItem::Range('\\', '\\'));
+                            ch
+                        }
+                    },
+                    None => return error("Trailing backslash"),
+                },
+                Some(ch) => ch,
+            };
+
+            let mut lookahead = self.chars.clone();
+            if lookahead.next() == Some('-') {
+                if let Some(to) = lookahead.next().filter(|&to| to != ']') {
+                    if to < ch {
+                        return error("Reverse range in character class");
+                    }
+                    self.chars.next();
+                    self.chars.next();
+                    class.items.push(ClassItem::Range(ch, to));
+                    continue;
+                }
+            }
+            class.items.push(ClassItem::Range(ch, ch));
+        }
+
+        Ok(Node::Class(class))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Inst {
+    Char(char),
+    Any {
+        newline: bool,
+    },
+    Class(Class),
+    Bol,
+    Eol,
+    WordStart,
+    WordEnd,
+    Save(usize),
+    /// both branches are taken, the first one has priority
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+impl Node {
+    fn multiline(&self) -> bool {
+        match self {
+            Node::Char(ch) => *ch == '\n',
+            Node::Any { newline } => *newline,
+            Node::Class(class) => class.newline,
+            Node::Group(node, _) | Node::Repeat { node, .. } => node.multiline(),
+            Node::Concat(nodes) | Node::Alt(nodes) => nodes.iter().any(Node::multiline),
+            _ => false,
+        }
+    }
+
+    /// the longest bit of literal text that every match has to contain
+    fn must(&self) -> String {
+        match self {
+            Node::Char(ch) => ch.to_string(),
+            Node::Group(node, _) => node.must(),
+            Node::Repeat { node, min, .. } if *min > 0 => node.must(),
+            Node::Concat(nodes) => {
+                let mut longest = String::new();
+                let mut run = String::new();
+                for node in nodes {
+                    match node {
+                        Node::Char(ch) => run.push(*ch),
+                        // zero width, they don't break up a run of literal chars
+                        Node::Bol | Node::Eol | Node::WordStart | Node::WordEnd => {}
+                        node => {
+                            let must = node.must();
+                            if run.len() > longest.len() {
+                                longest = std::mem::take(&mut run);
+                            }
+                            run.clear();
+                            if must.len() > longest.len() {
+                                longest = must;
+                            }
+                        }
+                    }
+                }
+                if run.len() > longest.len() {
+                    longest = run;
+                }
+                longest
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> Result<usize, Error> {
+        if self.prog.len() >= MAX_PROGRAM_LEN {
+            return error("Pattern too complex");
+        }
+        self.prog.push(inst);
+        Ok(self.prog.len() - 1)
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), Error> {
+        match node {
+            Node::Empty => {}
+            Node::Char(ch) => {
+                self.push(Inst::Char(*ch))?;
+            }
+            Node::Any { newline } => {
+                self.push(Inst::Any { newline: *newline })?;
+            }
+            Node::Class(class) => {
+                self.push(Inst::Class(class.clone()))?;
+            }
+            Node::Bol => {
+                self.push(Inst::Bol)?;
+            }
+            Node::Eol => {
+                self.push(Inst::Eol)?;
+            }
+            Node::WordStart => {
+                self.push(Inst::WordStart)?;
+            }
+            Node::WordEnd => {
+                self.push(Inst::WordEnd)?;
+            }
+            Node::MatchStart => {
+                self.push(Inst::Save(0))?;
+            }
+            Node::MatchEnd => {
+                self.push(Inst::Save(1))?;
+            }
+            Node::Group(inner, idx) => match idx {
+                Some(idx) => {
+                    self.push(Inst::Save(idx * 2))?;
+                    self.compile(inner)?;
+                    self.push(Inst::Save(idx * 2 + 1))?;
+                }
+                None => self.compile(inner)?,
+            },
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alt(branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i == branches.len() - 1 {
+                        self.compile(branch)?;
+                    } else {
+                        let split = self.push(Inst::Split(0, 0))?;
+                        self.compile(branch)?;
+                        jumps.push(self.push(Inst::Jmp(0))?);
+                        self.prog[split] = Inst::Split(split + 1, self.prog.len());
+                    }
+                }
+                let end = self.prog.len();
+                for jump in jumps {
+                    self.prog[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                match max {
+                    None => {
+                        let split = self.push(Inst::Split(0, 0))?;
+                        self.compile(node)?;
+                        self.push(Inst::Jmp(split))?;
+                        self.patch_split(split, *greedy);
+                    }
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0))?);
+                            self.compile(node)?;
+                        }
+                        let end = self.prog.len();
+                        for split in splits {
+                            self.prog[split] = if *greedy {
+                                Inst::Split(split + 1, end)
+                            } else {
+                                Inst::Split(end, split + 1)
+                            };
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn patch_split(&mut self, split: usize, greedy: bool) {
+        let end = self.prog.len();
+        self.prog[split] = if greedy {
+            Inst::Split(split + 1, end)
+        } else {
+            Inst::Split(end, split + 1)
+        };
+    }
+}
+
+pub struct Regex {
+    prog: Vec<Inst>,
+    slots: usize,
+    ignore_case: bool,
+    /// literal text every match has to start with, lets the search skip straight to candidates
+    prefix: String,
+    /// literal text every match has to contain somewhere, lines without it get skipped
+    must: String,
+    anchored: bool,
+    first: Option<FirstChars>,
+    /// whether there's any `^`, `$`, `\<` or `\>` that needs to look at the surrounding text
+    assertions: bool,
+}
+
+/// what the text around a position looks like, for the zero width instructions
+struct Context {
+    prev: Option<char>,
+    next: Option<char>,
+    bol: bool,
+    eol: bool,
+}
+
+/// The chars a match can start with, when the pattern can't match the empty string. The search
+/// skips over everything else without starting up the VM.
+struct FirstChars {
+    ascii: [bool; 128],
+    newline: bool,
+    insts: Vec<usize>,
+}
+
+impl FirstChars {
+    fn can_start(&self, regex: &Regex, text: &str, col: usize) -> bool {
+        match text[col..].chars().next() {
+            Some(ch) if ch.is_ascii() => self.ascii[ch as usize],
+            Some(ch) => self.insts.iter().any(|&pc| regex.step(&regex.prog[pc], ch)),
+            None => self.newline,
+        }
+    }
+
+    /// next byte offset that might start a match, non ascii chars are left for `can_start`
+    fn skip(&self, text: &str, col: usize) -> usize {
+        text.as_bytes()[col..]
+            .iter()
+            .position(|&b| b >= 0xC0 || (b < 128 && self.ascii[b as usize]))
+            .map_or(text.len(), |offset| col + offset)
+    }
+}
+
+enum Frame {
+    Explore(usize),
+    Restore(usize, Option<Pos>),
+}
+
+/// The threads alive at one position, their captures live side by side in one flat vec so
+/// stepping the VM never allocates.
+struct Threads {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+    caps: Vec<Option<Pos>>,
+}
+
+impl Threads {
+    fn new(size: usize, slots: usize) -> Self {
+        Self {
+            dense: Vec::with_capacity(size),
+            sparse: vec![0; size],
+            caps: vec![None; size * slots],
+        }
+    }
+
+    fn contains(&self, pc: usize) -> bool {
+        let idx = self.sparse[pc];
+        idx < self.dense.len() && self.dense[idx] == pc
+    }
+
+    fn insert(&mut self, pc: usize) {
+        self.sparse[pc] = self.dense.len();
+        self.dense.push(pc);
+    }
+}
+
+/// scratch space for one search, reused between the matches of a line
+struct Cache {
+    clist: Threads,
+    nlist: Threads,
+    stack: Vec<Frame>,
+    scratch: Vec<Option<Pos>>,
+}
+
+impl Cache {
+    fn new(regex: &Regex) -> Self {
+        Self {
+            clist: Threads::new(regex.prog.len(), regex.slots),
+            nlist: Threads::new(regex.prog.len(), regex.slots),
+            stack: Vec::new(),
+            scratch: vec![None; regex.slots],
+        }
+    }
+}
+
+fn fold(ch: char) -> char {
+    ch.to_lowercase().next().unwrap_or(ch)
+}
+
+impl Regex {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self, Error> {
+        let mut parser = Parser::new(pattern);
+        let node = parser.parse()?;
+        let ignore_case = parser.ignore_case.unwrap_or(ignore_case);
+
+        let mut compiler = Compiler { prog: Vec::new() };
+        compiler.push(Inst::Save(0))?;
+        compiler.compile(&node)?;
+        compiler.push(Inst::Match)?;
+        let prog = compiler.prog;
+
+        let mut prefix = String::new();
+        let mut anchored = false;
+        for inst in &prog {
+            match inst {
+                Inst::Save(_) | Inst::WordStart | Inst::WordEnd | Inst::Eol => {}
+                Inst::Bol if prefix.is_empty() => anchored = true,
+                // ignoring case is only cheap to do for ascii
+                Inst::Char(ch) if *ch != '\n' && (!ignore_case || ch.is_ascii()) => {
+                    prefix.push(*ch)
+                }
+                _ => break,
+            }
+        }
+
+        let assertions = prog.iter().any(|inst| {
+            matches!(
+                inst,
+                Inst::Bol | Inst::Eol | Inst::WordStart | Inst::WordEnd
+            )
+        });
+        let mut must = node.must();
+        if node.multiline() || (ignore_case && !must.is_ascii()) {
+            must.clear();
+        }
+
+        let mut regex = Self {
+            prog,
+            slots: parser.groups * 2,
+            ignore_case,
+            prefix,
+            must,
+            anchored,
+            first: None,
+            assertions,
+        };
+        regex.first = regex.first_chars();
+        Ok(regex)
+    }
+
+    fn first_chars(&self) -> Option<FirstChars> {
+        let mut seen = vec![false; self.prog.len()];
+        let mut stack = vec![0];
+        let mut insts = Vec::new();
+
+        while let Some(pc) = stack.pop() {
+            if std::mem::replace(&mut seen[pc], true) {
+                continue;
+            }
+            match &self.prog[pc] {
+                Inst::Match => return None,
+                Inst::Jmp(to) => stack.push(*to),
+                Inst::Split(first, second) => stack.extend([*first, *second]),
+                Inst::Char(_) | Inst::Any { .. } | Inst::Class(_) => insts.push(pc),
+                _ => stack.push(pc + 1),
+            }
+        }
+
+        let can_start = |ch: char| insts.iter().any(|&pc| self.step(&self.prog[pc], ch));
+        let mut ascii = [false; 128];
+        (0..128u8).for_each(|b| ascii[b as usize] = can_start(b as char));
+        Some(FirstChars {
+            ascii,
+            newline: can_start('\n'),
+            insts,
+        })
+    }
+
+    fn context<H: Haystack + ?Sized>(&self, hay: &H, line: &str, pos: Pos) -> Context {
+        if !self.assertions {
+            return Context {
+                prev: None,
+                next: None,
+                bol: false,
+                eol: false,
+            };
+        }
+        let prev = match line[..pos.col].chars().next_back() {
+            Some(ch) => Some(ch),
+            None if pos.line > 0 => Some('\n'),
+            None => None,
+        };
+        let next = match line[pos.col..].chars().next() {
+            Some(ch) => Some(ch),
+            None if hay.line(pos.line + 1).is_some() => Some('\n'),
+            None => None,
+        };
+        Context {
+            prev,
+            next,
+            bol: pos.col == 0,
+            eol: pos.col == line.len(),
+        }
+    }
+
+    /// Follows the zero width instructions from `pc`, adding every thread that ends up waiting
+    /// on a character along with the captures in `scratch`.
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        stack: &mut Vec<Frame>,
+        scratch: &mut [Option<Pos>],
+        pc: usize,
+        pos: Pos,
+        ctx: &Context,
+    ) {
+        let word = |ch: Option<char>| ch.is_some_and(is_word_char);
+        stack.push(Frame::Explore(pc));
+
+        while let Some(frame) = stack.pop() {
+            let mut pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::Restore(slot, saved) => {
+                    scratch[slot] = saved;
+                    continue;
+                }
+            };
+            loop {
+                if threads.contains(pc) {
+                    break;
+                }
+                threads.insert(pc);
+
+                let pass = match &self.prog[pc] {
+                    Inst::Jmp(to) => {
+                        pc = *to;
+                        continue;
+                    }
+                    Inst::Save(slot) => {
+                        stack.push(Frame::Restore(*slot, scratch[*slot]));
+                        scratch[*slot] = Some(pos);
+                        true
+                    }
+                    Inst::Split(first, second) => {
+                        stack.push(Frame::Explore(*second));
+                        pc = *first;
+                        continue;
+                    }
+                    Inst::Bol => ctx.bol,
+                    Inst::Eol => ctx.eol,
+                    Inst::WordStart => !word(ctx.prev) && word(ctx.next),
+                    Inst::WordEnd => word(ctx.prev) && !word(ctx.next),
+                    _ => {
+                        let slots = scratch.len();
+                        threads.caps[pc * slots..(pc + 1) * slots].copy_from_slice(scratch);
+                        break;
+                    }
+                };
+                if !pass {
+                    break;
+                }
+                pc += 1;
+            }
+        }
+    }
+
+    fn step(&self, inst: &Inst, ch: char) -> bool {
+        match inst {
+            Inst::Char(expected) => {
+                *expected == ch || (self.ignore_case && fold(*expected) == fold(ch))
+            }
+            Inst::Any { newline } => ch != '\n' || *newline,
+            Inst::Class(class) => class.contains(ch, self.ignore_case),
+            _ => false,
+        }
+    }
+
+    fn find_literal(&self, text: &str, literal: &str) -> Option<usize> {
+        if !self.ignore_case {
+            return text.find(literal);
+        }
+        let literal = literal.as_bytes();
+        text.as_bytes()
+            .windows(literal.len())
+            .position(|window| window.eq_ignore_ascii_case(literal))
+    }
+
+    /// cheap checks that rule out most lines before the VM gets involved
+    fn could_match(&self, text: &str, col: usize) -> bool {
+        if !self.must.is_empty() && self.find_literal(text, &self.must).is_none() {
+            return false;
+        }
+        if !self.prefix.is_empty() {
+            return self.find_literal(&text[col..], &self.prefix).is_some();
+        }
+        match &self.first {
+            Some(first) => first.newline || first.skip(text, col) < text.len(),
+            None => true,
+        }
+    }
+
+    /// Leftmost match that starts on `line` at or after byte `col`.
+    pub fn find_at<H: Haystack + ?Sized>(&self, hay: &H, line: usize, col: usize) -> Option<Match> {
+        let text = hay.line(line)?;
+        if col > text.len() || !self.could_match(text, col) {
+            return None;
+        }
+        self.find_with(&mut Cache::new(self), hay, line, col)
+    }
+
+    fn find_with<H: Haystack + ?Sized>(
+        &self,
+        cache: &mut Cache,
+        hay: &H,
+        line: usize,
+        col: usize,
+    ) -> Option<Match> {
+        let mut text = hay.line(line)?;
+        if col > text.len() || (self.anchored && col > 0) {
+            return None;
+        }
+        let start_line = line;
+        let mut pos = Pos::new(line, col);
+        let slots = self.slots;
+
+        let Cache {
+            clist,
+            nlist,
+            stack,
+            scratch,
+        } = cache;
+        clist.dense.clear();
+        nlist.dense.clear();
+        let mut matched: Option<Box<[Option<Pos>]>> = None;
+
+        loop {
+            if matched.is_none() && pos.line == start_line {
+                if clist.dense.is_empty() {
+                    if self.anchored && pos.col > 0 {
+                        break;
+                    }
+                    if !self.prefix.is_empty() {
+                        match self.find_literal(&text[pos.col..], &self.prefix) {
+                            Some(offset) => pos.col += offset,
+                            None => break,
+                        }
+                    } else if let Some(first) = &self.first {
+                        pos.col = first.skip(text, pos.col);
+                    }
+                }
+                let can_start = self
+                    .first
+                    .as_ref()
+                    .is_none_or(|first| first.can_start(self, text, pos.col));
+                if can_start {
+                    let ctx = self.context(hay, text, pos);
+                    scratch.fill(None);
+                    self.add_thread(clist, stack, scratch, 0, pos, &ctx);
+                }
+            }
+            if clist.dense.is_empty() {
+                if matched.is_some() || pos.line != start_line {
+                    break;
+                }
+                // nothing can start here, on to the next char
+                match text[pos.col..].chars().next() {
+                    Some(ch) => {
+                        pos.col += ch.len_utf8();
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+
+            let (ch, next_pos) = match text[pos.col..].chars().next() {
+                Some(ch) => (Some(ch), Pos::new(pos.line, pos.col + ch.len_utf8())),
+                None if hay.line(pos.line + 1).is_some() => (Some('\n'), Pos::new(pos.line + 1, 0)),
+                None => (None, pos),
+            };
+            let next_text = if next_pos.line != pos.line {
+                hay.line(next_pos.line).unwrap_or("")
+            } else {
+                text
+            };
+            let next_ctx = self.context(hay, next_text, next_pos);
+
+            for &pc in clist.dense.iter() {
+                let inst = &self.prog[pc];
+                let caps = &clist.caps[pc * slots..(pc + 1) * slots];
+                if let Inst::Match = inst {
+                    let mut caps: Box<[Option<Pos>]> = caps.into();
+                    if caps[1].is_none() {
+                        caps[1] = Some(pos);
+                    }
+                    matched = Some(caps);
+                    // everything after this thread has lower priority
+                    break;
+                }
+                if let Some(ch) = ch {
+                    if self.step(inst, ch) {
+                        scratch.copy_from_slice(caps);
+                        self.add_thread(nlist, stack, scratch, pc + 1, next_pos, &next_ctx);
+                    }
+                }
+            }
+
+            if ch.is_none() {
+                break;
+            }
+            std::mem::swap(clist, nlist);
+            nlist.dense.clear();
+            pos = next_pos;
+            text = next_text;
+        }
+
+        matched.map(|mut slots| {
+            // `\zs` after `\ze`, or a `\ze` that never got reached
+            let (start, end) = (slots[0].unwrap(), slots[1].unwrap());
+            if start > end {
+                slots[0] = Some(end);
+            }
+            Match { slots }
+        })
+    }
+
+    /// Every non-overlapping match that starts on `line`.
+    pub fn find_iter<H: Haystack + ?Sized>(&self, hay: &H, line: usize) -> Vec<Match> {
+        let mut matches = Vec::new();
+        let Some(text) = hay.line(line) else {
+            return matches;
+        };
+        if !self.could_match(text, 0) {
+            return matches;
+        }
+        let next_char = |col: usize| text[col..].chars().next().map(|ch| col + ch.len_utf8());
+        let mut cache = Cache::new(self);
+        let mut col = 0;
+        let mut last_end = None;
+
+        while let Some(m) = self.find_with(&mut cache, hay, line, col) {
+            let (start, end) = (m.start(), m.end());
+
+            // no empty match right where the previous one ended, `:s/x*/-/g` on "abc" gives
+            // "-a-b-c-" and not "-a--b--c-"
+            if start == end && Some(start) == last_end {
+                match next_char(start.col) {
+                    Some(next) => {
+                        col = next;
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+            matches.push(m);
+
+            if end.line != line {
+                break;
+            }
+            last_end = Some(end);
+            col = if end.col > start.col {
+                end.col
+            } else {
+                match next_char(start.col) {
+                    Some(next) => next,
+                    None => break,
+                }
+            };
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the text of the leftmost match of `pattern` in `text`
+    fn find<'t>(pattern: &str, text: &'t str) -> Option<&'t str> {
+        let m = Regex::new(pattern, false).unwrap().find_at(text, 0, 0)?;
+        Some(&text[m.start().col..m.end().col])
+    }
+
+    #[test]
+    fn literals_and_dots() {
+        assert_eq!(find("b.d", "abcde"), Some("bcd"));
+        assert_eq!(find("x", "abc"), None);
+        assert_eq!(find("a\\.c", "abc a.c"), Some("a.c"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(find("^ab", "abab"), Some("ab"));
+        assert_eq!(find("^b", "ab"), None);
+        assert_eq!(find("b$", "abab"), Some("b"));
+        assert_eq!(find("a$", "aba"), Some("a"));
+        assert_eq!(find("\\<is\\>", "this is"), Some("is"));
+        assert_eq!(find("\\<is\\>", "this"), None);
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(find("[0-9]\\+", "ab123c"), Some("123"));
+        assert_eq!(find("[^a-c]", "abcd"), Some("d"));
+        assert_eq!(find("[[:upper:]]\\+", "abCDe"), Some("CD"));
+        assert_eq!(find("\\d\\+", "x42"), Some("42"));
+        assert_eq!(find("\\s\\S", "a b"), Some(" b"));
+        assert_eq!(find("\\w\\+", "  foo_1 "), Some("foo_1"));
+        assert_eq!(find("[]x]", "a]"), Some("]"));
+    }
+
+    #[test]
+    fn repeats() {
+        assert_eq!(find("ab*", "abbbc"), Some("abbb"));
+        assert_eq!(find("ab\\=c", "ac"), Some("ac"));
+        assert_eq!(find("a\\{2}", "aaaa"), Some("aa"));
+        assert_eq!(find("a\\{2,3}", "aaaa"), Some("aaa"));
+        assert_eq!(find("a\\{2,}", "aaaa"), Some("aaaa"));
+        assert_eq!(find("a\\{,2}", "aaaa"), Some("aa"));
+        assert_eq!(find("a\\{-1,}", "aaaa"), Some("a"));
+        assert_eq!(find("a\\{3}", "aa"), None);
+    }
+
+    #[test]
+    fn groups_and_alternatives() {
+        assert_eq!(find("\\(ab\\)\\+", "xababy"), Some("abab"));
+        assert_eq!(find("cat\\|dog", "hotdog"), Some("dog"));
+        let m = Regex::new("\\(a\\+\\)\\(b*\\)", false)
+            .unwrap()
+            .find_at("xaabc", 0, 0)
+            .unwrap();
+        assert_eq!(m.group(1), Some((Pos::new(0, 1), Pos::new(0, 3))));
+        assert_eq!(m.group(2), Some((Pos::new(0, 3), Pos::new(0, 4))));
+        assert_eq!(m.group(3), None);
+    }
+
+    #[test]
+    fn backrefs() {
+        // a pike VM can't do them, they're for the replacement of `:s` only
+        let Err(error) = Regex::new("\\(a\\)\\1", false) else {
+            panic!("a backreference compiled");
+        };
+        assert_eq!(
+            error.to_string(),
+            "Backreferences in patterns are not supported"
+        );
+    }
+
+    #[test]
+    fn magic_levels() {
+        assert_eq!(find("\\v(a|b)+", "cabac"), Some("aba"));
+        assert_eq!(find("\\V.*", "a.*b"), Some(".*"));
+    }
+
+    #[test]
+    fn ignore_case() {
+        let regex = Regex::new("abc", true).unwrap();
+        assert!(regex.find_at("xABc", 0, 0).is_some());
+        assert!(Regex::new("\\Cabc", true)
+            .unwrap()
+            .find_at("ABC", 0, 0)
+            .is_none());
+    }
+
+    #[test]
+    fn across_lines() {
+        let lines = ["foo".to_string(), "bar".to_string()];
+        let m = Regex::new("o\\nb", false)
+            .unwrap()
+            .find_at(&lines[..], 0, 0)
+            .unwrap();
+        assert_eq!((m.start(), m.end()), (Pos::new(0, 2), Pos::new(1, 1)));
+    }
+
+    #[test]
+    fn every_match() {
+        let regex = Regex::new("x*", false).unwrap();
+        let starts: Vec<usize> = regex
+            .find_iter("abc", 0)
+            .iter()
+            .map(|m| m.start().col)
+            .collect();
+        assert_eq!(starts, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Regex::new("\\(a", false).is_err());
+        assert!(Regex::new("a\\{1000}\\{1000}", false).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use crate::regex::{self, Regex};
+use crate::substitute::Flags;
+use crate::EditorRows;
+
+/// vim stops counting matches past this, the status line shows `>99` instead
+const MAX_SEARCH_COUNT: usize = 99;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Direction {
+    pub fn prefix(&self) -> char {
+        match self {
+            Direction::Forward => '/',
+            Direction::Backward => '?',
+        }
+    }
+
+    pub fn reverse(&self) -> Self {
+        match self {
+            Direction::Forward => Direction::Backward,
+            Direction::Backward => Direction::Forward,
+        }
+    }
+}
+
+/// Backslashes whatever is magic in a pattern, so `text` only matches itself.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if "\\/.*$^~[".contains(ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// The keyword under or after byte `x` of `text`, or failing that the run of non blanks there,
+/// which is what `*` and `#` look for. Gives back where it starts and whether it's a keyword.
+pub fn word_under(text: &str, x: usize) -> Option<(usize, &str, bool)> {
+    let run = |wanted: fn(char) -> bool| {
+        let (idx, ch) = text[x..].char_indices().find(|(_, ch)| wanted(*ch))?;
+        // the cursor being inside the run it starts further back
+        let start = if idx == 0 {
+            text[..x]
+                .char_indices()
+                .rev()
+                .take_while(|(_, ch)| wanted(*ch))
+                .last()
+                .map_or(x, |(start, _)| start)
+        } else {
+            x + idx
+        };
+        let from = x + idx + ch.len_utf8();
+        let end = text[from..]
+            .char_indices()
+            .find(|(_, ch)| !wanted(*ch))
+            .map_or(text.len(), |(idx, _)| from + idx);
+        Some((start, &text[start..end]))
+    };
+
+    match run(regex::is_word_char) {
+        Some((start, word)) => Some((start, word, true)),
+        None => run(|ch| !ch.is_whitespace()).map(|(start, word)| (start, word, false)),
+    }
+}
+
+pub struct SearchHit {
+    pub x: usize,
+    pub y: usize,
+    pub wrapped: bool,
+}
+
+pub struct SearchCount {
+    pub x: usize,
+    pub y: usize,
+    pub current: usize,
+    pub total: usize,
+}
+
+impl SearchCount {
+    pub fn status(&self) -> String {
+        let fmt = |n: usize| {
+            if n > MAX_SEARCH_COUNT {
+                format!(">{}", MAX_SEARCH_COUNT)
+            } else {
+                n.to_string()
+            }
+        };
+        format!("[{}/{}]", fmt(self.current), fmt(self.total))
+    }
+}
+
+pub struct Search {
+    pub pattern: Option<String>,     // the last search "register"
+    pub replacement: Option<String>, // and the last :s replacement
+    /// the pattern of the last :s, which `:s` without one uses again
+    pub substitute_pattern: Option<String>,
+    /// the flags of the last :s, for its `&` flag
+    pub substitute_flags: Flags,
+    pub direction: Direction,
+    pub ignore_case: bool,
+    pub smart_case: bool,
+    pub count: Option<SearchCount>,
+    pub hlsearch: bool,
+    pub incsearch: bool,
+    /// `:nohlsearch`, hides the highlighting until the next search
+    pub no_highlight: bool,
+    /// what's been typed at the `/` prompt so far, for incsearch
+    pub typed: Option<String>,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Self {
+            pattern: None,
+            replacement: None,
+            substitute_pattern: None,
+            substitute_flags: Flags::default(),
+            direction: Direction::Forward,
+            ignore_case: false,
+            smart_case: false,
+            count: None,
+            hlsearch: true,
+            incsearch: true,
+            no_highlight: false,
+            typed: None,
+        }
+    }
+
+    pub fn set_pattern(&mut self, pattern: String) {
+        self.pattern = Some(pattern);
+        self.no_highlight = false;
+    }
+
+    /// the pattern whose matches get highlighted right now, if any
+    pub fn highlighted(&self) -> Option<&str> {
+        match (&self.typed, &self.pattern) {
+            (Some(typed), _) if self.incsearch => Some(typed.as_str()),
+            (_, Some(pattern)) if self.hlsearch && !self.no_highlight => Some(pattern.as_str()),
+            _ => None,
+        }
+        .filter(|pattern| !pattern.is_empty())
+    }
+
+    /// smartcase only kicks in when ignorecase is set, same as vim, and escaped letters like
+    /// `\S` don't count as upper case
+    pub fn ignores_case(&self, pattern: &str) -> bool {
+        if !self.ignore_case {
+            return false;
+        }
+        if !self.smart_case {
+            return true;
+        }
+        let mut chars = pattern.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' => {
+                    if let Some('_' | '%') = chars.next() {
+                        chars.next();
+                    }
+                }
+                ch if ch.is_uppercase() => return false,
+                _ => {}
+            }
+        }
+        true
+    }
+
+    pub fn regex(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        Regex::new(pattern, self.ignores_case(pattern))
+    }
+
+    /// Looks for `regex` starting right after (or before) the cursor, wrapping around the
+    /// end (or start) of the file.
+    pub fn find(
+        &self,
+        editor_rows: &EditorRows,
+        regex: &Regex,
+        cursor: (usize, usize),
+        direction: Direction,
+    ) -> Option<SearchHit> {
+        let nr_of_rows = editor_rows.nr_of_rows();
+        if nr_of_rows == 0 {
+            return None;
+        }
+        let cursor_y = cursor.1.min(nr_of_rows - 1);
+        let row = &editor_rows.get_editor_row(cursor_y).row_content;
+        let cursor_x = cursor.0.min(row.len());
+
+        // the cursor row gets visited twice: once for the part past the cursor, and once more
+        // after wrapping for the part before it
+        for step in 0..=nr_of_rows {
+            let (y, wrapped) = match direction {
+                Direction::Forward => {
+                    let y = cursor_y + step;
+                    (y % nr_of_rows, y >= nr_of_rows)
+                }
+                Direction::Backward => {
+                    let y = cursor_y as isize - step as isize;
+                    (y.rem_euclid(nr_of_rows as isize) as usize, y < 0)
+                }
+            };
+
+            let hit = match (direction, step) {
+                (Direction::Forward, 0) => row[cursor_x..]
+                    .chars()
+                    .next()
+                    .and_then(|ch| regex.find_at(editor_rows, y, cursor_x + ch.len_utf8())),
+                (Direction::Backward, 0) => regex
+                    .find_iter(editor_rows, y)
+                    .into_iter()
+                    .take_while(|m| m.start().col < cursor_x)
+                    .last(),
+                (Direction::Forward, _) => regex.find_at(editor_rows, y, 0),
+                (Direction::Backward, _) => regex.find_iter(editor_rows, y).pop(),
+            };
+
+            if let Some(m) = hit {
+                return Some(SearchHit {
+                    x: m.start().col,
+                    y,
+                    wrapped,
+                });
+            }
+        }
+        None
+    }
+
+    /// Which match the cursor sits on out of how many there are in the file.
+    pub fn count_matches(
+        &self,
+        editor_rows: &EditorRows,
+        regex: &Regex,
+        cursor: (usize, usize),
+    ) -> SearchCount {
+        let (cursor_x, cursor_y) = cursor;
+        let mut current = 0;
+        let mut total = 0;
+
+        for y in 0..editor_rows.nr_of_rows() {
+            for m in regex.find_iter(editor_rows, y) {
+                total += 1;
+                if (y, m.start().col) <= (cursor_y, cursor_x) {
+                    current = total;
+                }
+            }
+            if total > MAX_SEARCH_COUNT && current > 0 {
+                break;
+            }
+        }
+
+        SearchCount {
+            x: cursor_x,
+            y: cursor_y,
+            current,
+            total,
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::substitute::plural;
+use crate::{ex, Editor, Output};
+use crossterm::{cursor, execute, terminal};
+use std::{
+    io::{self, stdout, Write},
+    process::{self, Command, Stdio},
+    thread,
+};
+
+/// Runs `cmd` through `shell` with `input` on its stdin. Raw mode is off while it runs, and
+/// its output goes straight to the terminal unless it gets `captured`.
+fn run(
+    shell: &str,
+    cmd: &str,
+    input: Option<String>,
+    captured: bool,
+) -> io::Result<process::Output> {
+    let pipe_if = |piped: bool| {
+        if piped {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        }
+    };
+    terminal::disable_raw_mode()?;
+    let child = Command::new(shell)
+        .arg("-c")
+        .arg(cmd)
+        .stdin(pipe_if(input.is_some()))
+        .stdout(pipe_if(captured))
+        .stderr(pipe_if(captured))
+        .spawn();
+
+    let output = child.and_then(|mut child| {
+        // written from another thread so a command that answers before reading it all can't
+        // get stuck with us on a full pipe
+        let writer = input.zip(child.stdin.take()).map(|(input, mut stdin)| {
+            thread::spawn(move || {
+                let _ = stdin.write_all(input.as_bytes());
+            })
+        });
+        let output = child.wait_with_output();
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        output
+    });
+    terminal::enable_raw_mode()?;
+    output
+}
+
+/// what to tell about a command that failed, `None` if it went fine
+fn failure(output: &process::Output) -> Option<String> {
+    if output.status.success() {
+        return None;
+    }
+    let status = match output.status.code() {
+        Some(code) => format!("Shell returned {}", code),
+        None => "Shell command was killed".into(),
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Some(match stderr.lines().find(|line| !line.trim().is_empty()) {
+        Some(line) => format!("{}: {}", status, line.trim()),
+        None => status,
+    })
+}
+
+impl Editor {
+    /// tells off a `!` with nothing after it
+    fn no_command(&mut self, cmd: &str) -> bool {
+        let missing = cmd.trim().is_empty();
+        if missing {
+            self.output
+                .status_message
+                .set_error("Argument required".into());
+        }
+        missing
+    }
+
+    /// lines as a command reads them on stdin, each one ending in a newline
+    fn shell_input(&self, range: Option<ex::Range>) -> String {
+        let nr_of_rows = self.output.editor_rows.nr_of_rows();
+        let range = match range {
+            Some(range) => range,
+            None if nr_of_rows == 0 => return String::new(),
+            None => ex::Range {
+                start: 0,
+                end: nr_of_rows - 1,
+            },
+        };
+        self.output
+            .row_contents(range)
+            .into_iter()
+            .map(|row| row + "\n")
+            .collect()
+    }
+
+    /// Runs `cmd` with the terminal to itself, then waits for a key before going back to the
+    /// file so its output can be read.
+    fn run_in_terminal(&mut self, cmd: &str, input: Option<String>) -> io::Result<()> {
+        let shell = self.output.options.string("shell").to_string();
+        Output::clear_screen()?;
+        let result = run(&shell, cmd, input, false);
+        execute!(stdout(), cursor::MoveToColumn(0))?;
+        match result {
+            Ok(output) => {
+                if let Some(failure) = failure(&output) {
+                    print!("\r\n{}", failure);
+                }
+            }
+            Err(error) => print!("\r\n{}: {}", shell, error),
+        }
+        self.press_enter()
+    }
+
+    /// Runs `cmd` for its output, as lines. Anything going wrong ends up in the status bar.
+    fn command_output(
+        &mut self,
+        cmd: &str,
+        input: Option<String>,
+    ) -> io::Result<Option<Vec<String>>> {
+        if self.no_command(cmd) {
+            return Ok(None);
+        }
+        let shell = self.output.options.string("shell").to_string();
+        let output = match run(&shell, cmd, input, true) {
+            Ok(output) => output,
+            Err(error) => {
+                self.output
+                    .status_message
+                    .set_error(format!("{}: {}", shell, error));
+                return Ok(None);
+            }
+        };
+        if let Some(failure) = failure(&output) {
+            self.output.status_message.set_error(failure);
+            return Ok(None);
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(Some(stdout.lines().map(String::from).collect()))
+    }
+
+    /// `:!cmd` runs a command, `:{range}!cmd` filters the lines through it.
+    pub fn shell_command(&mut self, range: Option<ex::Range>, cmd: &str) -> io::Result<()> {
+        let Some(range) = range else {
+            if self.no_command(cmd) {
+                return Ok(());
+            }
+            return self.run_in_terminal(cmd, None);
+        };
+
+        let input = self.shell_input(Some(range));
+        let Some(rows) = self.command_output(cmd, Some(input))? else {
+            return Ok(());
+        };
+        let count = range.end - range.start + 1;
+        if rows.is_empty() {
+            self.output.delete_lines(range);
+        } else {
+            self.output
+                .edit(|output| output.editor_rows.replace_rows(range.start, count, rows));
+            self.output.set_cursor_first_non_blank(range.start);
+        }
+        self.output
+            .status_message
+            .set_message(format!("{} filtered", plural(count, "line")));
+        Ok(())
+    }
+
+    /// `:[range]w !cmd`, the lines (all of them by default) go to the command's stdin.
+    pub fn write_to_command(&mut self, range: Option<ex::Range>, cmd: &str) -> io::Result<()> {
+        if self.no_command(cmd) {
+            return Ok(());
+        }
+        let input = self.shell_input(range);
+        self.run_in_terminal(cmd, Some(input))
+    }
+
+    /// `:r !cmd`, what the command prints goes below line `line`, above the first one for `:0r`.
+    pub fn read_command(&mut self, line: usize, cmd: &str) -> io::Result<()> {
+        let Some(rows) = self.command_output(cmd, None)? else {
+            return Ok(());
+        };
+        self.output.put_lines(line, rows);
+        Ok(())
+    }
+}
@@ -0,0 +1,793 @@
+use crate::ex::{self, Range};
+use crate::regex::{Haystack, Match, Pos, Regex};
+use crate::search::Search;
+use crate::{Editor, EditorRows, Row};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{cmp, io};
+
+#[derive(Clone, Copy, Default)]
+pub struct Flags {
+    global: bool,
+    confirm: bool,
+    /// `n`, only count the matches
+    report_only: bool,
+    /// `e`, no "Pattern not found" error
+    no_error: bool,
+    ignore_case: Option<bool>,
+    /// `r`, a `:s` without a pattern uses the last search pattern instead of the last `:s` one
+    last_search: bool,
+}
+
+pub struct Substitute {
+    /// `None` means reuse the last search pattern, like `:s//foo/`
+    pattern: Option<String>,
+    /// `None` for `:s` without a pattern, which repeats the last replacement
+    replacement: Option<String>,
+    flags: Flags,
+    count: Option<usize>,
+}
+
+/// What the `:s` being typed would do to the rows on screen, drawn in their place until the
+/// command is run or cancelled.
+pub struct Preview {
+    /// first row of the file the preview stands in for, and how many of them
+    pub start: usize,
+    pub old_len: usize,
+    pub rows: Vec<Row>,
+    /// where the replacements ended up in `rows`
+    pub spans: Vec<(Pos, Pos)>,
+}
+
+/// Reads up to the next unescaped `delimiter`, `\<delimiter>` turns into the plain delimiter.
+pub fn split_delimited(text: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = text.char_indices();
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some((_, escaped)) if escaped == delimiter => part.push(escaped),
+                Some((_, escaped)) => {
+                    part.push('\\');
+                    part.push(escaped);
+                }
+                None => part.push('\\'),
+            },
+            ch if ch == delimiter => return (part, Some(&text[idx + ch.len_utf8()..])),
+            ch => part.push(ch),
+        }
+    }
+    (part, None)
+}
+
+/// `[flags] [count]` after the last delimiter, a `&` before the flags starting from `previous`
+fn parse_flags(text: &str, previous: Flags) -> Result<(Flags, Option<usize>), String> {
+    let mut rest = text.trim_start();
+    let mut flags = match rest.strip_prefix('&') {
+        Some(after) => {
+            rest = after;
+            previous
+        }
+        None => Flags::default(),
+    };
+
+    while let Some(ch) = rest.chars().next() {
+        match ch {
+            'g' => flags.global = !flags.global,
+            'c' => flags.confirm = true,
+            'n' => flags.report_only = true,
+            'e' => flags.no_error = true,
+            'i' => flags.ignore_case = Some(true),
+            'I' => flags.ignore_case = Some(false),
+            'r' => flags.last_search = true,
+            // the line is never printed after, so there's nothing for these to change
+            'p' | '#' | 'l' => {}
+            _ => break,
+        }
+        rest = &rest[ch.len_utf8()..];
+    }
+
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return Ok((flags, None));
+    }
+    match rest.parse::<usize>() {
+        Ok(0) => Err("Positive count required".into()),
+        Ok(count) => Ok((flags, Some(count))),
+        Err(_) => Err(format!("Trailing characters: {}", rest)),
+    }
+}
+
+/// `:s/pattern/replacement/flags count`, the delimiter being any char that can't be a flag.
+/// `previous` are the flags of the last `:s`, which the `&` flag keeps.
+pub fn parse(args: &str, previous: Flags) -> Result<Substitute, String> {
+    let delimiter = match args.chars().next() {
+        Some(ch) if !(ch.is_alphanumeric() || ch.is_whitespace() || "\\\"|&".contains(ch)) => ch,
+        _ => {
+            let (flags, count) = parse_flags(args, previous)?;
+            return Ok(Substitute {
+                pattern: None,
+                replacement: None,
+                flags,
+                count,
+            });
+        }
+    };
+
+    let (pattern, rest) = split_delimited(&args[delimiter.len_utf8()..], delimiter);
+    let (replacement, rest) = match rest {
+        Some(rest) => split_delimited(rest, delimiter),
+        None => (String::new(), None),
+    };
+    let (flags, count) = match rest {
+        Some(rest) => parse_flags(rest, previous)?,
+        None => (Flags::default(), None),
+    };
+
+    Ok(Substitute {
+        pattern: (!pattern.is_empty()).then_some(pattern),
+        replacement: Some(replacement),
+        flags,
+        count,
+    })
+}
+
+/// `~` in the replacement stands for the previous replacement string
+fn expand_tilde(replacement: &str, previous: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = replacement.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                expanded.push('\\');
+                if let Some(escaped) = chars.next() {
+                    expanded.push(escaped);
+                }
+            }
+            '~' => expanded.push_str(previous),
+            ch => expanded.push(ch),
+        }
+    }
+    expanded
+}
+
+/// text between two positions, rows joined with `\n`
+fn text_between<H: Haystack + ?Sized>(hay: &H, start: Pos, end: Pos) -> String {
+    let mut text = String::new();
+    for line in start.line..=end.line {
+        let row = hay.line(line).unwrap_or("");
+        let from = if line == start.line { start.col } else { 0 };
+        let to = if line == end.line { end.col } else { row.len() };
+        text.push_str(&row[from..to]);
+        if line != end.line {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+struct CaseConverter {
+    one_shot: Option<Case>,
+    persistent: Option<Case>,
+    text: String,
+}
+
+impl CaseConverter {
+    fn push(&mut self, ch: char) {
+        match self.one_shot.take().or(self.persistent) {
+            Some(Case::Upper) => self.text.extend(ch.to_uppercase()),
+            Some(Case::Lower) => self.text.extend(ch.to_lowercase()),
+            None => self.text.push(ch),
+        }
+    }
+
+    fn push_str(&mut self, text: &str) {
+        text.chars().for_each(|ch| self.push(ch));
+    }
+}
+
+/// Builds the replacement for `m`: `&` and `\0` through `\9` insert the match and its groups,
+/// `\u`, `\l`, `\U`, `\L` and `\E` change case and `\r` (or `\n`) breaks the line, which comes
+/// out as a `\n` in the returned text.
+fn expand<H: Haystack + ?Sized>(replacement: &str, m: &Match, hay: &H) -> String {
+    let group = |idx: usize| {
+        m.group(idx)
+            .map(|(start, end)| text_between(hay, start, end))
+            .unwrap_or_default()
+    };
+    let mut out = CaseConverter {
+        one_shot: None,
+        persistent: None,
+        text: String::new(),
+    };
+
+    let mut chars = replacement.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '&' => out.push_str(&group(0)),
+            '\r' => out.text.push('\n'),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => out.push_str(&group(digit as usize - '0' as usize)),
+                Some('n' | 'r') => out.text.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('u') => out.one_shot = Some(Case::Upper),
+                Some('l') => out.one_shot = Some(Case::Lower),
+                Some('U') => out.persistent = Some(Case::Upper),
+                Some('L') => out.persistent = Some(Case::Lower),
+                Some('E' | 'e') => {
+                    out.persistent = None;
+                    out.one_shot = None;
+                }
+                Some(escaped) => out.push(escaped),
+                None => out.push('\\'),
+            },
+            ch => out.push(ch),
+        }
+    }
+    out.text
+}
+
+fn next_char(text: &str, col: usize) -> Option<usize> {
+    text[col..].chars().next().map(|ch| col + ch.len_utf8())
+}
+
+/// where to look for the next match on row `y` when the one from `start` to `end` is left alone
+fn skip_match(text: &str, y: usize, start: Pos, end: Pos) -> Option<usize> {
+    if end.line != y {
+        None
+    } else if end.col > start.col {
+        Some(end.col)
+    } else {
+        next_char(text, start.col)
+    }
+}
+
+pub fn plural(count: usize, word: &str) -> String {
+    match (count, word.ends_with(['s', 'x', 'h'])) {
+        (1, _) => format!("{} {}", count, word),
+        (_, true) => format!("{} {}es", count, word),
+        (_, false) => format!("{} {}s", count, word),
+    }
+}
+
+/// The pattern, replacement and regex `substitute` stands for, blanks filled in with the last
+/// ones used.
+fn resolve(substitute: &Substitute, search: &Search) -> Result<(String, String, Regex), String> {
+    // `:s` on its own repeats the last one, `:s//` goes with the last search
+    let repeat = substitute.replacement.is_none() && !substitute.flags.last_search;
+    let Some(pattern) = substitute.pattern.clone().or_else(|| match repeat {
+        true => search.substitute_pattern.clone(),
+        false => search.pattern.clone(),
+    }) else {
+        return Err("No previous regular expression".into());
+    };
+    let replacement = match (&substitute.replacement, &search.replacement) {
+        (Some(replacement), previous) => {
+            expand_tilde(replacement, previous.as_deref().unwrap_or(""))
+        }
+        (None, Some(previous)) => previous.clone(),
+        (None, None) => return Err("No previous substitute".into()),
+    };
+
+    let ignore_case = substitute
+        .flags
+        .ignore_case
+        .unwrap_or_else(|| search.ignores_case(&pattern));
+    let regex = Regex::new(&pattern, ignore_case).map_err(|error| error.to_string())?;
+    Ok((pattern, replacement, regex))
+}
+
+/// the current line when no range is given, a count starts at the end of the range instead
+fn target_range(
+    range: Option<Range>,
+    count: Option<usize>,
+    cursor_y: usize,
+    nr_of_rows: usize,
+) -> Range {
+    let range = range.unwrap_or(Range::line(cmp::min(cursor_y, nr_of_rows - 1)));
+    match count {
+        Some(count) => Range {
+            start: range.end,
+            end: cmp::min(range.end + count - 1, nr_of_rows - 1),
+        },
+        None => range,
+    }
+}
+
+/// The rows that take the place of the ones `m` spans once it's replaced, and the column right
+/// after the inserted text on the last of them, which is where matching carries on.
+fn splice<H: Haystack + ?Sized>(hay: &H, replacement: &str, m: &Match) -> (Vec<String>, usize) {
+    let (start, end) = (m.start(), m.end());
+    let text = expand(replacement, m, hay);
+    let before = &hay.line(start.line).unwrap_or("")[..start.col];
+    let after = &hay.line(end.line).unwrap_or("")[end.col..];
+
+    let new_text = format!("{}{}", before, text);
+    let resume = new_text.len() - new_text.rfind('\n').map_or(0, |idx| idx + 1);
+    let rows = format!("{}{}", new_text, after)
+        .split('\n')
+        .map(String::from)
+        .collect();
+    (rows, resume)
+}
+
+/// `:s` done on a copy of some rows for the preview, replacing on rows up to `end`. Returns
+/// where each inserted text ended up.
+fn substitute_copy(
+    rows: &mut Vec<String>,
+    regex: &Regex,
+    replacement: &str,
+    global: bool,
+    mut end: usize,
+) -> Vec<(Pos, Pos)> {
+    let mut spans = Vec::new();
+    let mut y = 0;
+
+    while y <= end && y < rows.len() {
+        let mut col = 0;
+        let mut last_end = None;
+
+        while let Some(m) = regex.find_at(rows.as_slice(), y, col) {
+            let (start, match_end) = (m.start(), m.end());
+            if start == match_end && Some(start) == last_end {
+                match next_char(&rows[y], start.col) {
+                    Some(next) => {
+                        col = next;
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+
+            let (new_rows, resume) = splice(rows.as_slice(), replacement, &m);
+            let added = new_rows.len() - 1;
+            let joined = match_end.line - y;
+            rows.splice(y..=match_end.line, new_rows);
+            end = (end + added).saturating_sub(joined);
+            spans.push((start, Pos::new(y + added, resume)));
+            y += added;
+
+            last_end = Some(Pos::new(y, resume));
+            col = resume;
+            if start == match_end {
+                match next_char(&rows[y], col) {
+                    Some(next) => col = next,
+                    None => break,
+                }
+            }
+            if !global {
+                break;
+            }
+        }
+        y += 1;
+    }
+    spans
+}
+
+enum Answer {
+    Yes,
+    No,
+    All,
+    Last,
+    Quit,
+}
+
+impl Editor {
+    /// Asks about replacing the highlighted match, for the `c` flag.
+    fn confirm_substitution(
+        &mut self,
+        replacement: &str,
+        start: Pos,
+        end: Pos,
+    ) -> io::Result<Answer> {
+        self.output.set_cursor(start.col, start.line);
+        self.output.highlight = Some((start, end));
+        let prompt = format!(
+            "replace with {} (y/n/a/q/l)?",
+            replacement.replace('\r', "^M")
+        );
+        self.output.prompt = Some((prompt.clone(), prompt.chars().count()));
+
+        let answer = loop {
+            self.refresh_screen()?;
+            if let KeyEvent {
+                code,
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                kind: _,
+                state: _,
+            } = self.read_key()?
+            {
+                match code {
+                    KeyCode::Char('y') => break Answer::Yes,
+                    KeyCode::Char('n') => break Answer::No,
+                    KeyCode::Char('a') => break Answer::All,
+                    KeyCode::Char('l') => break Answer::Last,
+                    KeyCode::Char('q') | KeyCode::Esc => break Answer::Quit,
+                    _ => {}
+                }
+            }
+        };
+
+        self.output.highlight = None;
+        self.output.prompt = None;
+        Ok(answer)
+    }
+
+    /// Works out the preview for `command` if it's a `:s` with a pattern, `None` otherwise.
+    pub fn preview_substitute(&self, command: &str) -> Option<Preview> {
+        let editor_rows = &self.output.editor_rows;
+        let cursor_controller = &self.output.cursor_controller;
+        let nr_of_rows = editor_rows.nr_of_rows();
+        let position = ex::Position {
+            cursor_y: cursor_controller.cursor_y,
+            editor_rows,
+            search: &self.output.search,
+        };
+        let ex::Command {
+            range, name, args, ..
+        } = ex::parse(command, &position).ok()?;
+        if name != "substitute" {
+            return None;
+        }
+        let substitute = parse(args, self.output.search.substitute_flags).ok()?;
+        substitute.pattern.as_ref()?;
+        let (_, replacement, regex) = resolve(&substitute, &self.output.search).ok()?;
+        if nr_of_rows == 0 {
+            return None;
+        }
+
+        // until the replacement gets typed the matches are only highlighted, `&` puts them back
+        let delimiter = args.chars().next()?;
+        let typing_pattern = split_delimited(&args[delimiter.len_utf8()..], delimiter)
+            .1
+            .is_none();
+        let replacement = if typing_pattern || substitute.flags.report_only {
+            "&".into()
+        } else {
+            replacement
+        };
+
+        let range = target_range(
+            range,
+            substitute.count,
+            cursor_controller.cursor_y,
+            nr_of_rows,
+        );
+        let screen_rows = self.output.win_size.1;
+        let start = cmp::max(range.start, cursor_controller.row_offset);
+        let last = cmp::min(range.end, cursor_controller.row_offset + screen_rows - 1);
+        if start > last {
+            return None;
+        }
+        // another screenful of rows, for matches that run past the last one shown
+        let end = cmp::min(nr_of_rows, last + 1 + screen_rows);
+
+        let mut rows = (start..end)
+            .map(|y| editor_rows.get_editor_row(y).row_content.clone())
+            .collect();
+        let spans = substitute_copy(
+            &mut rows,
+            &regex,
+            &replacement,
+            substitute.flags.global,
+            last - start,
+        );
+        Some(Preview {
+            start,
+            old_len: end - start,
+            rows: rows
+                .into_iter()
+                .map(|row| EditorRows::make_row(row, editor_rows.tab_stop()))
+                .collect(),
+            spans,
+        })
+    }
+
+    pub fn substitute(&mut self, range: Option<Range>, args: &str) -> io::Result<bool> {
+        let substitute = match parse(args, self.output.search.substitute_flags) {
+            Ok(substitute) => substitute,
+            Err(error) => {
+                self.output.status_message.set_error(error);
+                return Ok(true);
+            }
+        };
+        let (pattern, replacement, regex) = match resolve(&substitute, &self.output.search) {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                self.output.status_message.set_error(error);
+                return Ok(true);
+            }
+        };
+        self.output.search.set_pattern(pattern.clone());
+        self.output.search.substitute_pattern = Some(pattern.clone());
+        self.output.search.replacement = Some(replacement.clone());
+        self.output.search.substitute_flags = substitute.flags;
+
+        let nr_of_rows = self.output.editor_rows.nr_of_rows();
+        if nr_of_rows == 0 {
+            return Ok(true);
+        }
+        let cursor_y = self.output.cursor_controller.cursor_y;
+        let range = target_range(range, substitute.count, cursor_y, nr_of_rows);
+
+        let cursor = (self.output.cursor_controller.cursor_x, cursor_y);
+        let saved_cursor = self.output.cursor_controller.clone();
+        let began = self.output.editor_rows.history.begin(cursor);
+        let result = self.substitute_range(&regex, &replacement, substitute.flags, range);
+        if began {
+            self.output.editor_rows.history.end();
+        }
+        let (substitutions, lines, last_line) = result?;
+
+        if substitutions == 0 {
+            self.output.cursor_controller = saved_cursor;
+            // :g runs this on lines that may well not match
+            if !substitute.flags.no_error && self.global_tally.is_none() {
+                self.output
+                    .status_message
+                    .set_error(format!("Pattern not found: {}", pattern));
+            }
+            return Ok(true);
+        }
+
+        if substitute.flags.report_only {
+            self.output.cursor_controller = saved_cursor;
+            self.output.status_message.set_message(format!(
+                "{} on {}",
+                plural(substitutions, "match"),
+                plural(lines, "line")
+            ));
+        } else {
+            // like vim, the cursor ends up on the first non blank of the last changed line
+            let row = &self
+                .output
+                .editor_rows
+                .get_editor_row(last_line)
+                .row_content;
+            let first_non_blank = row.find(|c: char| !c.is_whitespace()).unwrap_or(0);
+            self.output.set_cursor(first_non_blank, last_line);
+            self.output.dirty = 1;
+            if let Some((total, total_lines)) = &mut self.global_tally {
+                *total += substitutions;
+                *total_lines += lines;
+                return Ok(true);
+            }
+            self.output.status_message.set_message(format!(
+                "{} on {}",
+                plural(substitutions, "substitution"),
+                plural(lines, "line")
+            ));
+        }
+        Ok(true)
+    }
+
+    /// Returns how many substitutions were made, on how many lines, and the last line touched.
+    fn substitute_range(
+        &mut self,
+        regex: &Regex,
+        replacement: &str,
+        flags: Flags,
+        range: Range,
+    ) -> io::Result<(usize, usize, usize)> {
+        let mut confirm = flags.confirm && !flags.report_only;
+        let mut substitutions = 0;
+        let mut lines = 0;
+        let mut last_line = range.start;
+        let mut end = range.end;
+        let mut y = range.start;
+
+        'rows: while y <= end && y < self.output.editor_rows.nr_of_rows() {
+            let mut col = 0;
+            let mut last_end = None;
+            let mut changed_line = false;
+
+            while let Some(m) = regex.find_at(&self.output.editor_rows, y, col) {
+                let (start, match_end) = (m.start(), m.end());
+                let row = &self.output.editor_rows.get_editor_row(y).row_content;
+
+                // same rule as `find_iter`, no empty match right after the previous one
+                if start == match_end && Some(start) == last_end {
+                    match next_char(row, start.col) {
+                        Some(next) => {
+                            col = next;
+                            continue;
+                        }
+                        None => break,
+                    }
+                }
+                let skipped = skip_match(row, y, start, match_end);
+
+                let mut stop = false;
+                if confirm {
+                    match self.confirm_substitution(replacement, start, match_end)? {
+                        Answer::Yes => {}
+                        Answer::No => {
+                            last_end = Some(match_end);
+                            match skipped {
+                                Some(next) if flags.global => col = next,
+                                _ => break,
+                            }
+                            continue;
+                        }
+                        Answer::All => confirm = false,
+                        Answer::Last => stop = true,
+                        Answer::Quit => break 'rows,
+                    }
+                }
+
+                substitutions += 1;
+                if !changed_line {
+                    changed_line = true;
+                    lines += 1;
+                }
+
+                if flags.report_only {
+                    last_end = Some(match_end);
+                    match skipped {
+                        Some(next) => col = next,
+                        None => break,
+                    }
+                } else {
+                    let (new_rows, resume) = splice(&self.output.editor_rows, replacement, &m);
+                    let added = new_rows.len() - 1;
+                    let joined = match_end.line - y;
+
+                    self.output
+                        .editor_rows
+                        .replace_rows(y, joined + 1, new_rows);
+                    end = (end + added).saturating_sub(joined);
+                    y += added;
+
+                    last_end = Some(Pos::new(y, resume));
+                    col = resume;
+                    if start == match_end {
+                        // an empty match, step over the char that follows it
+                        let row = &self.output.editor_rows.get_editor_row(y).row_content;
+                        match next_char(row, col) {
+                            Some(next) => col = next,
+                            None => break,
+                        }
+                    }
+                }
+
+                if stop {
+                    last_line = y;
+                    break 'rows;
+                }
+                if !flags.global {
+                    break;
+                }
+            }
+            if changed_line {
+                last_line = y;
+            }
+            y += 1;
+        }
+
+        Ok((substitutions, lines, last_line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `replacement` expanded for the first match of `pattern` in `text`
+    fn expanded(pattern: &str, replacement: &str, text: &str) -> String {
+        let m = Regex::new(pattern, false)
+            .unwrap()
+            .find_at(text, 0, 0)
+            .unwrap();
+        expand(replacement, &m, text)
+    }
+
+    /// `rows` after `:%s/pattern/replacement/[g]`
+    fn substituted(pattern: &str, replacement: &str, rows: &[&str], global: bool) -> Vec<String> {
+        let mut rows: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+        let regex = Regex::new(pattern, false).unwrap();
+        let end = rows.len() - 1;
+        substitute_copy(&mut rows, &regex, replacement, global, end);
+        rows
+    }
+
+    #[test]
+    fn whole_match_and_groups() {
+        assert_eq!(expanded("b\\+", "[&]", "abbc"), "[bb]");
+        assert_eq!(expanded("b\\+", "[\\0]", "abbc"), "[bb]");
+        assert_eq!(expanded("\\(a\\)\\(b\\)", "\\2\\1", "ab"), "ba");
+        assert_eq!(expanded("\\(a\\)", "<\\9>", "ab"), "<>");
+        assert_eq!(expanded("a", "\\&", "ab"), "&");
+    }
+
+    #[test]
+    fn case_changes() {
+        assert_eq!(expanded("\\w\\+", "\\u&", "foo"), "Foo");
+        assert_eq!(expanded("\\w\\+", "\\l&", "FOO"), "fOO");
+        assert_eq!(expanded("\\w\\+", "\\U&", "foo"), "FOO");
+        assert_eq!(expanded("\\w\\+", "\\L&", "FoO"), "foo");
+        assert_eq!(expanded("\\w\\+", "\\U&\\E!&", "ab"), "AB!ab");
+        assert_eq!(expanded("\\w\\+", "\\L\\u&", "HELLO"), "Hello");
+    }
+
+    #[test]
+    fn line_breaks() {
+        assert_eq!(expanded("b", "\\r", "abc"), "\n");
+        assert_eq!(expanded("b", "\\t", "abc"), "\t");
+        assert_eq!(substituted(",", "\\r", &["a,b,c"], true), ["a", "b", "c"]);
+        assert_eq!(substituted("b\\nc", "-", &["ab", "cd"], false), ["a-d"]);
+    }
+
+    #[test]
+    fn global_and_empty_matches() {
+        assert_eq!(substituted("a", "x", &["aaa"], false), ["xaa"]);
+        assert_eq!(substituted("a", "x", &["aaa"], true), ["xxx"]);
+        assert_eq!(substituted("x*", "-", &["abc"], true), ["-a-b-c-"]);
+    }
+
+    #[test]
+    fn tilde() {
+        assert_eq!(expand_tilde("a~b", "X"), "aXb");
+        assert_eq!(expand_tilde("a\\~b", "X"), "a\\~b");
+
+        let mut search = Search::new();
+        search.replacement = Some("old".into());
+        let substitute = parse("/x/[~]/", Flags::default()).unwrap();
+        let (_, replacement, _) = resolve(&substitute, &search).unwrap();
+        assert_eq!(replacement, "[old]");
+    }
+
+    #[test]
+    fn parsing() {
+        let substitute = parse("#a\\#b#c#g 3", Flags::default()).unwrap();
+        assert_eq!(substitute.pattern.as_deref(), Some("a#b"));
+        assert_eq!(substitute.replacement.as_deref(), Some("c"));
+        assert!(substitute.flags.global);
+        assert_eq!(substitute.count, Some(3));
+
+        let substitute = parse("/a", Flags::default()).unwrap();
+        assert_eq!(substitute.replacement.as_deref(), Some(""));
+        assert!(parse("/a/b/0", Flags::default()).is_err());
+        assert!(parse("/a/b/gz", Flags::default()).is_err());
+    }
+
+    #[test]
+    fn flags() {
+        let (flags, _) = parse_flags("gg", Flags::default()).unwrap();
+        assert!(!flags.global);
+        let (flags, _) = parse_flags("gcI", Flags::default()).unwrap();
+        assert!(flags.global && flags.confirm && flags.ignore_case == Some(false));
+        let (flags, count) = parse_flags("ne 12", Flags::default()).unwrap();
+        assert!(flags.report_only && flags.no_error);
+        assert_eq!(count, Some(12));
+        assert!(parse_flags("gz", Flags::default()).is_err());
+
+        let (previous, _) = parse_flags("gi", Flags::default()).unwrap();
+        let (flags, _) = parse_flags("&n", previous).unwrap();
+        assert!(flags.global && flags.report_only && flags.ignore_case == Some(true));
+        let (flags, _) = parse_flags("n", previous).unwrap();
+        assert!(!flags.global);
+        // `&` has to come first
+        assert!(parse_flags("g&", Flags::default()).is_err());
+    }
+
+    #[test]
+    fn repeat_patterns() {
+        let mut search = Search::new();
+        search.pattern = Some("searched".into());
+        search.substitute_pattern = Some("substituted".into());
+        search.replacement = Some("x".into());
+
+        let repeat = |args: &str| {
+            let substitute = parse(args, Flags::default()).unwrap();
+            resolve(&substitute, &search).unwrap().0
+        };
+        assert_eq!(repeat(""), "substituted");
+        assert_eq!(repeat("r"), "searched");
+        assert_eq!(repeat("//y/"), "searched");
+    }
+}
@@ -0,0 +1,375 @@
+use crate::window::{Window, Windows};
+use crate::{Editor, Output, NO_FILE_NAME};
+use crossterm::{cursor, queue, style};
+use std::io::{self, stdout, Write};
+
+/// A tab page that isn't the current one: its windows, with the one that's current in it.
+pub struct TabPage {
+    windows: Windows,
+    current: Window,
+}
+
+/// Every tab page in order. The current one's place is empty, its windows are the editor's.
+pub struct Tabs {
+    pages: Vec<Option<TabPage>>,
+}
+
+impl Tabs {
+    pub fn new() -> Self {
+        Self { pages: vec![None] }
+    }
+
+    pub fn count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// where the current tab page is, from 0
+    pub fn current(&self) -> usize {
+        self.pages.iter().position(Option::is_none).unwrap_or(0)
+    }
+
+    /// Whether a window in a tab page other than the current one shows buffer `nr`.
+    pub fn shows(&self, nr: usize) -> bool {
+        self.pages.iter().flatten().any(|page| {
+            page.current.buffer == nr || page.windows.buffers().any(|buffer| buffer == nr)
+        })
+    }
+
+    /// Closes the windows showing buffer `nr` in the other tab pages, and the tab pages that
+    /// leaves empty, for `:bd`.
+    pub fn remove_buffer(&mut self, nr: usize) {
+        self.pages.retain_mut(|page| match page {
+            None => true,
+            Some(page) => page.windows.remove_buffer(&mut page.current, nr),
+        });
+    }
+}
+
+impl Output {
+    /// Draws `labels` along the top row, the current tab page's standing out from the rest.
+    pub fn draw_tabline(&mut self, labels: &[(String, bool)]) {
+        queue!(self.editor_contents, cursor::MoveTo(0, 0)).unwrap();
+        let mut used = 0;
+        for (label, current) in labels {
+            let label: String = label.chars().take(self.screen.0 - used).collect();
+            used += label.chars().count();
+            if *current {
+                self.editor_contents
+                    .push_str(&style::Attribute::Bold.to_string());
+            } else {
+                self.editor_contents
+                    .push_str(&style::Attribute::Reverse.to_string());
+            }
+            self.editor_contents.push_str(&label);
+            self.editor_contents
+                .push_str(&style::Attribute::Reset.to_string());
+        }
+        self.editor_contents
+            .push_str(&style::Attribute::Reverse.to_string());
+        self.editor_contents
+            .push_str(&" ".repeat(self.screen.0 - used));
+        self.editor_contents
+            .push_str(&style::Attribute::Reset.to_string());
+    }
+}
+
+/// Where `:tabmove {arg}` puts the tab page at `current` of `count`, if it can go there.
+fn destination(arg: &str, count: usize, current: usize) -> Option<usize> {
+    let places = |nr: &str| match nr {
+        "" => Some(1),
+        _ => nr.parse::<usize>().ok(),
+    };
+    match arg {
+        "" => Some(count - 1),
+        _ if arg.starts_with('+') => places(&arg[1..])
+            .map(|nr| current + nr)
+            .filter(|to| *to < count),
+        _ if arg.starts_with('-') => places(&arg[1..]).and_then(|nr| current.checked_sub(nr)),
+        _ => arg.parse::<usize>().ok().map(|nr| match nr > current {
+            true => nr.min(count) - 1,
+            false => nr,
+        }),
+    }
+}
+
+impl Editor {
+    /// whether there's a tabline, which 'showtabline' has at 2 always and at 1 when there's
+    /// more than one tab page
+    pub fn tabline_shown(&self) -> bool {
+        match self.output.options.number("showtabline") {
+            0 => false,
+            1 => self.tabs.count() > 1,
+            _ => true,
+        }
+    }
+
+    /// the buffers the windows in tab page `idx` show, that of its current window first
+    fn tab_buffers(&self, idx: usize) -> Vec<usize> {
+        match &self.tabs.pages[idx] {
+            None => std::iter::once(self.buffers.current)
+                .chain(self.windows.buffers())
+                .collect(),
+            Some(page) => std::iter::once(page.current.buffer)
+                .chain(page.windows.buffers())
+                .collect(),
+        }
+    }
+
+    /// For each tab page, what the tabline shows for it and whether it's the current one: the
+    /// number of windows when there's more than one, a `+` when one of them has changes, and
+    /// the name of the file in its current window.
+    pub fn tab_labels(&self) -> Vec<(String, bool)> {
+        let current = self.tabs.current();
+        (0..self.tabs.count())
+            .map(|idx| {
+                let buffers = self.tab_buffers(idx);
+                let modified = buffers
+                    .iter()
+                    .any(|nr| self.buffer(*nr).is_some_and(|(_, dirty, _)| dirty > 0));
+                let mut flags = String::new();
+                if buffers.len() > 1 {
+                    flags += &buffers.len().to_string();
+                }
+                if modified {
+                    flags.push('+');
+                }
+                if !flags.is_empty() {
+                    flags.push(' ');
+                }
+                let name = self
+                    .buffer(buffers[0])
+                    .and_then(|(editor_rows, ..)| editor_rows.file_name.as_ref())
+                    .and_then(|path| path.file_name())
+                    .and_then(|name| name.to_str())
+                    .unwrap_or(NO_FILE_NAME);
+                (format!(" {}{} ", flags, name), idx == current)
+            })
+            .collect()
+    }
+
+    /// Makes tab page `idx` the current one.
+    pub fn enter_tab(&mut self, idx: usize) {
+        let current = self.tabs.current();
+        if idx == current || idx >= self.tabs.count() {
+            return;
+        }
+        let page = self.tabs.pages[idx].take().unwrap();
+        let window = self.take_window();
+        let windows = std::mem::replace(&mut self.windows, page.windows);
+        self.tabs.pages[current] = Some(TabPage {
+            windows,
+            current: window,
+        });
+        self.output.mode = crate::Mode::Normal;
+        self.put_window(page.current);
+        self.fit_windows();
+    }
+
+    /// `:tabnew [file]` and `:tabe[dit] [file]` open a tab page after the current one, with
+    /// `file` in it or else an empty buffer.
+    pub fn new_tab(&mut self, args: &str) {
+        let current = self.tabs.current();
+        let window = self.take_window();
+        let windows = std::mem::replace(&mut self.windows, Windows::new());
+        self.tabs.pages[current] = Some(TabPage {
+            windows,
+            current: window,
+        });
+        self.tabs.pages.insert(current + 1, None);
+        self.fit_windows();
+        if args.trim().is_empty() {
+            let editor_rows = crate::EditorRows::new(self.output.options.clone());
+            self.add_buffer(editor_rows, false);
+        } else {
+            self.edit_file(false, args);
+        }
+    }
+
+    /// `:tabn[ext]` and `gt` go to the next tab page, from the last one to the first, and with
+    /// a number to that tab page.
+    pub fn next_tab(&mut self, args: &str) {
+        let count = self.tabs.count();
+        let idx = match args.trim() {
+            "" => (self.tabs.current() + 1) % count,
+            arg => match arg.parse::<usize>() {
+                Ok(nr) if nr > 0 && nr <= count => nr - 1,
+                _ => {
+                    self.output
+                        .status_message
+                        .set_error(format!("Invalid argument: {}", arg));
+                    return;
+                }
+            },
+        };
+        self.enter_tab(idx);
+    }
+
+    /// `:tabp[revious] [N]`, `:tabN[ext] [N]` and `gT` go `N` tab pages back, going round
+    /// from the first to the last one.
+    pub fn previous_tab(&mut self, args: &str) {
+        let count = self.tabs.count();
+        let back = match args.trim() {
+            "" => 1,
+            arg => match arg.parse::<usize>() {
+                Ok(nr) => nr,
+                Err(_) => {
+                    self.output
+                        .status_message
+                        .set_error(format!("Invalid argument: {}", arg));
+                    return;
+                }
+            },
+        };
+        let idx = (self.tabs.current() + count - back % count) % count;
+        self.enter_tab(idx);
+    }
+
+    /// Whether tab page `idx` can go, which it can't when it has a buffer with changes that no
+    /// other tab page shows, unless 'hidden' is on or there's a `!`.
+    fn can_close_tab(&self, idx: usize, bang: bool) -> bool {
+        if bang || self.output.options.bool("hidden") {
+            return true;
+        }
+        self.tab_buffers(idx).into_iter().all(|nr| {
+            let dirty = self.buffer(nr).is_some_and(|(_, dirty, _)| dirty > 0);
+            !dirty
+                || (0..self.tabs.count())
+                    .any(|other| other != idx && self.tab_buffers(other).contains(&nr))
+        })
+    }
+
+    /// Closes tab page `idx`, gives back whether it did. Closing the current one goes to the
+    /// one after it, or before it when it was the last.
+    pub fn close_tab(&mut self, idx: usize, bang: bool) -> bool {
+        if self.tabs.count() == 1 {
+            self.output
+                .status_message
+                .set_error("Cannot close last tab page".into());
+            return false;
+        }
+        if !self.can_close_tab(idx, bang) {
+            let message = format!("{} (add ! to override)", crate::DIRTY);
+            self.output.status_message.set_error(message);
+            return false;
+        }
+        if idx == self.tabs.current() {
+            let next = if idx + 1 < self.tabs.count() {
+                idx + 1
+            } else {
+                idx - 1
+            };
+            self.enter_tab(next);
+        }
+        self.tabs.pages.remove(idx);
+        self.fit_windows();
+        true
+    }
+
+    /// `:tabc[lose][!] [N]` closes the current tab page, or tab page `N`.
+    pub fn close_tab_command(&mut self, bang: bool, args: &str) {
+        let idx = match args.trim() {
+            "" => self.tabs.current(),
+            arg => match arg.parse::<usize>() {
+                Ok(nr) if nr > 0 && nr <= self.tabs.count() => nr - 1,
+                _ => {
+                    self.output
+                        .status_message
+                        .set_error(format!("Invalid argument: {}", arg));
+                    return;
+                }
+            },
+        };
+        self.close_tab(idx, bang);
+    }
+
+    /// `:tabo[nly][!]` closes every other tab page, except those with changes no other tab
+    /// page shows, unless 'hidden' is on or there's a `!`.
+    pub fn only_tab(&mut self, bang: bool) {
+        let mut kept = false;
+        let mut idx = 0;
+        while idx < self.tabs.count() {
+            if idx != self.tabs.current() && self.can_close_tab(idx, bang) {
+                self.tabs.pages.remove(idx);
+            } else {
+                kept |= idx != self.tabs.current();
+                idx += 1;
+            }
+        }
+        self.fit_windows();
+        if kept {
+            let message = format!("{} (add ! to override)", crate::DIRTY);
+            self.output.status_message.set_error(message);
+        }
+    }
+
+    /// `:tabm[ove] [N]` puts the current tab page after tab page `N`, first for 0 and last
+    /// without a number. `+N` and `-N` move it that many places right or left, one without `N`.
+    pub fn move_tab(&mut self, args: &str) {
+        let arg = args.trim();
+        let Some(to) = destination(arg, self.tabs.count(), self.tabs.current()) else {
+            self.output
+                .status_message
+                .set_error(format!("Invalid argument: {}", arg));
+            return;
+        };
+        let current = self.tabs.current();
+        let page = self.tabs.pages.remove(current);
+        self.tabs.pages.insert(to, page);
+    }
+
+    /// `:tabs` lists the tab pages and the files in their windows, `>` marking the current
+    /// window and `+` those with changes.
+    pub fn list_tabs(&mut self) -> io::Result<()> {
+        let current = self.tabs.current();
+        let mut lines = Vec::new();
+        for idx in 0..self.tabs.count() {
+            lines.push(format!("Tab page {}", idx + 1));
+            for (nr_idx, nr) in self.tab_buffers(idx).into_iter().enumerate() {
+                let Some((editor_rows, dirty, _)) = self.buffer(nr) else {
+                    continue;
+                };
+                lines.push(format!(
+                    "{}{} {}",
+                    if idx == current && nr_idx == 0 {
+                        '>'
+                    } else {
+                        ' '
+                    },
+                    if dirty > 0 { '+' } else { ' ' },
+                    crate::buffer::display_name(editor_rows)
+                ));
+            }
+        }
+        Output::clear_screen()?;
+        print!("{}", lines.join("\r\n"));
+        stdout().flush()?;
+        self.press_enter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destinations() {
+        // the second of four
+        for (arg, to) in [
+            ("", Some(3)),
+            ("0", Some(0)),
+            ("1", Some(1)),
+            ("3", Some(2)),
+            ("9", Some(3)),
+            ("+", Some(2)),
+            ("+2", Some(3)),
+            ("+3", None),
+            ("-", Some(0)),
+            ("-1", Some(0)),
+            ("-2", None),
+            ("x", None),
+            ("+x", None),
+        ] {
+            assert_eq!(destination(arg, 4, 1), to, ":tabmove {}", arg);
+        }
+    }
+}
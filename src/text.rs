@@ -0,0 +1,381 @@
+use crate::options::Value;
+use crate::substitute::split_delimited;
+use crate::{ex, Editor};
+
+/// width `:center` and `:right` use when neither an argument nor 'textwidth' gives one
+const DEFAULT_WIDTH: usize = 80;
+
+/// How `:sort` compares lines.
+#[derive(Default)]
+struct SortFlags {
+    reverse: bool,
+    ignore_case: bool,
+    numeric: bool,
+    hex: bool,
+    unique: bool,
+    /// sort on what the pattern matches instead of what comes after it
+    on_match: bool,
+    /// `None` when there's none, empty for the last search pattern
+    pattern: Option<String>,
+}
+
+impl SortFlags {
+    /// `[!] [i] [n] [x] [u] [r] [/pattern/]`, the pattern can go between any other delimiter.
+    fn parse(bang: bool, args: &str) -> Result<Self, String> {
+        let mut flags = SortFlags {
+            reverse: bang,
+            ..Default::default()
+        };
+        let mut rest = args;
+        while let Some(ch) = rest.chars().next() {
+            rest = &rest[ch.len_utf8()..];
+            match ch {
+                ch if ch.is_whitespace() => {}
+                'i' => flags.ignore_case = true,
+                'n' => flags.numeric = true,
+                'x' => flags.hex = true,
+                'u' => flags.unique = true,
+                'r' => flags.on_match = true,
+                ch if !ch.is_alphanumeric() && ch != '"' => {
+                    let (pattern, after) = split_delimited(rest, ch);
+                    flags.pattern = Some(pattern);
+                    rest = after.unwrap_or("");
+                }
+                _ => return Err(format!("Invalid argument: {}", args.trim())),
+            }
+        }
+        if flags.numeric && flags.hex {
+            return Err(format!("Invalid argument: {}", args.trim()));
+        }
+        Ok(flags)
+    }
+}
+
+/// What a line gets sorted on, lines without a number go before the ones with one.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Text(String),
+    Number(Option<i128>),
+}
+
+/// The first number in `text`, `-` included, in base 10 or 16. Hex ones can start with `0x`.
+fn first_number(text: &str, hex: bool) -> Option<i128> {
+    let radix = if hex { 16 } else { 10 };
+    let start = text.find(|ch: char| ch.is_digit(radix))?;
+    let mut digits = &text[start..];
+    if hex {
+        if let Some(after) = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+            .filter(|after| after.starts_with(|ch: char| ch.is_ascii_hexdigit()))
+        {
+            digits = after;
+        }
+    }
+    let len = digits
+        .find(|ch: char| !ch.is_digit(radix))
+        .unwrap_or(digits.len());
+    // too many digits to fit still sort after all the others
+    let number = i128::from_str_radix(&digits[..len], radix).unwrap_or(i128::MAX);
+    Some(match text[..start].ends_with('-') {
+        true => -number,
+        false => number,
+    })
+}
+
+/// Leading white space as tabs and spaces, only spaces with 'expandtab'.
+fn indent(width: usize, tab_stop: usize, expand_tab: bool) -> String {
+    match expand_tab {
+        true => " ".repeat(width),
+        false => "\t".repeat(width / tab_stop) + &" ".repeat(width % tab_stop),
+    }
+}
+
+/// how many columns `text` takes up, tabs going to the next tab stop
+fn display_width(text: &str, tab_stop: usize) -> usize {
+    text.chars().fold(0, |col, ch| match ch {
+        '\t' => col + tab_stop - col % tab_stop,
+        _ => col + 1,
+    })
+}
+
+/// `row` with every stretch of blanks that has a tab in it (or with `all`, just two spaces or
+/// more) laid out again for `new_tab_stop`, `None` if nothing changes.
+fn retab_row(
+    row: &str,
+    tab_stop: usize,
+    new_tab_stop: usize,
+    expand_tab: bool,
+    all: bool,
+) -> Option<String> {
+    let mut new = String::new();
+    let mut col = 0;
+    let mut chars = row.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != ' ' && ch != '\t' {
+            new.push(ch);
+            col += 1;
+            continue;
+        }
+
+        let (start, mut got_tab, mut spaces) = (col, false, 0);
+        let mut next = Some(ch);
+        while let Some(blank @ (' ' | '\t')) = next {
+            if blank == '\t' {
+                got_tab = true;
+                col += tab_stop - col % tab_stop;
+            } else {
+                spaces += 1;
+                col += 1;
+            }
+            next = chars.next_if(|ch| *ch == ' ' || *ch == '\t');
+        }
+        if !(got_tab || (all && spaces > 1)) {
+            new.push_str(&" ".repeat(col - start));
+            continue;
+        }
+        if expand_tab {
+            new.push_str(&" ".repeat(col - start));
+        } else {
+            // tabs as far as they go, spaces for the rest
+            let mut at = start;
+            while at + new_tab_stop - at % new_tab_stop <= col {
+                new.push('\t');
+                at += new_tab_stop - at % new_tab_stop;
+            }
+            new.push_str(&" ".repeat(col - at));
+        }
+    }
+    (new != row).then_some(new)
+}
+
+impl Editor {
+    /// the lines a command goes over when it's given no range
+    fn whole_file(&self, range: Option<ex::Range>) -> Option<ex::Range> {
+        let nr_of_rows = self.output.editor_rows.nr_of_rows();
+        match range {
+            Some(range) => Some(range),
+            None if nr_of_rows == 0 => None,
+            None => Some(ex::Range {
+                start: 0,
+                end: nr_of_rows - 1,
+            }),
+        }
+    }
+
+    /// puts `rows` in place of the ones in `range` as a single undo step
+    fn replace_range(&mut self, range: ex::Range, rows: Vec<String>) {
+        let count = range.end - range.start + 1;
+        self.output
+            .edit(|output| output.editor_rows.replace_rows(range.start, count, rows));
+    }
+
+    /// `:[range]sor[t][!] [i] [n] [x] [u] [r] [/pattern/]`, the whole file without a range.
+    pub fn sort(&mut self, range: Option<ex::Range>, bang: bool, args: &str) {
+        let flags = match SortFlags::parse(bang, args) {
+            Ok(flags) => flags,
+            Err(error) => {
+                self.output.status_message.set_error(error);
+                return;
+            }
+        };
+        let Some(range) = self.whole_file(range) else {
+            return;
+        };
+
+        let regex = match &flags.pattern {
+            Some(pattern) => {
+                let pattern = match pattern.is_empty() {
+                    false => pattern.clone(),
+                    true => match self.output.search.pattern.clone() {
+                        Some(pattern) => pattern,
+                        None => {
+                            self.output
+                                .status_message
+                                .set_error("No previous regular expression".into());
+                            return;
+                        }
+                    },
+                };
+                match self.output.search.regex(&pattern) {
+                    Ok(regex) => {
+                        self.output.search.set_pattern(pattern);
+                        Some(regex)
+                    }
+                    Err(error) => {
+                        self.output.status_message.set_error(error.to_string());
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let rows = self.output.row_contents(range);
+        let key = |row: &str| -> SortKey {
+            let text = match &regex {
+                // lines it doesn't match sort on nothing, which puts them first
+                Some(regex) => match regex.find_at(row, 0, 0) {
+                    Some(m) if flags.on_match => &row[m.start().col..m.end().col],
+                    Some(m) => &row[m.end().col..],
+                    None => "",
+                },
+                None => row,
+            };
+            match (flags.numeric || flags.hex, flags.ignore_case) {
+                (true, _) => SortKey::Number(first_number(text, flags.hex)),
+                (false, true) => SortKey::Text(text.to_lowercase()),
+                (false, false) => SortKey::Text(text.to_string()),
+            }
+        };
+        let mut keyed: Vec<(SortKey, String)> =
+            rows.into_iter().map(|row| (key(&row), row)).collect();
+        // stable, so lines that compare the same stay in the order they were in
+        keyed.sort_by(|(a, _), (b, _)| match flags.reverse {
+            true => b.cmp(a),
+            false => a.cmp(b),
+        });
+        if flags.unique {
+            keyed.dedup_by(|(a, _), (b, _)| a == b);
+        }
+
+        let removed = (range.end - range.start + 1) - keyed.len();
+        self.replace_range(range, keyed.into_iter().map(|(_, row)| row).collect());
+        if removed > 0 {
+            self.output
+                .status_message
+                .set_message(format!("{} fewer lines", removed));
+        }
+    }
+
+    /// `:[range]ret[ab][!] [new_tabstop]` lays out the blanks with tabs in them again for the
+    /// new tab stop, which 'tabstop' then gets set to. With the `!` runs of spaces count too.
+    pub fn retab(&mut self, range: Option<ex::Range>, bang: bool, args: &str) {
+        let tab_stop = self.output.editor_rows.tab_stop();
+        let new_tab_stop = match args.trim() {
+            "" => tab_stop,
+            arg => match arg.parse::<usize>() {
+                Ok(new) if new > 0 => new,
+                _ => {
+                    self.output
+                        .status_message
+                        .set_error(format!("Invalid argument: {}", arg));
+                    return;
+                }
+            },
+        };
+        let expand_tab = self.output.editor_rows.options.bool("expandtab");
+
+        if let Some(range) = self.whole_file(range) {
+            let rows = self.output.row_contents(range);
+            let retabbed: Vec<Option<String>> = rows
+                .iter()
+                .map(|row| retab_row(row, tab_stop, new_tab_stop, expand_tab, bang))
+                .collect();
+            if retabbed.iter().any(Option::is_some) {
+                let rows = rows
+                    .into_iter()
+                    .zip(retabbed)
+                    .map(|(row, new)| new.unwrap_or(row))
+                    .collect();
+                self.replace_range(range, rows);
+            }
+        }
+        if new_tab_stop != tab_stop {
+            self.output
+                .set_local("tabstop", Value::Number(new_tab_stop));
+        }
+    }
+
+    /// `:[range]ce[nter] [width]`, `:[range]ri[ght] [width]` and `:[range]le[ft] [indent]`.
+    /// The width defaults to 'textwidth', or 80 when that's 0.
+    pub fn align(&mut self, range: ex::Range, name: &str, args: &str) {
+        let arg = match args.trim() {
+            "" => None,
+            arg => match arg.parse::<usize>() {
+                Ok(arg) => Some(arg),
+                Err(_) => {
+                    self.output
+                        .status_message
+                        .set_error(format!("Invalid argument: {}", arg));
+                    return;
+                }
+            },
+        };
+        let options = &self.output.editor_rows.options;
+        let width = match (arg, options.number("textwidth")) {
+            (Some(width), _) => width,
+            (None, 0) => DEFAULT_WIDTH,
+            (None, textwidth) => textwidth,
+        };
+        let tab_stop = self.output.editor_rows.tab_stop();
+        let expand_tab = options.bool("expandtab");
+
+        let rows = self
+            .output
+            .row_contents(range)
+            .into_iter()
+            .map(|row| {
+                let text = row.trim_matches([' ', '\t']);
+                // empty lines stay empty
+                if text.is_empty() {
+                    return String::new();
+                }
+                let len = display_width(text, tab_stop);
+                let spaces = match name {
+                    "left" => arg.unwrap_or(0),
+                    "center" => width.saturating_sub(len) / 2,
+                    _ => width.saturating_sub(len),
+                };
+                indent(spaces, tab_stop, expand_tab) + text
+            })
+            .collect::<Vec<String>>();
+        if rows != self.output.row_contents(range) {
+            self.replace_range(range, rows);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimal_numbers() {
+        assert_eq!(first_number("item 42 of 7", false), Some(42));
+        assert_eq!(first_number("x-12y", false), Some(-12));
+        assert_eq!(first_number("- 3", false), Some(3));
+        assert_eq!(first_number("007", false), Some(7));
+        assert_eq!(first_number("no digits", false), None);
+        assert_eq!(first_number("", false), None);
+    }
+
+    #[test]
+    fn hex_numbers() {
+        assert_eq!(first_number("0x1F", true), Some(31));
+        assert_eq!(first_number("0X1f", true), Some(31));
+        assert_eq!(first_number("n: ff", true), Some(0xff));
+        // letters count as digits as soon as they can be
+        assert_eq!(first_number("value 10", true), Some(0xa));
+        assert_eq!(first_number("-0x10", true), Some(-16));
+        // no digit after it, so the `0` is the number
+        assert_eq!(first_number("0xg", true), Some(0));
+        assert_eq!(first_number("xyz", true), None);
+    }
+
+    #[test]
+    fn huge_numbers_sort_last() {
+        let huge = "9".repeat(50);
+        assert_eq!(first_number(&huge, false), Some(i128::MAX));
+        assert!(SortKey::Number(None) < SortKey::Number(Some(-1)));
+    }
+
+    #[test]
+    fn flags() {
+        let flags = SortFlags::parse(true, "n u /a\\/b/").unwrap();
+        assert!(flags.reverse && flags.numeric && flags.unique);
+        assert_eq!(flags.pattern.as_deref(), Some("a/b"));
+        assert!(SortFlags::parse(false, "nx").is_err());
+        assert!(SortFlags::parse(false, "q").is_err());
+    }
+}
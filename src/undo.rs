@@ -0,0 +1,133 @@
+/// `start..start + new_len` used to hold `old`, undoing puts it back
+struct Change {
+    start: usize,
+    old: Vec<String>,
+    new_len: usize,
+}
+
+/// Everything one command did, it all gets undone together.
+pub struct UndoStep {
+    changes: Vec<Change>,
+    /// where the cursor was before the command, undo puts it back there
+    cursor: (usize, usize),
+}
+
+impl UndoStep {
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    /// first row the step touches, redo leaves the cursor there
+    pub fn first_row(&self) -> usize {
+        self.changes
+            .iter()
+            .map(|change| change.start)
+            .min()
+            .unwrap_or(self.cursor.1)
+    }
+
+    /// Reverts the step on `rows`, giving back the step that would redo it. `moved` hears of
+    /// every replacement as it happens: where it starts, how many rows went and how many came.
+    pub fn apply<T>(
+        self,
+        rows: &mut Vec<T>,
+        content: impl Fn(&T) -> String,
+        make_row: impl Fn(String) -> T,
+        mut moved: impl FnMut(usize, usize, usize),
+    ) -> UndoStep {
+        let mut inverse = Vec::with_capacity(self.changes.len());
+        for change in self.changes.into_iter().rev() {
+            let end = std::cmp::min(change.start + change.new_len, rows.len());
+            let new_len = change.old.len();
+            let removed = rows
+                .splice(change.start..end, change.old.into_iter().map(&make_row))
+                .map(|row| content(&row))
+                .collect::<Vec<_>>();
+            moved(change.start, removed.len(), new_len);
+            // collected back to front, which is exactly the order undoing the inverse needs
+            inverse.push(Change {
+                start: change.start,
+                old: removed,
+                new_len,
+            });
+        }
+
+        UndoStep {
+            changes: inverse,
+            cursor: self.cursor,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<UndoStep>,
+    redo: Vec<UndoStep>,
+    open: Option<UndoStep>,
+}
+
+impl History {
+    /// Groups every change until `end` into one step. Returns false if a step is already open,
+    /// in which case the changes just become part of that one.
+    pub fn begin(&mut self, cursor: (usize, usize)) -> bool {
+        if self.open.is_some() {
+            return false;
+        }
+        self.open = Some(UndoStep {
+            changes: Vec::new(),
+            cursor,
+        });
+        true
+    }
+
+    pub fn end(&mut self) {
+        if let Some(step) = self.open.take() {
+            if !step.changes.is_empty() {
+                self.undo.push(step);
+                self.redo.clear();
+            }
+        }
+    }
+
+    /// `old` got replaced by `new_len` rows starting at `start`
+    pub fn record(&mut self, start: usize, old: Vec<String>, new_len: usize) {
+        let one_off = self.open.is_none();
+        let step = self.open.get_or_insert_with(|| UndoStep {
+            changes: Vec::new(),
+            cursor: (0, start),
+        });
+
+        // typing into the same row over and over only needs the row as it was at first
+        let same_row =
+            |change: &Change| change.start == start && change.new_len == 1 && change.old.len() == 1;
+        if old.len() == 1 && new_len == 1 && step.changes.last().is_some_and(same_row) {
+            return;
+        }
+        step.changes.push(Change {
+            start,
+            old,
+            new_len,
+        });
+        if one_off {
+            self.end();
+        }
+    }
+
+    pub fn pop_undo(&mut self) -> Option<UndoStep> {
+        self.end();
+        self.undo.pop()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<UndoStep> {
+        self.end();
+        self.redo.pop()
+    }
+
+    pub fn push_undo(&mut self, step: UndoStep) {
+        self.undo.push(step);
+    }
+
+    pub fn push_redo(&mut self, step: UndoStep) {
+        self.redo.push(step);
+    }
+}
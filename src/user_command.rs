@@ -0,0 +1,308 @@
+use crate::complete::ArgKind;
+use crate::{ex, Editor, Output};
+use std::io::{self, stdout, Write};
+
+/// how deep user commands can run each other, so one running itself doesn't go on forever
+const MAX_COMMAND_DEPTH: usize = 100;
+
+/// How many arguments a user command takes, `-nargs`.
+#[derive(Clone, Copy, PartialEq)]
+enum Nargs {
+    Zero,
+    One,
+    Any,
+    /// `?`, zero or one
+    Optional,
+    /// `+`, one or more
+    Some,
+}
+
+const NARGS: &[(&str, Nargs)] = &[
+    ("0", Nargs::Zero),
+    ("1", Nargs::One),
+    ("*", Nargs::Any),
+    ("?", Nargs::Optional),
+    ("+", Nargs::Some),
+];
+
+/// The lines a user command that takes a range works on when it's given none.
+#[derive(Clone, Copy, PartialEq)]
+enum DefaultRange {
+    /// `-range`, the cursor line
+    Line,
+    /// `-range=%`, the whole file
+    All,
+}
+
+/// A command defined with `:command`.
+pub struct UserCommand {
+    nargs: Nargs,
+    range: Option<DefaultRange>,
+    bang: bool,
+    /// `-bar`, a `|` ends its arguments instead of being part of them
+    bar: bool,
+    pub complete: Option<ArgKind>,
+    replacement: String,
+}
+
+impl UserCommand {
+    /// Reads the `-attributes` in front of the name, what's left being the name and the
+    /// replacement.
+    fn parse(mut args: &str) -> Result<(Self, &str), String> {
+        let mut command = UserCommand {
+            nargs: Nargs::Zero,
+            range: None,
+            bang: false,
+            bar: false,
+            complete: None,
+            replacement: String::new(),
+        };
+        while let Some(rest) = args.strip_prefix('-') {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let attribute = &rest[..end];
+            match attribute.split_once('=') {
+                Some(("nargs", value)) => {
+                    command.nargs = NARGS
+                        .iter()
+                        .find(|(name, _)| *name == value)
+                        .map(|(_, nargs)| *nargs)
+                        .ok_or_else(|| format!("Invalid number of arguments: {}", value))?;
+                }
+                Some(("range", "%")) => command.range = Some(DefaultRange::All),
+                Some(("complete", value)) => {
+                    command.complete = Some(
+                        ArgKind::from_name(value)
+                            .ok_or_else(|| format!("Invalid complete value: {}", value))?,
+                    );
+                }
+                None if attribute == "range" => command.range = Some(DefaultRange::Line),
+                None if attribute == "bang" => command.bang = true,
+                None if attribute == "bar" => command.bar = true,
+                _ => return Err(format!("Invalid attribute: -{}", attribute)),
+            }
+            args = rest[end..].trim_start();
+        }
+        Ok((command, args))
+    }
+
+    /// the line `:command` lists it on
+    fn describe(&self, name: &str) -> String {
+        let nargs = NARGS
+            .iter()
+            .find(|(_, nargs)| *nargs == self.nargs)
+            .map_or("", |(value, _)| value);
+        let range = match self.range {
+            Some(DefaultRange::Line) => ".",
+            Some(DefaultRange::All) => "%",
+            None => "",
+        };
+        format!(
+            "{}{} {:<11} {:<4} {:<7} {:<8} {}",
+            if self.bang { '!' } else { ' ' },
+            if self.bar { '|' } else { ' ' },
+            name,
+            nargs,
+            range,
+            self.complete.map_or("", ArgKind::name),
+            self.replacement
+        )
+    }
+}
+
+/// `args` quoted as a string, for `<q-args>`
+fn quote(args: &str) -> String {
+    format!("\"{}\"", args.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Editor {
+    /// The command `name` is short for, it can be any start of a command's name as long as only
+    /// one has it.
+    pub fn user_command(&self, name: &str) -> Result<(&String, &UserCommand), String> {
+        if let Some(found) = self.user_commands.get_key_value(name) {
+            return Ok(found);
+        }
+        let mut matching = self
+            .user_commands
+            .iter()
+            .filter(|(full, _)| full.starts_with(name));
+        match (matching.next(), matching.next()) {
+            (Some(found), None) => Ok(found),
+            (Some(_), Some(_)) => Err(format!("Ambiguous use of user-defined command: {}", name)),
+            _ => Err(format!("Not an editor command: {}", name)),
+        }
+    }
+
+    /// `:com[mand][!] [-attributes] {Name} {replacement}` defines a command, without a
+    /// replacement it lists the commands starting with `Name`, without anything all of them.
+    pub fn define_command(&mut self, bang: bool, args: &str) -> io::Result<()> {
+        let (command, rest) = match UserCommand::parse(args.trim_start()) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                self.output.status_message.set_error(error);
+                return Ok(());
+            }
+        };
+        let (name, replacement) = match rest.split_once(char::is_whitespace) {
+            Some((name, replacement)) => (name, replacement.trim()),
+            None => (rest.trim(), ""),
+        };
+        if replacement.is_empty() {
+            return self.list_commands(name);
+        }
+
+        let error = if ex::full_name(name).is_some() {
+            Some("Reserved name, cannot be used for user defined command")
+        } else if !ex::is_user_command(name) {
+            Some("User defined commands must start with an uppercase letter")
+        } else if !name.chars().all(|ch| ch.is_ascii_alphanumeric()) {
+            Some("Invalid command name")
+        } else if !bang && self.user_commands.contains_key(name) {
+            Some("Command already exists: add ! to replace it")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            self.output.status_message.set_error(error.into());
+            return Ok(());
+        }
+        self.user_commands.insert(
+            name.to_string(),
+            UserCommand {
+                replacement: replacement.to_string(),
+                ..command
+            },
+        );
+        Ok(())
+    }
+
+    /// `:delc[ommand] {Name}`
+    pub fn delete_command(&mut self, args: &str) {
+        if self.user_commands.remove(args.trim()).is_none() {
+            self.output
+                .status_message
+                .set_error(format!("No such user-defined command: {}", args.trim()));
+        }
+    }
+
+    fn list_commands(&mut self, prefix: &str) -> io::Result<()> {
+        let lines: Vec<String> = self
+            .user_commands
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, command)| command.describe(name))
+            .collect();
+        if lines.is_empty() {
+            self.output
+                .status_message
+                .set_message("No user-defined commands found".into());
+            return Ok(());
+        }
+
+        Output::clear_screen()?;
+        print!(
+            "    {:<11} {:<4} {:<7} {:<8} Definition",
+            "Name", "Args", "Address", "Complete"
+        );
+        for line in &lines {
+            print!("\r\n{}", line);
+        }
+        stdout().flush()?;
+        self.press_enter()
+    }
+
+    /// Runs the user command `name` by putting the range, `!` and arguments in its replacement
+    /// and running that.
+    pub fn run_user_command(
+        &mut self,
+        name: &str,
+        range: Option<ex::Range>,
+        bang: bool,
+        args: &str,
+    ) -> io::Result<bool> {
+        let command = match self.user_command(name) {
+            Ok((_, command)) => command,
+            Err(error) => {
+                self.output.status_message.set_error(error);
+                return Ok(true);
+            }
+        };
+        let (args, next) = match command.bar {
+            true => ex::split_bar(args),
+            false => (args, None),
+        };
+        let args = args.trim();
+        let error = match command.nargs {
+            _ if bang && !command.bang => Some("No ! allowed".to_string()),
+            _ if range.is_some() && command.range.is_none() => Some("No range allowed".into()),
+            Nargs::Zero if !args.is_empty() => Some(format!("Trailing characters: {}", args)),
+            Nargs::One | Nargs::Some if args.is_empty() => Some("Argument required".into()),
+            _ => None,
+        };
+        if let Some(error) = error {
+            self.output.status_message.set_error(error);
+            return Ok(true);
+        }
+        if self.command_depth >= MAX_COMMAND_DEPTH {
+            self.output
+                .status_message
+                .set_error("User commands nested too deep".into());
+            return Ok(true);
+        }
+
+        let nr_of_rows = self.output.editor_rows.nr_of_rows();
+        let lines = match (range, command.range) {
+            (Some(range), _) => range,
+            (None, Some(DefaultRange::All)) => ex::Range {
+                start: 0,
+                end: nr_of_rows.saturating_sub(1),
+            },
+            _ => ex::Range::line(self.output.cursor_controller.cursor_y),
+        };
+        let expanded = self.expand_replacement(&command.replacement, lines, bang, args);
+
+        self.command_depth += 1;
+        let result = self.parse_command(expanded);
+        self.command_depth -= 1;
+        match (result?, next) {
+            (true, Some(next)) => self.parse_command(next.to_string()),
+            (running, _) => Ok(running),
+        }
+    }
+
+    /// Puts `<args>`, `<q-args>`, `<line1>`, `<line2>`, `<bang>` and `<lt>` in `replacement`,
+    /// any other `<...>` stays the way it is.
+    fn expand_replacement(
+        &self,
+        replacement: &str,
+        lines: ex::Range,
+        bang: bool,
+        args: &str,
+    ) -> String {
+        let mut expanded = String::new();
+        let mut rest = replacement;
+        while let Some(start) = rest.find('<') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find('>') else {
+                break;
+            };
+            let value = match rest[1..end].to_ascii_lowercase().as_str() {
+                "args" => args.to_string(),
+                "q-args" => quote(args),
+                "line1" => (lines.start + 1).to_string(),
+                "line2" => (lines.end + 1).to_string(),
+                "bang" => if bang { "!" } else { "" }.to_string(),
+                "lt" => "<".to_string(),
+                _ => {
+                    expanded.push('<');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            expanded.push_str(&value);
+            rest = &rest[end + 1..];
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
@@ -0,0 +1,982 @@
+use crate::options::Options;
+use crate::{CursorController, Editor, Output};
+use crossterm::event::{KeyCode, KeyModifiers};
+use crossterm::{cursor, queue};
+use std::{cmp, collections::BTreeMap, io};
+
+/// A part of the screen, a window's taking in its status line.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Which way a split lays out what's in it.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Split {
+    /// one above the other, `:split`
+    Horizontal,
+    /// side by side with a `|` between them, `:vsplit`
+    Vertical,
+}
+
+/// How the windows share the screen: a window, or a split with the size each part takes in the
+/// direction it splits in. A split never has another split going the same way right inside it.
+enum Layout {
+    Window(usize),
+    Split(Split, Vec<(Layout, usize)>),
+}
+
+/// columns the `|` between windows side by side take up, for a split with `count` parts
+fn separators(split: Split, count: usize) -> usize {
+    match split {
+        Split::Horizontal => 0,
+        Split::Vertical => count - 1,
+    }
+}
+
+impl Layout {
+    fn contains(&self, id: usize) -> bool {
+        match self {
+            Layout::Window(window) => *window == id,
+            Layout::Split(_, children) => children.iter().any(|(child, _)| child.contains(id)),
+        }
+    }
+
+    /// every window, from the top left
+    fn windows(&self, ids: &mut Vec<usize>) {
+        match self {
+            Layout::Window(id) => ids.push(*id),
+            Layout::Split(_, children) => children.iter().for_each(|(child, _)| child.windows(ids)),
+        }
+    }
+
+    /// Where each window goes in `rect`, and the `|` between windows side by side.
+    fn rects(&self, rect: Rect, windows: &mut Vec<(usize, Rect)>, lines: &mut Vec<Rect>) {
+        let Layout::Split(split, children) = self else {
+            if let Layout::Window(id) = self {
+                windows.push((*id, rect));
+            }
+            return;
+        };
+        let (mut x, mut y) = (rect.x, rect.y);
+        for (idx, (child, size)) in children.iter().enumerate() {
+            match split {
+                Split::Horizontal => {
+                    child.rects(
+                        Rect {
+                            y,
+                            height: *size,
+                            ..rect
+                        },
+                        windows,
+                        lines,
+                    );
+                    y += size;
+                }
+                Split::Vertical => {
+                    child.rects(
+                        Rect {
+                            x,
+                            width: *size,
+                            ..rect
+                        },
+                        windows,
+                        lines,
+                    );
+                    x += size;
+                    if idx + 1 < children.len() {
+                        lines.push(Rect {
+                            x,
+                            width: 1,
+                            ..rect
+                        });
+                        x += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// the least room it fits in going `split`'s way, a line of text and a status line high
+    /// and a column wide for each window
+    fn min_size(&self, split: Split) -> usize {
+        match self {
+            Layout::Window(_) if split == Split::Horizontal => 2,
+            Layout::Window(_) => 1,
+            Layout::Split(direction, children) => {
+                let sizes = children.iter().map(|(child, _)| child.min_size(split));
+                match *direction == split {
+                    true => sizes.sum::<usize>() + separators(split, children.len()),
+                    false => sizes.max().unwrap_or(0),
+                }
+            }
+        }
+    }
+
+    /// Makes it `size` big going `split`'s way, the room it gains or loses going to the last
+    /// parts in it.
+    fn resize(&mut self, split: Split, size: usize) {
+        let Layout::Split(direction, children) = self else {
+            return;
+        };
+        if *direction != split {
+            children
+                .iter_mut()
+                .for_each(|(child, _)| child.resize(split, size));
+            return;
+        }
+        let current: usize = children.iter().map(|(_, size)| size).sum::<usize>()
+            + separators(split, children.len());
+        if size >= current {
+            if let Some((child, child_size)) = children.last_mut() {
+                *child_size += size - current;
+                child.resize(split, *child_size);
+            }
+            return;
+        }
+        let mut shrink = current - size;
+        for (child, child_size) in children.iter_mut().rev() {
+            let taken = child_size.saturating_sub(child.min_size(split)).min(shrink);
+            *child_size -= taken;
+            shrink -= taken;
+            child.resize(split, *child_size);
+        }
+    }
+
+    /// Gives every part of each split the same room on top of the least it needs, in a `width`
+    /// by `height` rect.
+    fn equalize(&mut self, width: usize, height: usize) {
+        let Layout::Split(split, children) = self else {
+            return;
+        };
+        let count = children.len();
+        let total = match split {
+            Split::Horizontal => height,
+            Split::Vertical => width.saturating_sub(separators(*split, count)),
+        };
+        let needed: usize = children
+            .iter()
+            .map(|(child, _)| child.min_size(*split))
+            .sum();
+        let spare = total.saturating_sub(needed);
+        for (idx, (child, size)) in children.iter_mut().enumerate() {
+            *size = child.min_size(*split) + spare / count + usize::from(idx < spare % count);
+            match split {
+                Split::Horizontal => child.equalize(width, *size),
+                Split::Vertical => child.equalize(*size, height),
+            }
+        }
+    }
+
+    /// Splits window `id`, which is `size` big going `split`'s way, putting window `new` above
+    /// or left of it.
+    fn split(&mut self, id: usize, new: usize, split: Split, size: usize) -> bool {
+        let halves = |size: usize| match split {
+            Split::Horizontal => (size / 2, size - size / 2),
+            Split::Vertical => ((size - 1) / 2, size - 1 - (size - 1) / 2),
+        };
+        match self {
+            Layout::Window(window) if *window == id => {
+                let (first, second) = halves(size);
+                *self = Layout::Split(
+                    split,
+                    vec![(Layout::Window(new), first), (Layout::Window(id), second)],
+                );
+                true
+            }
+            Layout::Window(_) => false,
+            Layout::Split(direction, children) => {
+                let found = children.iter().position(
+                    |(child, _)| matches!(child, Layout::Window(window) if *window == id),
+                );
+                match found {
+                    Some(idx) if *direction == split => {
+                        let (first, second) = halves(children[idx].1);
+                        children[idx].1 = second;
+                        children.insert(idx, (Layout::Window(new), first));
+                        true
+                    }
+                    _ => children
+                        .iter_mut()
+                        .any(|(child, _)| child.split(id, new, split, size)),
+                }
+            }
+        }
+    }
+
+    /// Takes window `id` out, the part before it (or else after it) getting its room.
+    fn remove(&mut self, id: usize) -> bool {
+        let Layout::Split(split, children) = self else {
+            return false;
+        };
+        let found = children
+            .iter()
+            .position(|(child, _)| matches!(child, Layout::Window(window) if *window == id));
+        match found {
+            Some(idx) => {
+                let (_, size) = children.remove(idx);
+                let gets = idx.saturating_sub(1);
+                children[gets].1 += size + separators(*split, 2);
+                let (child, size) = &mut children[gets];
+                child.resize(*split, *size);
+            }
+            None => {
+                if !children.iter_mut().any(|(child, _)| child.remove(id)) {
+                    return false;
+                }
+            }
+        }
+        // a split left with one part makes way for it, and one going the same way joins in
+        let mut idx = 0;
+        while idx < children.len() {
+            match &mut children[idx].0 {
+                Layout::Split(inner, parts) if *inner == *split => {
+                    let parts = std::mem::take(parts);
+                    children.splice(idx..=idx, parts);
+                }
+                _ => idx += 1,
+            }
+        }
+        if children.len() == 1 {
+            let (only, _) = children.pop().unwrap();
+            *self = only;
+        }
+        true
+    }
+
+    /// Makes window `id` `size` big going `split`'s way, as far as the others in the split
+    /// closest around it going that way can make room.
+    fn resize_window(&mut self, id: usize, split: Split, size: usize) -> bool {
+        let Layout::Split(direction, children) = self else {
+            return false;
+        };
+        let Some(idx) = children.iter().position(|(child, _)| child.contains(id)) else {
+            return false;
+        };
+        if children[idx].0.resize_window(id, split, size) {
+            return true;
+        }
+        if *direction != split {
+            return false;
+        }
+
+        let others: usize = children
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| *other != idx)
+            .map(|(_, (child, _))| child.min_size(split))
+            .sum();
+        let total: usize = children.iter().map(|(_, size)| size).sum();
+        let min = children[idx].0.min_size(split);
+        let size = size.clamp(min, cmp::max(total.saturating_sub(others), min));
+        let old = children[idx].1;
+        if size > old {
+            // from the ones after it first, then the ones before it
+            let mut needed = size - old;
+            let order: Vec<usize> = (idx + 1..children.len()).chain((0..idx).rev()).collect();
+            for other in order {
+                let (child, child_size) = &mut children[other];
+                let taken = child_size.saturating_sub(child.min_size(split)).min(needed);
+                *child_size -= taken;
+                needed -= taken;
+                child.resize(split, *child_size);
+            }
+        } else {
+            let gets = if idx + 1 < children.len() {
+                idx + 1
+            } else {
+                idx - 1
+            };
+            let (child, child_size) = &mut children[gets];
+            *child_size += old - size;
+            child.resize(split, *child_size);
+        }
+        let (child, child_size) = &mut children[idx];
+        *child_size = size;
+        child.resize(split, size);
+        true
+    }
+}
+
+/// A window that isn't the current one. The current one's cursor and options live in `Output`.
+pub struct Window {
+    pub buffer: usize,
+    cursor: CursorController,
+    options: Options,
+}
+
+/// Every window by its number, and how they're laid out.
+pub struct Windows {
+    others: BTreeMap<usize, Window>,
+    pub current: usize,
+    /// the window that was current before, for Ctrl-W p
+    previous: Option<usize>,
+    last: usize,
+    layout: Layout,
+}
+
+impl Windows {
+    pub fn new() -> Self {
+        Self {
+            others: BTreeMap::new(),
+            current: 1,
+            previous: None,
+            last: 1,
+            layout: Layout::Window(1),
+        }
+    }
+
+    /// every window number, from the top left
+    pub fn order(&self) -> Vec<usize> {
+        let mut ids = Vec::new();
+        self.layout.windows(&mut ids);
+        ids
+    }
+
+    pub fn count(&self) -> usize {
+        self.others.len() + 1
+    }
+
+    /// the buffers the windows other than the current one show
+    pub fn buffers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.others.values().map(|window| window.buffer)
+    }
+
+    /// Takes the windows showing buffer `nr` out of a tab page that isn't the current one,
+    /// `current` being the window that's current in it. Gives back false when that leaves none.
+    pub fn remove_buffer(&mut self, current: &mut Window, nr: usize) -> bool {
+        let showing: Vec<usize> = self
+            .others
+            .iter()
+            .filter(|(_, window)| window.buffer == nr)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in showing {
+            self.others.remove(&id);
+            self.layout.remove(id);
+        }
+        if current.buffer == nr {
+            let Some(&id) = self.others.keys().next() else {
+                return false;
+            };
+            *current = self.others.remove(&id).unwrap();
+            self.layout.remove(self.current);
+            self.current = id;
+        }
+        self.previous = self.previous.filter(|id| self.others.contains_key(id));
+        true
+    }
+}
+
+impl Editor {
+    /// the part of the screen the windows share, all of it but the command line and the
+    /// tabline
+    fn window_area(&self) -> Rect {
+        let top = usize::from(self.tabline_shown());
+        Rect {
+            x: 0,
+            y: top,
+            width: self.output.screen.0,
+            height: self.output.screen.1 - 1 - top,
+        }
+    }
+
+    /// Makes the windows take up the window area again, after the tabline came or went.
+    pub fn fit_windows(&mut self) {
+        let area = self.window_area();
+        let layout = &mut self.windows.layout;
+        layout.resize(Split::Horizontal, area.height);
+        layout.resize(Split::Vertical, area.width);
+        let rect = self.current_rect();
+        self.output.win_size = (rect.width, rect.height.saturating_sub(1));
+    }
+
+    /// where each window goes, and the lines between those side by side
+    fn window_rects(&self) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let (mut windows, mut lines) = (Vec::new(), Vec::new());
+        self.windows
+            .layout
+            .rects(self.window_area(), &mut windows, &mut lines);
+        (windows, lines)
+    }
+
+    fn current_rect(&self) -> Rect {
+        let (windows, _) = self.window_rects();
+        windows
+            .into_iter()
+            .find(|(id, _)| *id == self.windows.current)
+            .map(|(_, rect)| rect)
+            .unwrap_or(self.window_area())
+    }
+
+    /// Whether a window other than the current one shows buffer `nr`, in this tab page or
+    /// another.
+    pub fn shown_elsewhere(&self, nr: usize) -> bool {
+        self.windows.buffers().any(|buffer| buffer == nr) || self.tabs.shows(nr)
+    }
+
+    /// Whether any window shows buffer `nr`.
+    pub fn shown(&self, nr: usize) -> bool {
+        nr == self.buffers.current || self.shown_elsewhere(nr)
+    }
+
+    /// Has `draw` draw in window `id` as though it were the current one.
+    fn with_window(&mut self, id: usize, draw: impl FnOnce(&mut Output)) {
+        let Some(window) = self.windows.others.get_mut(&id) else {
+            return;
+        };
+        let output = &mut self.output;
+        let mut buffer = match window.buffer == self.buffers.current {
+            true => None,
+            false => self.buffers.other_mut(window.buffer),
+        };
+        std::mem::swap(&mut output.cursor_controller, &mut window.cursor);
+        std::mem::swap(&mut output.window_options, &mut window.options);
+        if let Some(buffer) = buffer.as_mut() {
+            buffer.swap_rows(output);
+        }
+        // only the current window shows the visual selection, the `:s` preview and the like
+        let preview = output.preview.take();
+        let highlight = output.highlight.take();
+        let mode = std::mem::replace(&mut output.mode, crate::Mode::Normal);
+        output.clamp_cursor();
+        output.update_line_nr_padding();
+
+        draw(output);
+
+        output.preview = preview;
+        output.highlight = highlight;
+        output.mode = mode;
+        if let Some(buffer) = buffer.as_mut() {
+            buffer.swap_rows(output);
+        }
+        std::mem::swap(&mut output.cursor_controller, &mut window.cursor);
+        std::mem::swap(&mut output.window_options, &mut window.options);
+        output.update_line_nr_padding();
+    }
+
+    /// Draws the tabline and every window, the current one last so `Output` is left with its
+    /// size.
+    pub fn refresh_screen(&mut self) -> io::Result<()> {
+        self.fit_windows();
+        let (windows, lines) = self.window_rects();
+        queue!(self.output.editor_contents, cursor::Hide)?;
+        if self.tabline_shown() {
+            let labels = self.tab_labels();
+            self.output.draw_tabline(&labels);
+        }
+        let current = self.windows.current;
+        let mut current_rect = self.window_area();
+        for (id, rect) in windows {
+            if id == current {
+                current_rect = rect;
+                continue;
+            }
+            self.with_window(id, |output| {
+                output.origin = (rect.x, rect.y);
+                output.win_size = (rect.width, rect.height.saturating_sub(1));
+                output.draw_window(false);
+            });
+        }
+        self.output.origin = (current_rect.x, current_rect.y);
+        self.output.win_size = (current_rect.width, current_rect.height.saturating_sub(1));
+        self.output.draw_window(true);
+        self.output.finish_screen(&lines)
+    }
+
+    /// The current window, as it'd be kept if another one became current.
+    pub fn take_window(&self) -> Window {
+        Window {
+            buffer: self.buffers.current,
+            cursor: self.output.cursor_controller.clone(),
+            options: self.output.window_options.clone(),
+        }
+    }
+
+    /// Shows `window` where the current window is, the other way round from `take_window`.
+    pub fn put_window(&mut self, window: Window) {
+        if window.buffer != self.buffers.current {
+            // going to another window isn't going to another file, Ctrl-^ stays the same
+            let alternate = self.buffers.alternate;
+            self.enter_buffer(window.buffer);
+            self.buffers.alternate = alternate.filter(|nr| *nr != window.buffer);
+        }
+        self.output.window_options = window.options;
+        self.output.cursor_controller = window.cursor;
+
+        let rect = self.current_rect();
+        self.output.win_size = (rect.width, rect.height.saturating_sub(1));
+        self.output.highlight = None;
+        self.output.update_line_nr_padding();
+        self.output.clamp_cursor();
+    }
+
+    /// Makes window `id` the current one.
+    pub fn enter_window(&mut self, id: usize) {
+        let Some(window) = self.windows.others.remove(&id) else {
+            return;
+        };
+        let old = self.take_window();
+        self.windows.others.insert(self.windows.current, old);
+        self.windows.previous = Some(self.windows.current);
+        self.windows.current = id;
+        self.put_window(window);
+    }
+
+    /// Splits the current window in two, the new one showing the same and becoming the current
+    /// one.
+    pub fn split_window(&mut self, split: Split) -> bool {
+        let rect = self.current_rect();
+        let (size, room) = match split {
+            Split::Horizontal => (rect.height, rect.height >= 4),
+            Split::Vertical => (rect.width, rect.width >= 3),
+        };
+        if !room {
+            self.output
+                .status_message
+                .set_error("Not enough room".into());
+            return false;
+        }
+        self.windows.last += 1;
+        let new = self.windows.last;
+        self.windows
+            .layout
+            .split(self.windows.current, new, split, size);
+        self.windows.others.insert(new, self.take_window());
+        self.enter_window(new);
+        true
+    }
+
+    /// `:sp[lit] [file]` and `:vs[plit] [file]`, `:new` and `:vne[w]` with `empty` for an
+    /// empty buffer in the new window.
+    pub fn split_command(&mut self, split: Split, empty: bool, args: &str) {
+        if !self.split_window(split) {
+            return;
+        }
+        if empty {
+            let editor_rows = crate::EditorRows::new(self.output.options.clone());
+            self.add_buffer(editor_rows, false);
+        } else if !args.trim().is_empty() {
+            self.edit_file(false, args);
+        }
+    }
+
+    /// Closes window `id`, gives back whether it did. The buffer in it stays around, unless it
+    /// has changes that no other window shows and neither 'hidden' nor a `!` say that's fine.
+    pub fn close_window(&mut self, id: usize, bang: bool) -> bool {
+        if self.windows.count() == 1 {
+            self.output
+                .status_message
+                .set_error("Cannot close last window".into());
+            return false;
+        }
+        if id == self.windows.current {
+            if !self.can_leave(bang) {
+                return false;
+            }
+            let order = self.windows.order();
+            let idx = order.iter().position(|other| *other == id).unwrap_or(0);
+            let next = self
+                .windows
+                .previous
+                .filter(|previous| self.windows.others.contains_key(previous))
+                .unwrap_or(order[if idx == 0 { 1 } else { idx - 1 }]);
+            self.enter_window(next);
+        } else {
+            let Some(window) = self.windows.others.get(&id) else {
+                return false;
+            };
+            let nr = window.buffer;
+            let dirty = self.buffer(nr).is_some_and(|(_, dirty, _)| dirty > 0);
+            let elsewhere = self.buffers.current == nr
+                || self.tabs.shows(nr)
+                || self
+                    .windows
+                    .others
+                    .iter()
+                    .any(|(other, window)| *other != id && window.buffer == nr);
+            if dirty && !elsewhere && !bang && !self.output.options.bool("hidden") {
+                return false;
+            }
+        }
+        self.windows.others.remove(&id);
+        self.windows.layout.remove(id);
+        if self.windows.previous == Some(id) {
+            self.windows.previous = None;
+        }
+        let rect = self.current_rect();
+        self.output.win_size = (rect.width, rect.height.saturating_sub(1));
+        true
+    }
+
+    /// Closes every window that shows buffer `nr` but the last one, for `:bd`.
+    pub fn close_buffer_windows(&mut self, nr: usize) {
+        self.tabs.remove_buffer(nr);
+        let showing: Vec<usize> = self
+            .windows
+            .others
+            .iter()
+            .filter(|(_, window)| window.buffer == nr)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in showing {
+            self.close_window(id, true);
+        }
+        if self.buffers.current == nr && self.windows.count() > 1 {
+            self.close_window(self.windows.current, true);
+        }
+    }
+
+    /// `:clo[se][!]` and Ctrl-W c
+    pub fn close_current(&mut self, bang: bool) {
+        if !self.close_window(self.windows.current, bang) && self.windows.count() > 1 {
+            let message = format!("{} (add ! to override)", crate::DIRTY);
+            self.output.status_message.set_error(message);
+        }
+    }
+
+    /// `:on[ly][!]` and Ctrl-W o close every other window, except those with changes no other
+    /// window shows, unless 'hidden' is on or there's a `!`.
+    pub fn only_window(&mut self, bang: bool) {
+        let others: Vec<usize> = self.windows.others.keys().copied().collect();
+        let mut kept = false;
+        for id in others {
+            kept |= !self.close_window(id, bang);
+        }
+        if kept {
+            self.output
+                .status_message
+                .set_error("Other window contains changes".into());
+        }
+    }
+
+    /// Ctrl-W h, j, k and l: the window left, below, above or right of the current one, the
+    /// one next to the cursor if there's more than one.
+    fn neighbour(&self, direction: char) -> Option<usize> {
+        let (windows, _) = self.window_rects();
+        let rect = self.current_rect();
+        let cursor = &self.output.cursor_controller;
+        let x = rect.x + cursor.render_x.saturating_sub(cursor.col_offset);
+        let y = rect.y + cursor.cursor_y.saturating_sub(cursor.row_offset);
+        // whether `other` is on that side, and whether it's across from the cursor
+        let placed = |other: &Rect| -> (bool, bool) {
+            let across_y = (other.y..other.y + other.height).contains(&y);
+            let across_x = (other.x..other.x + other.width).contains(&x);
+            match direction {
+                'h' => (other.x + other.width + 1 == rect.x, across_y),
+                'l' => (other.x == rect.x + rect.width + 1, across_y),
+                'k' => (other.y + other.height == rect.y, across_x),
+                _ => (other.y == rect.y + rect.height, across_x),
+            }
+        };
+        let beside: Vec<&(usize, Rect)> = windows
+            .iter()
+            .filter(|(_, other)| placed(other).0)
+            .collect();
+        beside
+            .iter()
+            .find(|(_, other)| placed(other).1)
+            .or(beside.first())
+            .map(|(id, _)| *id)
+    }
+
+    /// Makes the current window `size` lines high (or, `Vertical`, columns wide) as far as
+    /// there's room, its status line not counted.
+    pub fn resize_window(&mut self, split: Split, size: usize) {
+        let size = match split {
+            Split::Horizontal => size + 1,
+            Split::Vertical => size,
+        };
+        let id = self.windows.current;
+        self.windows.layout.resize_window(id, split, size);
+        let rect = self.current_rect();
+        self.output.win_size = (rect.width, rect.height.saturating_sub(1));
+    }
+
+    /// `:res[ize] [+-]N`, the most room there is without a number
+    pub fn resize_command(&mut self, args: &str) {
+        let rows = self.output.win_size.1;
+        let arg = args.trim();
+        let size = match arg.parse::<usize>() {
+            _ if arg.is_empty() => Ok(usize::MAX / 2),
+            _ if arg.starts_with('+') => arg[1..].parse::<usize>().map(|more| rows + more),
+            _ if arg.starts_with('-') => arg[1..]
+                .parse::<usize>()
+                .map(|less| rows.saturating_sub(less)),
+            size => size,
+        };
+        match size {
+            Ok(size) => self.resize_window(Split::Horizontal, size),
+            Err(_) => self
+                .output
+                .status_message
+                .set_error(format!("Invalid argument: {}", arg)),
+        }
+    }
+
+    /// `:winc[md] {arg}` does what Ctrl-W `arg` does.
+    pub fn wincmd(&mut self, args: &str) -> io::Result<bool> {
+        let arg = args.trim();
+        let (count, key) = arg.split_at(
+            arg.find(|ch: char| !ch.is_ascii_digit())
+                .unwrap_or(arg.len()),
+        );
+        let mut chars = key.chars();
+        match (chars.next(), chars.next()) {
+            (Some(key), None) => self.window_command(count.parse().ok(), KeyCode::Char(key)),
+            _ => {
+                self.output
+                    .status_message
+                    .set_error("Argument required".into());
+                Ok(true)
+            }
+        }
+    }
+
+    /// Ctrl-W in normal mode, a count can go between it and the key that says what to do.
+    pub fn window_key(&mut self) -> io::Result<bool> {
+        let mut count: Option<usize> = None;
+        loop {
+            let key = self.read_key()?;
+            match key.code {
+                KeyCode::Char(digit @ '0'..='9') if digit != '0' || count.is_some() => {
+                    let digit = digit.to_digit(10).unwrap() as usize;
+                    count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                }
+                KeyCode::Esc => return Ok(true),
+                // Ctrl-W Ctrl-W is Ctrl-W w and so on
+                KeyCode::Char(ch) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return self.window_command(count, KeyCode::Char(ch.to_ascii_lowercase()));
+                }
+                code => return self.window_command(count, code),
+            }
+        }
+    }
+
+    /// What Ctrl-W followed by `key` does, `count` times where that goes.
+    fn window_command(&mut self, count: Option<usize>, key: KeyCode) -> io::Result<bool> {
+        let times = count.unwrap_or(1);
+        let key = match key {
+            KeyCode::Left => KeyCode::Char('h'),
+            KeyCode::Down => KeyCode::Char('j'),
+            KeyCode::Up => KeyCode::Char('k'),
+            KeyCode::Right => KeyCode::Char('l'),
+            key => key,
+        };
+        let order = self.windows.order();
+        let idx = order
+            .iter()
+            .position(|id| *id == self.windows.current)
+            .unwrap_or(0);
+        match key {
+            KeyCode::Char('s' | 'S') => {
+                self.split_window(Split::Horizontal);
+            }
+            KeyCode::Char('v') => {
+                self.split_window(Split::Vertical);
+            }
+            KeyCode::Char('n') => self.split_command(Split::Horizontal, true, ""),
+            KeyCode::Char('w') => {
+                let next = match count {
+                    Some(count) => order[count.clamp(1, order.len()) - 1],
+                    None => order[(idx + 1) % order.len()],
+                };
+                self.enter_window(next);
+            }
+            KeyCode::Char('W') => {
+                let next = match count {
+                    Some(count) => order[count.clamp(1, order.len()) - 1],
+                    None => order[(idx + order.len() - 1) % order.len()],
+                };
+                self.enter_window(next);
+            }
+            KeyCode::Char('p') => match self.windows.previous {
+                Some(previous) => self.enter_window(previous),
+                None => self
+                    .output
+                    .status_message
+                    .set_error("There is no previous window".into()),
+            },
+            KeyCode::Char('t') => self.enter_window(order[0]),
+            KeyCode::Char('b') => self.enter_window(order[order.len() - 1]),
+            KeyCode::Char(direction @ ('h' | 'j' | 'k' | 'l')) => {
+                for _ in 0..times {
+                    match self.neighbour(direction) {
+                        Some(id) => self.enter_window(id),
+                        None => break,
+                    }
+                }
+            }
+            KeyCode::Char('c') => self.close_current(false),
+            KeyCode::Char('q') => return self.quit(false),
+            KeyCode::Char('o') => self.only_window(false),
+            KeyCode::Char('=') => {
+                let area = self.window_area();
+                self.windows.layout.equalize(area.width, area.height);
+                let rect = self.current_rect();
+                self.output.win_size = (rect.width, rect.height.saturating_sub(1));
+            }
+            KeyCode::Char('+') => {
+                self.resize_window(Split::Horizontal, self.output.win_size.1 + times)
+            }
+            KeyCode::Char('-') => self.resize_window(
+                Split::Horizontal,
+                self.output.win_size.1.saturating_sub(times),
+            ),
+            KeyCode::Char('>') => {
+                self.resize_window(Split::Vertical, self.output.win_size.0 + times)
+            }
+            KeyCode::Char('<') => self.resize_window(
+                Split::Vertical,
+                self.output.win_size.0.saturating_sub(times),
+            ),
+            KeyCode::Char('_') => {
+                self.resize_window(Split::Horizontal, count.unwrap_or(usize::MAX / 2))
+            }
+            KeyCode::Char('|') => {
+                self.resize_window(Split::Vertical, count.unwrap_or(usize::MAX / 2))
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// where each window goes on a `width` by `height` screen
+    fn rects(layout: &Layout, width: usize, height: usize) -> Vec<(usize, Rect)> {
+        let (mut windows, mut lines) = (Vec::new(), Vec::new());
+        layout.rects(rect(0, 0, width, height), &mut windows, &mut lines);
+        windows
+    }
+
+    #[test]
+    fn splits() {
+        let mut layout = Layout::Window(1);
+        assert!(layout.split(1, 2, Split::Horizontal, 20));
+        assert_eq!(
+            rects(&layout, 80, 20),
+            [(2, rect(0, 0, 80, 10)), (1, rect(0, 10, 80, 10))]
+        );
+
+        // the `|` takes a column
+        assert!(layout.split(1, 3, Split::Vertical, 80));
+        assert_eq!(
+            rects(&layout, 80, 20),
+            [
+                (2, rect(0, 0, 80, 10)),
+                (3, rect(0, 10, 39, 10)),
+                (1, rect(40, 10, 40, 10))
+            ]
+        );
+
+        // splitting the same way again shares the window's room, not the whole split's
+        assert!(layout.split(2, 4, Split::Horizontal, 10));
+        assert_eq!(
+            rects(&layout, 80, 20)[..2],
+            [(4, rect(0, 0, 80, 5)), (2, rect(0, 5, 80, 5))]
+        );
+        assert!(!layout.split(9, 5, Split::Horizontal, 10));
+    }
+
+    #[test]
+    fn resizes() {
+        let mut layout = Layout::Window(1);
+        layout.split(1, 2, Split::Horizontal, 20);
+
+        // the last part gains or loses first
+        layout.resize(Split::Horizontal, 30);
+        assert_eq!(
+            rects(&layout, 80, 30),
+            [(2, rect(0, 0, 80, 10)), (1, rect(0, 10, 80, 20))]
+        );
+        layout.resize(Split::Horizontal, 6);
+        assert_eq!(
+            rects(&layout, 80, 6),
+            [(2, rect(0, 0, 80, 4)), (1, rect(0, 4, 80, 2))]
+        );
+
+        // and never below the least each part needs
+        layout.resize(Split::Horizontal, 1);
+        assert_eq!(
+            rects(&layout, 80, 4),
+            [(2, rect(0, 0, 80, 2)), (1, rect(0, 2, 80, 2))]
+        );
+    }
+
+    #[test]
+    fn resizes_parts_that_are_too_small() {
+        let mut layout = Layout::Split(
+            Split::Horizontal,
+            vec![(Layout::Window(1), 1), (Layout::Window(2), 10)],
+        );
+        layout.resize(Split::Horizontal, 5);
+        assert_eq!(
+            rects(&layout, 80, 5),
+            [(1, rect(0, 0, 80, 1)), (2, rect(0, 1, 80, 4))]
+        );
+
+        assert!(layout.resize_window(1, Split::Horizontal, 3));
+        assert_eq!(
+            rects(&layout, 80, 5),
+            [(1, rect(0, 0, 80, 3)), (2, rect(0, 3, 80, 2))]
+        );
+    }
+
+    #[test]
+    fn equalizes() {
+        let mut layout = Layout::Window(1);
+        layout.split(1, 2, Split::Vertical, 80);
+        layout.split(1, 3, Split::Vertical, 40);
+        layout.equalize(80, 20);
+        assert_eq!(
+            rects(&layout, 80, 20),
+            [
+                (2, rect(0, 0, 26, 20)),
+                (3, rect(27, 0, 26, 20)),
+                (1, rect(54, 0, 26, 20))
+            ]
+        );
+    }
+
+    #[test]
+    fn equalizes_without_squashing_nested_windows() {
+        // a column of four windows left of another one, above five more
+        let mut layout = Layout::Window(1);
+        layout.split(1, 2, Split::Horizontal, 20);
+        layout.split(2, 3, Split::Vertical, 80);
+        for new in 4..7 {
+            layout.split(3, new, Split::Horizontal, 10);
+        }
+        for new in 7..11 {
+            layout.split(1, new, Split::Horizontal, 10);
+        }
+
+        layout.equalize(80, 20);
+        let windows = rects(&layout, 80, 20);
+        assert_eq!(windows.len(), 10);
+        for (id, rect) in windows {
+            assert!(rect.height >= 2, "window {} is {} high", id, rect.height);
+            assert!(
+                rect.y + rect.height <= 20,
+                "window {} is off the screen",
+                id
+            );
+        }
+    }
+}